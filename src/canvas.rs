@@ -0,0 +1,179 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+
+use crate::hwdevices::{NUM_KEYS, RGBA};
+
+/// A color value in linear light, all components are in the range [0.0..1.0].
+/// Alpha is not premultiplied
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+/// The canvas that all layers get blended onto, in linear light
+pub type Canvas = Vec<Color>;
+
+lazy_static! {
+    /// Lookup table, maps 8 bit sRGB values to linear light
+    static ref SRGB_TO_LINEAR: [f32; 256] = {
+        let mut table = [0.0; 256];
+
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = srgb_to_linear(i as f32 / 255.0);
+        }
+
+        table
+    };
+}
+
+/// Ordered dithering thresholds (4x4 Bayer matrix), centered in their
+/// intervals, so that they average to 0.5 and preserve the mean brightness
+const DITHER_THRESHOLDS: [f32; 16] = [
+    0.5 / 16.0,
+    8.5 / 16.0,
+    2.5 / 16.0,
+    10.5 / 16.0,
+    12.5 / 16.0,
+    4.5 / 16.0,
+    14.5 / 16.0,
+    6.5 / 16.0,
+    3.5 / 16.0,
+    11.5 / 16.0,
+    1.5 / 16.0,
+    9.5 / 16.0,
+    15.5 / 16.0,
+    7.5 / 16.0,
+    13.5 / 16.0,
+    5.5 / 16.0,
+];
+
+/// Convert a sRGB encoded component to linear light
+#[inline]
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a component in linear light to the sRGB encoding
+#[inline]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl Color {
+    /// Convert a 32 bits (ARGB) sRGB color value, like the ones used by Lua scripts
+    pub fn from_u32(c: u32) -> Self {
+        Self {
+            r: SRGB_TO_LINEAR[((c >> 16) & 0xff) as usize],
+            g: SRGB_TO_LINEAR[((c >> 8) & 0xff) as usize],
+            b: SRGB_TO_LINEAR[(c & 0xff) as usize],
+            a: ((c >> 24) & 0xff) as f32 / 255.0,
+        }
+    }
+
+    /// Convert to a 32 bits (ARGB) sRGB color value, like the ones used by Lua scripts
+    pub fn to_u32(&self) -> u32 {
+        let rgba = self.to_rgba();
+
+        (rgba.a as u32) << 24 | (rgba.r as u32) << 16 | (rgba.g as u32) << 8 | rgba.b as u32
+    }
+
+    /// Convert to an 8 bits per component sRGB value (rounding, no dithering)
+    pub fn to_rgba(&self) -> RGBA {
+        RGBA {
+            r: quantize(self.r, 0.5),
+            g: quantize(self.g, 0.5),
+            b: quantize(self.b, 0.5),
+            a: (self.a.max(0.0).min(1.0) * 255.0).round() as u8,
+        }
+    }
+
+    /// Alpha blend `self` over `background`
+    #[inline]
+    pub fn over(&self, background: &Color) -> Color {
+        let a = self.a.max(0.0).min(1.0);
+
+        Color {
+            r: self.r * a + background.r * (1.0 - a),
+            g: self.g * a + background.g * (1.0 - a),
+            b: self.b * a + background.b * (1.0 - a),
+            a: a + background.a * (1.0 - a),
+        }
+    }
+}
+
+/// Quantize a component in linear light to 8 bits sRGB, `threshold` is
+/// the rounding threshold in the range [0.0..1.0)
+#[inline]
+fn quantize(v: f32, threshold: f32) -> u8 {
+    let v = linear_to_srgb(v.max(0.0).min(1.0)) * 255.0;
+
+    (v + threshold).floor().max(0.0).min(255.0) as u8
+}
+
+/// Allocate a new, fully transparent canvas
+pub fn new_canvas() -> Canvas {
+    vec![Color::default(); NUM_KEYS]
+}
+
+/// Convert a slice of 32 bits (ARGB) sRGB color values to linear light
+pub fn from_color_map(map: &[u32]) -> Canvas {
+    map.iter().map(|c| Color::from_u32(*c)).collect()
+}
+
+/// Convert the canvas to the 8 bits sRGB representation that is sent to the
+/// device. `brightness` is applied in linear light, in the range [0.0..1.0].
+/// If `dither_frame` is set, temporal dithering is applied using it as the
+/// frame index, so that values between two 8 bits steps average out over time
+pub fn to_led_map(canvas: &[Color], brightness: f32, dither_frame: Option<usize>) -> Vec<RGBA> {
+    canvas
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| {
+            let threshold = match dither_frame {
+                Some(frame) => DITHER_THRESHOLDS[(idx + frame) % DITHER_THRESHOLDS.len()],
+                None => 0.5,
+            };
+
+            RGBA {
+                r: quantize(c.r * brightness, threshold),
+                g: quantize(c.g * brightness, threshold),
+                b: quantize(c.b * brightness, threshold),
+                a: (c.a.max(0.0).min(1.0) * 255.0).round() as u8,
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_dither_thresholds() {
+    let mean = DITHER_THRESHOLDS.iter().sum::<f32>() / DITHER_THRESHOLDS.len() as f32;
+
+    assert!((mean - 0.5).abs() < 0.0001);
+}
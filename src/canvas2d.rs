@@ -0,0 +1,759 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+
+use crate::canvas::{self, Canvas, Color};
+use crate::color::Gradient;
+use crate::constants;
+use crate::hwdevices::layout::{
+    self, KeyGeometry, KeyboardVariant, KEYBOARD_HEIGHT, KEYBOARD_WIDTH, KEY_UNIT_MM,
+};
+use crate::hwdevices::NUM_KEYS;
+
+pub type Result<T> = std::result::Result<T, Canvas2DError>;
+
+#[derive(Debug, Fail)]
+pub enum Canvas2DError {
+    #[fail(display = "Transformation stack overflow, too many calls to save()")]
+    StackOverflow {},
+
+    #[fail(display = "Transformation stack underflow, restore() without save()")]
+    StackUnderflow {},
+}
+
+/// An affine transformation, maps the point (x, y) to
+/// (a * x + c * y + e, b * x + d * y + f)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+    f: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 1.0,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    /// Apply `other` first, then `self`
+    fn multiply(&self, other: &Transform) -> Transform {
+        Transform {
+            a: self.a * other.a + self.c * other.b,
+            b: self.b * other.a + self.d * other.b,
+            c: self.a * other.c + self.c * other.d,
+            d: self.b * other.c + self.d * other.d,
+            e: self.a * other.e + self.c * other.f + self.e,
+            f: self.b * other.e + self.d * other.f + self.f,
+        }
+    }
+
+    pub fn translate(&self, x: f64, y: f64) -> Transform {
+        self.multiply(&Transform {
+            e: x,
+            f: y,
+            ..Transform::identity()
+        })
+    }
+
+    /// Rotate clockwise by `angle` radians, around the origin
+    pub fn rotate(&self, angle: f64) -> Transform {
+        let (sin, cos) = angle.sin_cos();
+
+        self.multiply(&Transform {
+            a: cos,
+            b: sin,
+            c: -sin,
+            d: cos,
+            ..Transform::identity()
+        })
+    }
+
+    pub fn scale(&self, sx: f64, sy: f64) -> Transform {
+        self.multiply(&Transform {
+            a: sx,
+            d: sy,
+            ..Transform::identity()
+        })
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.a * x + self.c * y + self.e,
+            self.b * x + self.d * y + self.f,
+        )
+    }
+
+    /// The inverse transformation, or None if the transformation is degenerate
+    pub fn invert(&self) -> Option<Transform> {
+        let det = self.a * self.d - self.b * self.c;
+
+        if det.abs() < std::f64::EPSILON {
+            None
+        } else {
+            Some(Transform {
+                a: self.d / det,
+                b: -self.b / det,
+                c: -self.c / det,
+                d: self.a / det,
+                e: (self.c * self.f - self.d * self.e) / det,
+                f: (self.b * self.e - self.a * self.f) / det,
+            })
+        }
+    }
+}
+
+/// How the area of a shape is painted
+#[derive(Debug, Clone)]
+pub enum Paint {
+    Solid(u32),
+
+    /// A gradient along the line from (x1, y1) to (x2, y2)
+    Linear {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        gradient: Gradient,
+    },
+
+    /// A gradient from the center (x, y) outwards, up to `radius`
+    Radial {
+        x: f64,
+        y: f64,
+        radius: f64,
+        gradient: Gradient,
+    },
+}
+
+impl Paint {
+    fn get_color_at(&self, x: f64, y: f64) -> u32 {
+        match self {
+            Paint::Solid(color) => *color,
+
+            Paint::Linear {
+                x1,
+                y1,
+                x2,
+                y2,
+                gradient,
+            } => {
+                let (dx, dy) = (x2 - x1, y2 - y1);
+                let len = dx * dx + dy * dy;
+
+                let p = if len > 0.0 {
+                    ((x - x1) * dx + (y - y1) * dy) / len
+                } else {
+                    0.0
+                };
+
+                gradient.sample(p)
+            }
+
+            Paint::Radial {
+                x: cx,
+                y: cy,
+                radius,
+                gradient,
+            } => {
+                let p = if *radius > 0.0 {
+                    ((x - cx).powi(2) + (y - cy).powi(2)).sqrt() / radius
+                } else {
+                    0.0
+                };
+
+                gradient.sample(p)
+            }
+        }
+    }
+
+    /// Get the paint from a Lua value, either a color or a fill
+    pub fn from_lua(value: rlua::Value) -> rlua::Result<Paint> {
+        match value {
+            rlua::Value::Integer(i) => Ok(Paint::Solid(i as u32)),
+            rlua::Value::Number(f) => Ok(Paint::Solid(f as u32)),
+            rlua::Value::UserData(fill) => Ok(fill.borrow::<Fill>()?.0.clone()),
+
+            _ => Err(rlua::Error::RuntimeError(
+                "Invalid paint, expected a color or a fill".to_string(),
+            )),
+        }
+    }
+}
+
+/// A gradient fill, as seen by Lua scripts
+#[derive(Debug, Clone)]
+pub struct Fill(pub Paint);
+
+impl rlua::UserData for Fill {}
+
+/// Shapes, in the coordinate system they have been specified in
+#[derive(Debug, Clone)]
+enum Shape {
+    Rect {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+
+    Circle {
+        x: f64,
+        y: f64,
+        radius: f64,
+    },
+
+    /// The outline of a circle
+    Ring {
+        x: f64,
+        y: f64,
+        radius: f64,
+        width: f64,
+    },
+
+    Polygon {
+        points: Vec<(f64, f64)>,
+    },
+
+    /// Connected line segments, with round caps and joins
+    Path {
+        points: Vec<(f64, f64)>,
+        width: f64,
+        closed: bool,
+    },
+}
+
+/// Distance of the point (x, y) to the line segment from `p1` to `p2`
+fn distance_to_segment(x: f64, y: f64, p1: (f64, f64), p2: (f64, f64)) -> f64 {
+    let (dx, dy) = (p2.0 - p1.0, p2.1 - p1.1);
+    let len = dx * dx + dy * dy;
+
+    let t = if len > 0.0 {
+        (((x - p1.0) * dx + (y - p1.1) * dy) / len)
+            .max(0.0)
+            .min(1.0)
+    } else {
+        0.0
+    };
+
+    ((x - p1.0 - t * dx).powi(2) + (y - p1.1 - t * dy).powi(2)).sqrt()
+}
+
+impl Shape {
+    fn contains(&self, px: f64, py: f64) -> bool {
+        match self {
+            Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => px >= *x && px < x + width && py >= *y && py < y + height,
+
+            Shape::Circle { x, y, radius } => (px - x).powi(2) + (py - y).powi(2) <= radius.powi(2),
+
+            Shape::Ring {
+                x,
+                y,
+                radius,
+                width,
+            } => {
+                let d = ((px - x).powi(2) + (py - y).powi(2)).sqrt();
+                (d - radius).abs() <= width / 2.0
+            }
+
+            Shape::Polygon { points } => {
+                // even-odd rule
+                let mut inside = false;
+                let mut j = points.len().wrapping_sub(1);
+
+                for (i, (xi, yi)) in points.iter().enumerate() {
+                    let (xj, yj) = points[j];
+
+                    if (*yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+
+                    j = i;
+                }
+
+                inside
+            }
+
+            Shape::Path {
+                points,
+                width,
+                closed,
+            } => {
+                let half_width = width / 2.0;
+
+                let closing = if *closed && points.len() > 2 {
+                    Some((points[points.len() - 1], points[0]))
+                } else {
+                    None
+                };
+
+                points
+                    .windows(2)
+                    .map(|w| (w[0], w[1]))
+                    .chain(closing)
+                    .any(|(p1, p2)| distance_to_segment(px, py, p1, p2) <= half_width)
+                    || (points.len() == 1
+                        && distance_to_segment(px, py, points[0], points[0]) <= half_width)
+            }
+        }
+    }
+}
+
+/// Get the sample positions of `key`, in millimeters
+fn get_sample_positions(key: &KeyGeometry) -> impl Iterator<Item = (f64, f64)> {
+    let n = constants::CANVAS_SAMPLES_PER_AXIS;
+    let key = *key;
+
+    (0..n * n).map(move |i| {
+        let (sx, sy) = (i % n, i / n);
+
+        (
+            (key.x + key.width * (sx as f64 + 0.5) / n as f64) * KEY_UNIT_MM,
+            (key.y + key.height * (sy as f64 + 0.5) / n as f64) * KEY_UNIT_MM,
+        )
+    })
+}
+
+/// A canvas that shapes get drawn onto. It holds one color per key, and a
+/// transformation that is applied to all shapes. Shapes are specified in
+/// millimeters, relative to the top left corner of the Esc key, and each key
+/// gets the share of its area that is covered by a shape
+#[derive(Debug, Clone)]
+pub struct Canvas2D {
+    pixels: Canvas,
+    transform: Transform,
+    stack: Vec<Transform>,
+    keys: &'static [KeyGeometry],
+}
+
+impl Default for Canvas2D {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Canvas2D {
+    /// Create a new, fully transparent canvas
+    pub fn new() -> Self {
+        Self {
+            pixels: canvas::new_canvas(),
+            transform: Transform::identity(),
+            stack: Vec::new(),
+            keys: layout::get_layout(KeyboardVariant::from_config()),
+        }
+    }
+
+    /// Width of the canvas, in millimeters
+    pub fn width() -> f64 {
+        KEYBOARD_WIDTH * KEY_UNIT_MM
+    }
+
+    /// Height of the canvas, in millimeters
+    pub fn height() -> f64 {
+        KEYBOARD_HEIGHT * KEY_UNIT_MM
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        let color = Color::from_u32(color);
+
+        for pixel in self.pixels.iter_mut() {
+            *pixel = color;
+        }
+    }
+
+    /// Push the current transformation onto the stack
+    pub fn save(&mut self) -> Result<()> {
+        if self.stack.len() >= constants::MAX_CANVAS_STACK_DEPTH {
+            Err(Canvas2DError::StackOverflow {})
+        } else {
+            self.stack.push(self.transform);
+            Ok(())
+        }
+    }
+
+    /// Pop the transformation that has been saved last
+    pub fn restore(&mut self) -> Result<()> {
+        self.transform = self.stack.pop().ok_or(Canvas2DError::StackUnderflow {})?;
+        Ok(())
+    }
+
+    pub fn reset_transform(&mut self) {
+        self.transform = Transform::identity();
+    }
+
+    pub fn translate(&mut self, x: f64, y: f64) {
+        self.transform = self.transform.translate(x, y);
+    }
+
+    pub fn rotate(&mut self, angle: f64) {
+        self.transform = self.transform.rotate(angle);
+    }
+
+    pub fn scale(&mut self, sx: f64, sy: f64) {
+        self.transform = self.transform.scale(sx, sy);
+    }
+
+    /// Paint the area of `shape` with `paint`. Keys that are only partially
+    /// covered by the shape are blended with their current color
+    fn draw(&mut self, shape: &Shape, paint: &Paint) {
+        let inverse = match self.transform.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let samples =
+            (constants::CANVAS_SAMPLES_PER_AXIS * constants::CANVAS_SAMPLES_PER_AXIS) as f32;
+
+        for key in self.keys.iter().filter(|k| k.index < NUM_KEYS) {
+            let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+
+            for (x, y) in get_sample_positions(key) {
+                let (x, y) = inverse.apply(x, y);
+
+                if shape.contains(x, y) {
+                    let c = Color::from_u32(paint.get_color_at(x, y));
+
+                    r += c.r * c.a;
+                    g += c.g * c.a;
+                    b += c.b * c.a;
+                    a += c.a;
+                }
+            }
+
+            if a > 0.0 {
+                let color = Color {
+                    r: r / a,
+                    g: g / a,
+                    b: b / a,
+                    a: a / samples,
+                };
+
+                self.pixels[key.index] = color.over(&self.pixels[key.index]);
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64, paint: &Paint) {
+        self.draw(
+            &Shape::Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            paint,
+        );
+    }
+
+    pub fn stroke_rect(&mut self, x: f64, y: f64, w: f64, h: f64, width: f64, paint: &Paint) {
+        self.draw(
+            &Shape::Path {
+                points: vec![(x, y), (x + w, y), (x + w, y + h), (x, y + h)],
+                width,
+                closed: true,
+            },
+            paint,
+        );
+    }
+
+    pub fn fill_circle(&mut self, x: f64, y: f64, radius: f64, paint: &Paint) {
+        self.draw(&Shape::Circle { x, y, radius }, paint);
+    }
+
+    pub fn stroke_circle(&mut self, x: f64, y: f64, radius: f64, width: f64, paint: &Paint) {
+        self.draw(
+            &Shape::Ring {
+                x,
+                y,
+                radius,
+                width,
+            },
+            paint,
+        );
+    }
+
+    pub fn line(&mut self, p1: (f64, f64), p2: (f64, f64), width: f64, paint: &Paint) {
+        self.draw(
+            &Shape::Path {
+                points: vec![p1, p2],
+                width,
+                closed: false,
+            },
+            paint,
+        );
+    }
+
+    pub fn fill_polygon(&mut self, points: Vec<(f64, f64)>, paint: &Paint) {
+        if points.len() > 2 {
+            self.draw(&Shape::Polygon { points }, paint);
+        }
+    }
+
+    pub fn stroke_polygon(&mut self, points: Vec<(f64, f64)>, width: f64, paint: &Paint) {
+        if !points.is_empty() {
+            self.draw(
+                &Shape::Path {
+                    points,
+                    width,
+                    closed: true,
+                },
+                paint,
+            );
+        }
+    }
+
+    /// Draw the color map `map` with the current transformation applied, so
+    /// that it can be moved, rotated or scaled. Each point of the canvas gets
+    /// the color of the key of `map` below it
+    pub fn draw_map(&mut self, map: &[u32], opacity: f64) {
+        let inverse = match self.transform.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let keys = self.keys;
+        let samples =
+            (constants::CANVAS_SAMPLES_PER_AXIS * constants::CANVAS_SAMPLES_PER_AXIS) as f32;
+
+        for key in keys.iter().filter(|k| k.index < NUM_KEYS) {
+            let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+
+            for (x, y) in get_sample_positions(key) {
+                let (x, y) = inverse.apply(x, y);
+                let (u, v) = (x / KEY_UNIT_MM, y / KEY_UNIT_MM);
+
+                let color = keys
+                    .iter()
+                    .find(|k| u >= k.x && u < k.x + k.width && v >= k.y && v < k.y + k.height)
+                    .and_then(|k| map.get(k.index));
+
+                if let Some(color) = color {
+                    let c = Color::from_u32(*color);
+
+                    r += c.r * c.a;
+                    g += c.g * c.a;
+                    b += c.b * c.a;
+                    a += c.a;
+                }
+            }
+
+            if a > 0.0 {
+                let color = Color {
+                    r: r / a,
+                    g: g / a,
+                    b: b / a,
+                    a: a / samples * opacity as f32,
+                };
+
+                self.pixels[key.index] = color.over(&self.pixels[key.index]);
+            }
+        }
+    }
+
+    /// Get the contents of the canvas as a color map
+    pub fn get_color_map(&self) -> Vec<u32> {
+        self.pixels.iter().map(|c| c.to_u32()).collect()
+    }
+}
+
+/// Convert a flat list of coordinates { x1, y1, x2, y2, ... } to points
+fn get_points(coords: Vec<f64>) -> rlua::Result<Vec<(f64, f64)>> {
+    if coords.len() % 2 != 0 {
+        Err(rlua::Error::RuntimeError(
+            "Invalid list of points, expected pairs of coordinates".to_string(),
+        ))
+    } else {
+        Ok(coords.chunks(2).map(|p| (p[0], p[1])).collect())
+    }
+}
+
+impl rlua::UserData for Canvas2D {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("width", |_, _, ()| Ok(Canvas2D::width()));
+        methods.add_method("height", |_, _, ()| Ok(Canvas2D::height()));
+
+        methods.add_method_mut("clear", |_, canvas, color: Option<u32>| {
+            canvas.clear(color.unwrap_or(0));
+            Ok(())
+        });
+
+        methods.add_method_mut("save", |_, canvas, ()| {
+            canvas
+                .save()
+                .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+        });
+
+        methods.add_method_mut("restore", |_, canvas, ()| {
+            canvas
+                .restore()
+                .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+        });
+
+        methods.add_method_mut("reset_transform", |_, canvas, ()| {
+            canvas.reset_transform();
+            Ok(())
+        });
+
+        methods.add_method_mut("translate", |_, canvas, (x, y): (f64, f64)| {
+            canvas.translate(x, y);
+            Ok(())
+        });
+
+        methods.add_method_mut("rotate", |_, canvas, angle: f64| {
+            canvas.rotate(angle);
+            Ok(())
+        });
+
+        methods.add_method_mut("scale", |_, canvas, (sx, sy): (f64, Option<f64>)| {
+            canvas.scale(sx, sy.unwrap_or(sx));
+            Ok(())
+        });
+
+        methods.add_method_mut(
+            "fill_rect",
+            |_, canvas, (x, y, w, h, paint): (f64, f64, f64, f64, rlua::Value)| {
+                canvas.fill_rect(x, y, w, h, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "stroke_rect",
+            |_, canvas, (x, y, w, h, width, paint): (f64, f64, f64, f64, f64, rlua::Value)| {
+                canvas.stroke_rect(x, y, w, h, width, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "fill_circle",
+            |_, canvas, (x, y, radius, paint): (f64, f64, f64, rlua::Value)| {
+                canvas.fill_circle(x, y, radius, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "stroke_circle",
+            |_, canvas, (x, y, radius, width, paint): (f64, f64, f64, f64, rlua::Value)| {
+                canvas.stroke_circle(x, y, radius, width, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "line",
+            |_, canvas, (x1, y1, x2, y2, width, paint): (f64, f64, f64, f64, f64, rlua::Value)| {
+                canvas.line((x1, y1), (x2, y2), width, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "fill_polygon",
+            |_, canvas, (coords, paint): (Vec<f64>, rlua::Value)| {
+                canvas.fill_polygon(get_points(coords)?, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "stroke_polygon",
+            |_, canvas, (coords, width, paint): (Vec<f64>, f64, rlua::Value)| {
+                canvas.stroke_polygon(get_points(coords)?, width, &Paint::from_lua(paint)?);
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "draw_map",
+            |_, canvas, (map, opacity): (Vec<u32>, Option<f64>)| {
+                canvas.draw_map(&map, opacity.unwrap_or(1.0));
+                Ok(())
+            },
+        );
+
+        methods.add_method("get_color_map", |_, canvas, ()| Ok(canvas.get_color_map()));
+    }
+}
+
+#[test]
+fn test_transform() {
+    let t = Transform::identity()
+        .translate(10.0, 20.0)
+        .rotate(std::f64::consts::PI / 2.0)
+        .scale(2.0, 2.0);
+
+    let (x, y) = t.apply(1.0, 0.0);
+    assert!((x - 10.0).abs() < 1e-9 && (y - 22.0).abs() < 1e-9);
+
+    let (u, v) = t.invert().unwrap().apply(x, y);
+    assert!((u - 1.0).abs() < 1e-9 && v.abs() < 1e-9);
+
+    assert!(Transform::identity().scale(0.0, 1.0).invert().is_none());
+}
+
+#[test]
+fn test_shapes() {
+    let square = Shape::Polygon {
+        points: vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)],
+    };
+
+    assert!(square.contains(5.0, 5.0));
+    assert!(!square.contains(15.0, 5.0));
+
+    let line = Shape::Path {
+        points: vec![(0.0, 0.0), (10.0, 0.0)],
+        width: 2.0,
+        closed: false,
+    };
+
+    assert!(line.contains(5.0, 0.9));
+    assert!(!line.contains(5.0, 1.1));
+    assert!(line.contains(10.5, 0.0));
+
+    let ring = Shape::Ring {
+        x: 0.0,
+        y: 0.0,
+        radius: 10.0,
+        width: 2.0,
+    };
+
+    assert!(ring.contains(10.5, 0.0));
+    assert!(!ring.contains(5.0, 0.0));
+}
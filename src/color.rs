@@ -0,0 +1,479 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::canvas::Color;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, ColorError>;
+
+#[derive(Debug, Fail)]
+pub enum ColorError {
+    #[fail(display = "Unknown color space: '{}'", name)]
+    UnknownColorSpace { name: String },
+
+    #[fail(display = "Palette '{}' could not be found in the library path", name)]
+    PaletteNotFound { name: String },
+
+    #[fail(display = "Could not parse palette '{}': {}", name, msg)]
+    PaletteParseError { name: String, msg: String },
+}
+
+/// Color spaces that colors may be interpolated in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    /// Component wise in sRGB, like `linear_gradient`
+    Srgb,
+
+    /// Component wise in linear light
+    Linear,
+
+    /// Perceptually uniform
+    Oklab,
+
+    /// Perceptually uniform, hues are interpolated along the shorter arc
+    Oklch,
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "linear" => Ok(ColorSpace::Linear),
+            "oklab" => Ok(ColorSpace::Oklab),
+            "oklch" => Ok(ColorSpace::Oklch),
+
+            _ => Err(ColorError::UnknownColorSpace {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+fn get_alpha(c: u32) -> f64 {
+    f64::from((c >> 24) & 0xff) / 255.0
+}
+
+fn with_alpha(c: u32, alpha: f64) -> u32 {
+    let a = (alpha.max(0.0).min(1.0) * 255.0).round() as u32;
+
+    (c & 0x00ff_ffff) | a << 24
+}
+
+#[inline]
+fn lerp(a: f64, b: f64, p: f64) -> f64 {
+    a + (b - a) * p
+}
+
+/// Convert a color in linear light to OKLab
+#[allow(clippy::many_single_char_names)]
+pub fn linear_to_oklab(r: f64, g: f64, b: f64) -> (f64, f64, f64) {
+    let l = (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+    let m = (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+    let s = (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+
+    (
+        0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+        1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+        0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+    )
+}
+
+/// Convert a color in OKLab to linear light. The result may be out of gamut
+#[allow(clippy::many_single_char_names)]
+pub fn oklab_to_linear(l: f64, a: f64, b: f64) -> (f64, f64, f64) {
+    let l_ = (l + 0.396_337_777_4 * a + 0.215_803_757_3 * b).powi(3);
+    let m_ = (l - 0.105_561_345_8 * a - 0.063_854_172_8 * b).powi(3);
+    let s_ = (l - 0.089_484_177_5 * a - 1.291_485_548_0 * b).powi(3);
+
+    (
+        4.076_741_662_1 * l_ - 3.307_711_591_3 * m_ + 0.230_969_929_2 * s_,
+        -1.268_438_004_6 * l_ + 2.609_757_401_1 * m_ - 0.341_319_396_5 * s_,
+        -0.004_196_086_3 * l_ - 0.703_418_614_7 * m_ + 1.707_614_701_0 * s_,
+    )
+}
+
+/// Get the OKLab components (L, a, b) of a color
+pub fn color_to_oklab(c: u32) -> (f64, f64, f64) {
+    let c = Color::from_u32(c);
+
+    linear_to_oklab(f64::from(c.r), f64::from(c.g), f64::from(c.b))
+}
+
+/// Convert OKLab components to a color, out of gamut colors are clipped
+pub fn oklab_to_color(l: f64, a: f64, b: f64, alpha: f64) -> u32 {
+    let (r, g, b) = oklab_to_linear(l, a, b);
+
+    Color {
+        r: r as f32,
+        g: g as f32,
+        b: b as f32,
+        a: alpha as f32,
+    }
+    .to_u32()
+}
+
+/// Get the OKLCH components (L, C, h) of a color, the hue is in degrees
+pub fn color_to_oklch(c: u32) -> (f64, f64, f64) {
+    let (l, a, b) = color_to_oklab(c);
+
+    let h = b.atan2(a).to_degrees();
+    let h = if h < 0.0 { h + 360.0 } else { h };
+
+    (l, (a * a + b * b).sqrt(), h)
+}
+
+/// Convert OKLCH components to a color, the hue is in degrees
+pub fn oklch_to_color(l: f64, c: f64, h: f64, alpha: f64) -> u32 {
+    let h = h.to_radians();
+
+    oklab_to_color(l, c * h.cos(), c * h.sin(), alpha)
+}
+
+/// Get the HSV components of a color, the hue is in degrees
+#[allow(clippy::many_single_char_names)]
+pub fn color_to_hsv(c: u32) -> (f64, f64, f64) {
+    let r = f64::from((c >> 16) & 0xff) / 255.0;
+    let g = f64::from((c >> 8) & 0xff) / 255.0;
+    let b = f64::from(c & 0xff) / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if (max - r).abs() < std::f64::EPSILON {
+        60.0 * (((g - b) / delta) % 6.0)
+    } else if (max - g).abs() < std::f64::EPSILON {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    let h = if h < 0.0 { h + 360.0 } else { h };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    (h, s, max)
+}
+
+/// Convert HSV components to a color, the hue is in degrees
+#[allow(clippy::many_single_char_names)]
+pub fn hsv_to_color(h: f64, s: f64, v: f64, alpha: f64) -> u32 {
+    let h = h.rem_euclid(360.0);
+    let s = s.max(0.0).min(1.0);
+    let v = v.max(0.0).min(1.0);
+
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = v - c;
+
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    let to_byte = |v: f64| ((v + m) * 255.0).round() as u32;
+
+    with_alpha(to_byte(r) << 16 | to_byte(g) << 8 | to_byte(b), alpha)
+}
+
+/// Interpolate between the colors `c1` and `c2` in the color space `space`,
+/// `p` is in the range [0.0..1.0]
+pub fn mix(c1: u32, c2: u32, p: f64, space: ColorSpace) -> u32 {
+    let p = p.max(0.0).min(1.0);
+    let alpha = lerp(get_alpha(c1), get_alpha(c2), p);
+
+    match space {
+        ColorSpace::Srgb => {
+            let component = |shift: u32| {
+                let a = f64::from((c1 >> shift) & 0xff);
+                let b = f64::from((c2 >> shift) & 0xff);
+
+                lerp(a, b, p).round() as u32
+            };
+
+            with_alpha(
+                component(16) << 16 | component(8) << 8 | component(0),
+                alpha,
+            )
+        }
+
+        ColorSpace::Linear => {
+            let a = Color::from_u32(c1);
+            let b = Color::from_u32(c2);
+            let p = p as f32;
+
+            Color {
+                r: a.r + (b.r - a.r) * p,
+                g: a.g + (b.g - a.g) * p,
+                b: a.b + (b.b - a.b) * p,
+                a: alpha as f32,
+            }
+            .to_u32()
+        }
+
+        ColorSpace::Oklab => {
+            let a = color_to_oklab(c1);
+            let b = color_to_oklab(c2);
+
+            oklab_to_color(
+                lerp(a.0, b.0, p),
+                lerp(a.1, b.1, p),
+                lerp(a.2, b.2, p),
+                alpha,
+            )
+        }
+
+        ColorSpace::Oklch => {
+            let a = color_to_oklch(c1);
+            let b = color_to_oklch(c2);
+
+            // the hue of achromatic colors is meaningless, so use the other one
+            let (h1, h2) = match (a.1 < 1e-4, b.1 < 1e-4) {
+                (true, false) => (b.2, b.2),
+                (false, true) => (a.2, a.2),
+                _ => (a.2, b.2),
+            };
+
+            // take the shorter arc
+            let mut delta = h2 - h1;
+            if delta > 180.0 {
+                delta -= 360.0;
+            } else if delta < -180.0 {
+                delta += 360.0;
+            }
+
+            oklch_to_color(lerp(a.0, b.0, p), lerp(a.1, b.1, p), h1 + delta * p, alpha)
+        }
+    }
+}
+
+/// A gradient with an arbitrary number of color stops
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    /// Color stops (position, color), sorted by position
+    stops: Vec<(f64, u32)>,
+    space: ColorSpace,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f64, u32)>, space: ColorSpace) -> Self {
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        Self { stops, space }
+    }
+
+    /// Create a gradient with the colors `colors`, evenly spaced in the range [0.0..1.0]
+    pub fn from_colors(colors: &[u32], space: ColorSpace) -> Self {
+        let n = colors.len().max(2) - 1;
+
+        let stops = colors
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i as f64 / n as f64, *c))
+            .collect();
+
+        Self::new(stops, space)
+    }
+
+    /// Get the color at position `p`. Positions outside of the first and
+    /// last stops are clamped
+    pub fn sample(&self, p: f64) -> u32 {
+        match self.stops.len() {
+            0 => 0,
+            1 => self.stops[0].1,
+
+            _ => {
+                let first = self.stops[0];
+                let last = self.stops[self.stops.len() - 1];
+
+                if p <= first.0 {
+                    first.1
+                } else {
+                    // positions after the last stop, and NaN
+                    match util::find_segment(&self.stops, p, |s| s.0) {
+                        Some((i, f)) => mix(self.stops[i - 1].1, self.stops[i].1, f, self.space),
+                        None => last.1,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl rlua::UserData for Gradient {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("sample", |_, gradient, p: f64| Ok(gradient.sample(p)));
+
+        methods.add_method("sample_map", |_, gradient, values: Vec<f64>| {
+            Ok(values
+                .iter()
+                .map(|p| gradient.sample(*p))
+                .collect::<Vec<u32>>())
+        });
+    }
+}
+
+/// Interpolate between two color maps, see `mix`
+pub fn map_mix(map1: &[u32], map2: &[u32], p: f64, space: ColorSpace) -> Vec<u32> {
+    map1.iter()
+        .zip(map2.iter())
+        .map(|(c1, c2)| mix(*c1, *c2, p, space))
+        .collect()
+}
+
+/// Scale the intensity of all colors of a color map in linear light
+pub fn map_scale(map: &[u32], factor: f64) -> Vec<u32> {
+    let factor = factor as f32;
+
+    map.iter()
+        .map(|c| {
+            let c = Color::from_u32(*c);
+
+            Color {
+                r: c.r * factor,
+                g: c.g * factor,
+                b: c.b * factor,
+                a: c.a,
+            }
+            .to_u32()
+        })
+        .collect()
+}
+
+/// Rotate the hues of all colors of a color map by `degrees`, and scale
+/// their chroma by `saturation` (in OKLCH)
+pub fn map_adjust(map: &[u32], degrees: f64, saturation: f64) -> Vec<u32> {
+    map.iter()
+        .map(|c| {
+            let (l, chroma, h) = color_to_oklch(*c);
+
+            oklch_to_color(l, chroma * saturation, h + degrees, get_alpha(*c))
+        })
+        .collect()
+}
+
+/// A named set of colors, loaded from a `.palette` file in the library path
+#[derive(Debug, Clone, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<u32>,
+
+    /// Colors with a dedicated purpose, e.g. `background` or `accent`
+    #[serde(default)]
+    pub named: HashMap<String, u32>,
+}
+
+lazy_static! {
+    /// Palettes shared by all Lua VMs, by file, with their modification time
+    static ref PALETTES: Mutex<HashMap<PathBuf, (SystemTime, Palette)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Find the file of the palette `name`, e.g. `palettes/sunset.palette`
+fn find_palette(name: &str, library_path: &[PathBuf]) -> Option<PathBuf> {
+    library_path
+        .iter()
+        .map(|dir| dir.join("palettes").join(format!("{}.palette", name)))
+        .find(|p| p.is_file())
+}
+
+fn get_modification_time(file: &Path) -> SystemTime {
+    fs::metadata(file)
+        .and_then(|m| m.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Load the palette `name` from the library path. Palettes are cached, and
+/// re-read when their file has been modified
+pub fn load_palette(name: &str, library_path: &[PathBuf]) -> Result<Palette> {
+    let file = find_palette(name, library_path).ok_or_else(|| ColorError::PaletteNotFound {
+        name: name.to_string(),
+    })?;
+
+    let modified = get_modification_time(&file);
+
+    let mut palettes = PALETTES.lock();
+    if let Some((time, palette)) = palettes.get(&file) {
+        if *time == modified {
+            return Ok(palette.clone());
+        }
+    }
+
+    let parse_error = |msg: String| ColorError::PaletteParseError {
+        name: name.to_string(),
+        msg,
+    };
+
+    let toml = fs::read_to_string(&file).map_err(|e| parse_error(format!("{}", e)))?;
+    let palette: Palette = toml::de::from_str(&toml).map_err(|e| parse_error(format!("{}", e)))?;
+
+    palettes.insert(file, (modified, palette.clone()));
+
+    Ok(palette)
+}
+
+#[test]
+fn test_oklab_round_trip() {
+    for c in [
+        0xffff0000_u32,
+        0xff00ff00,
+        0xff0000ff,
+        0xff808080,
+        0xff123456,
+    ]
+    .iter()
+    {
+        let (l, a, b) = color_to_oklab(*c);
+
+        assert_eq!(oklab_to_color(l, a, b, 1.0), *c);
+    }
+
+    // white has a lightness of 1.0 and no chroma
+    let (l, _c, _h) = color_to_oklch(0xffffffff);
+    assert!((l - 1.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_hsv() {
+    assert_eq!(color_to_hsv(0xff00ff00), (120.0, 1.0, 1.0));
+    assert_eq!(hsv_to_color(240.0, 1.0, 1.0, 1.0), 0xff0000ff);
+}
+
+#[test]
+fn test_gradient() {
+    let gradient = Gradient::from_colors(&[0xffff0000, 0xff00ff00, 0xff0000ff], ColorSpace::Srgb);
+
+    assert_eq!(gradient.sample(-1.0), 0xffff0000);
+    assert_eq!(gradient.sample(0.5), 0xff00ff00);
+    assert_eq!(gradient.sample(0.75), 0xff008080);
+    assert_eq!(gradient.sample(2.0), 0xff0000ff);
+    assert_eq!(gradient.sample(std::f64::NAN), 0xff0000ff);
+}
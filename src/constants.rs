@@ -27,17 +27,35 @@ pub const DEFAULT_SCRIPT_DIR: &str = "/usr/lib/eruption/scripts/";
 /// State directory
 pub const STATE_DIR: &str = "/var/lib/eruption/";
 
+/// Default directory of recordings, captured from the running profile
+pub const DEFAULT_RECORDING_DIR: &str = "/var/lib/eruption/recordings/";
+
 /// Number of slots
 pub const NUM_SLOTS: usize = 4;
 
 /// Default effect script
 pub const DEFAULT_EFFECT_SCRIPT: &str = "organic.lua";
 
-/// Target "Frames per Second"
+/// Default target "Frames per Second", see `global.target_fps`
 pub const TARGET_FPS: u64 = 24;
 
-/// Target delay time of main loop iteration
-pub const MAIN_LOOP_DELAY_MILLIS: u64 = 1000 / TARGET_FPS;
+/// Min. target "Frames per Second"
+pub const MIN_TARGET_FPS: u64 = 1;
+
+/// Max. target "Frames per Second"
+pub const MAX_TARGET_FPS: u64 = 60;
+
+/// Interval in which the adaptive frame rate control adjusts the frame rate
+pub const ADAPTIVE_FPS_INTERVAL_MILLIS: u64 = 1000;
+
+/// The profile is considered static, if the canvas did not change for this long
+pub const ADAPTIVE_FPS_IDLE_MILLIS: u64 = 2000;
+
+/// Frame rate of static profiles, in adaptive mode
+pub const ADAPTIVE_FPS_IDLE: u64 = 8;
+
+/// Lower the frame rate if the load average per CPU exceeds this value, in adaptive mode
+pub const ADAPTIVE_FPS_MAX_LOAD: f64 = 1.0;
 
 /// Static correction offset, to compensate for
 /// known time consumers like e.g. hardware accesses
@@ -63,3 +81,108 @@ pub const SENSOR_UPDATE_TICKS: u64 = 120;
 
 /// Timeout value to use for D-Bus connections
 pub const DBUS_TIMEOUT_MILLIS: u32 = 250;
+
+/// Max number of messages that may be queued on the inter-script message bus
+pub const MAX_PENDING_BUS_MESSAGES: usize = 1024;
+
+/// Max nesting depth of tables sent over the inter-script message bus
+pub const MAX_BUS_TABLE_DEPTH: usize = 16;
+
+/// Max nesting depth of tables encoded as JSON or TOML
+pub const MAX_SERIALIZE_DEPTH: usize = 64;
+
+/// Layers are exported to D-Bus clients, until they have not been requested for this long
+pub const LAYER_EXPORT_TIMEOUT_MILLIS: u64 = 2000;
+
+/// Default path of the debugger socket
+pub const DEFAULT_DEBUGGER_SOCKET: &str = "/run/eruption/debugger.sock";
+
+/// Timeout for requests of debugger sessions to Lua VMs
+pub const DEBUGGER_TIMEOUT_MILLIS: u64 = 5000;
+
+/// Number of recent handler calls that the rolling performance statistics are based on
+pub const STATS_WINDOW_SIZE: usize = 240;
+
+/// Log a summary of the performance statistics every 5 minutes (at log level "debug")
+pub const STATS_LOG_INTERVAL_SECS: u64 = 300;
+
+/// Latest version of the event API that scripts may declare in their manifests
+pub const CURRENT_EVENT_API_VERSION: u32 = 3;
+
+/// Max. width and height of images loaded by Lua scripts, in pixels
+pub const MAX_IMAGE_SIZE: usize = 4096;
+
+/// Max. number of frames of animated images loaded by Lua scripts
+pub const MAX_IMAGE_FRAMES: usize = 1024;
+
+/// Max. number of decoded pixels of an image, summed over all of its frames
+pub const MAX_IMAGE_PIXELS: usize = 16 * 1024 * 1024;
+
+/// Number of samples per axis, taken to compute the color of a key from an image
+pub const IMAGE_SAMPLES_PER_AXIS: usize = 4;
+
+/// Scroll speed of messages shown via D-Bus, in key units per second
+pub const MESSAGE_SCROLL_SPEED: f64 = 8.0;
+
+/// Backdrop of messages shown via D-Bus, dims the active profile
+pub const MESSAGE_BACKGROUND: u32 = 0xc000_0000;
+
+/// Max. length of messages shown via D-Bus, in characters
+pub const MAX_MESSAGE_LENGTH: usize = 256;
+
+/// Max. number of live particles per Lua VM
+pub const MAX_PARTICLES: usize = 2048;
+
+/// Max. number of particle emitters per Lua VM
+pub const MAX_EMITTERS: usize = 64;
+
+/// Max. number of live ripples per Lua VM
+pub const MAX_RIPPLES: usize = 256;
+
+/// Max. rate of a particle emitter, in particles per second
+pub const MAX_PARTICLE_RATE: f64 = 10_000.0;
+
+/// Max. time step of the particle simulation, in seconds
+pub const MAX_PARTICLE_TIME_STEP: f64 = 0.1;
+
+/// Number of samples per axis, taken to compute the coverage of a key by a shape
+pub const CANVAS_SAMPLES_PER_AXIS: usize = 4;
+
+/// Max. depth of the transformation stack of a vector canvas
+pub const MAX_CANVAS_STACK_DEPTH: usize = 64;
+
+/// Neutral color temperature, in Kelvin. The night light has no effect at this temperature
+pub const NEUTRAL_COLOR_TEMPERATURE: f64 = 6600.0;
+
+/// Min. color temperature of the night light, in Kelvin
+pub const MIN_COLOR_TEMPERATURE: f64 = 1000.0;
+
+/// Default color temperature of the night light, in Kelvin
+pub const NIGHT_LIGHT_TEMPERATURE: f64 = 3400.0;
+
+/// Default duration of the transitions of the night light, in minutes
+pub const NIGHT_LIGHT_TRANSITION_MINUTES: f64 = 30.0;
+
+/// Duration of a fade of the night light after its mode has been changed, in seconds
+pub const NIGHT_LIGHT_FADE_SECS: f64 = 2.0;
+
+/// Interval at which the schedule of the night light is evaluated
+pub const NIGHT_LIGHT_CHECK_INTERVAL_MILLIS: u64 = 1000;
+
+/// File name extension of recordings
+pub const RECORDING_EXTENSION: &str = "rec";
+
+/// Max. number of frames of a recording, 10 minutes at 60 fps
+pub const MAX_RECORDING_FRAMES: usize = 10 * 60 * 60;
+
+/// Max. number of recordings in the recording directory
+pub const MAX_RECORDINGS: usize = 64;
+
+/// Max. total size of the recordings in the recording directory, in bytes
+pub const MAX_RECORDINGS_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Max. number of octaves of fractal noise generators
+pub const MAX_NOISE_OCTAVES: usize = 16;
+
+/// Seed for the random number generators of scripts run by the test harness
+pub const HARNESS_RANDOM_SEED: u64 = 0x5eed;
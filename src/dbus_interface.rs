@@ -27,9 +27,16 @@ use std::sync::mpsc::Sender;
 use std::sync::Arc;
 
 use crate::constants;
+use crate::frame_rate;
+use crate::night_light;
 use crate::plugins::audio;
 use crate::profiles;
+use crate::recording;
 use crate::script;
+use crate::scripting::manifest;
+use crate::scripting::stats;
+use crate::text;
+use crate::visualize;
 use crate::CONFIG;
 
 /// D-Bus messages and signals that are processed by the main thread
@@ -155,24 +162,193 @@ impl DbusApi {
 
         let brightness_property_clone = Arc::new(brightness_property);
 
+        let night_light_property = f
+            .property::<&str, _>("NightLight", ())
+            .emits_changed(EmitsChangedSignal::True)
+            .access(Access::ReadWrite)
+            .auto_emit_on_set(true)
+            .on_get(|i, _m| {
+                i.append(night_light::get_mode().as_str());
+
+                Ok(())
+            })
+            .on_set(|i, _m| {
+                let mode = i
+                    .read::<&str>()?
+                    .parse::<night_light::Mode>()
+                    .map_err(|e| MethodErr::failed(&e))?;
+
+                night_light::set_mode(mode);
+
+                Ok(())
+            });
+
+        let night_light_property_clone = Arc::new(night_light_property);
+
+        let color_temperature_property = f
+            .property::<u64, _>("ColorTemperature", ())
+            .emits_changed(EmitsChangedSignal::False)
+            .on_get(|i, _m| {
+                i.append(night_light::get_temperature());
+
+                Ok(())
+            });
+
+        let color_temperature_property_clone = Arc::new(color_temperature_property);
+
         let tree = f
             .tree(())
             .add(
                 f.object_path("/org/eruption/status", ())
                     .introspectable()
                     .add(
-                        f.interface("org.eruption.Status", ()).add_p(
-                            f.property::<bool, _>("Running", ())
-                                .emits_changed(EmitsChangedSignal::True)
-                                .on_get(|i, _m| {
-                                    i.append(true);
-                                    Ok(())
+                        f.interface("org.eruption.Status", ())
+                            .add_p(
+                                f.property::<bool, _>("Running", ())
+                                    .emits_changed(EmitsChangedSignal::True)
+                                    .on_get(|i, _m| {
+                                        i.append(true);
+                                        Ok(())
+                                    })
+                                    .on_set(|i, _m| {
+                                        let _b: bool = i.read()?;
+                                        Ok(())
+                                    }),
+                            )
+                            .add_m(
+                                f.method("GetScriptStats", (), move |m| {
+                                    // durations are in microseconds
+                                    let s: Vec<(String, String, u64, u64, u64, u64)> =
+                                        stats::get_stats()
+                                            .iter()
+                                            .map(|(script, handler, stats)| {
+                                                (
+                                                    script.clone(),
+                                                    handler.clone(),
+                                                    stats.calls,
+                                                    stats.get_average().as_micros() as u64,
+                                                    stats.get_max().as_micros() as u64,
+                                                    stats.total.as_micros() as u64,
+                                                )
+                                            })
+                                            .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(
+                                    String,
+                                    String,
+                                    u64,
+                                    u64,
+                                    u64,
+                                    u64,
+                                )>, _>(
+                                    "stats"
+                                ),
+                            )
+                            .add_m(
+                                f.method("GetFrameStats", (), move |m| {
+                                    let settings = frame_rate::get_settings();
+                                    let stats = frame_rate::get_stats();
+
+                                    // durations are in microseconds
+                                    let s: (u64, u64, bool, u64, u64, u64, u64, u64) = (
+                                        frame_rate::get_current_fps(),
+                                        settings.target_fps,
+                                        settings.adaptive,
+                                        stats.frames,
+                                        stats.dropped,
+                                        stats.get_average().as_micros() as u64,
+                                        stats.get_max().as_micros() as u64,
+                                        stats.get_jitter().as_micros() as u64,
+                                    );
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<(
+                                    u64,
+                                    u64,
+                                    bool,
+                                    u64,
+                                    u64,
+                                    u64,
+                                    u64,
+                                    u64,
+                                ), _>(
+                                    "stats"
+                                ),
+                            )
+                            .add_m(
+                                f.method("GetLedMap", (), move |m| {
+                                    // 32 bits (ARGB) sRGB values, as sent to the device
+                                    let s = visualize::get_led_map();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<u32>, _>("colors"),
+                            )
+                            .add_m(
+                                f.method("GetLayerMaps", (), move |m| {
+                                    // unblended color maps of the active scripts, these are
+                                    // captured for a short time after each request only
+                                    let s = visualize::get_layer_maps();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(String, Vec<u32>)>, _>("layers"),
+                            ),
+                    ),
+            )
+            .add(
+                // methods that change the state of the daemon, restricted to root
+                // by the D-Bus policy
+                f.object_path("/org/eruption/control", ())
+                    .introspectable()
+                    .add(
+                        f.interface("org.eruption.Control", ())
+                            .add_m(
+                                f.method("ShowMessage", (), move |m| {
+                                    let (message, color): (&str, u32) = m.msg.read2()?;
+
+                                    if message.chars().count() > constants::MAX_MESSAGE_LENGTH {
+                                        Err(MethodErr::failed("Message too long"))
+                                    } else {
+                                        text::show_message(message, color);
+
+                                        let s = true;
+                                        Ok(vec![m.msg.method_return().append1(s)])
+                                    }
                                 })
-                                .on_set(|i, _m| {
-                                    let _b: bool = i.read()?;
-                                    Ok(())
-                                }),
-                        ),
+                                .inarg::<&str, _>("message")
+                                .inarg::<u32, _>("color")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StartRecording", (), move |m| {
+                                    // an empty layer records the whole profile
+                                    let (name, layer): (&str, &str) = m.msg.read2()?;
+                                    let layer = if layer.is_empty() { None } else { Some(layer) };
+
+                                    recording::start_recording(name, layer)
+                                        .map_err(|e| MethodErr::failed(&e))?;
+
+                                    let s = true;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .inarg::<&str, _>("name")
+                                .inarg::<&str, _>("layer")
+                                .outarg::<bool, _>("status"),
+                            )
+                            .add_m(
+                                f.method("StopRecording", (), move |m| {
+                                    let frames = recording::stop_recording()
+                                        .map_err(|e| MethodErr::failed(&e))?;
+
+                                    let s = frames as u64;
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<u64, _>("frames"),
+                            ),
                     ),
             )
             .add(
@@ -181,7 +357,9 @@ impl DbusApi {
                     .add(
                         f.interface("org.eruption.Config", ())
                             .add_p(enable_sfx_property_clone)
-                            .add_p(brightness_property_clone),
+                            .add_p(brightness_property_clone)
+                            .add_p(night_light_property_clone)
+                            .add_p(color_temperature_property_clone),
                     ),
             )
             .add(
@@ -310,6 +488,52 @@ impl DbusApi {
                                     Ok(vec![m.msg.method_return().append1(s)])
                                 })
                                 .outarg::<Vec<(String, String)>, _>("profiles"),
+                            )
+                            .add_m(
+                                f.method("EnumScripts", (), move |m| {
+                                    let script_dir = PathBuf::from(
+                                        CONFIG
+                                            .lock()
+                                            .as_ref()
+                                            .unwrap()
+                                            .get_str("global.script_dir")
+                                            .unwrap_or_else(|_| {
+                                                constants::DEFAULT_SCRIPT_DIR.to_string()
+                                            }),
+                                    );
+
+                                    let s: Vec<(String, String, String, String)> =
+                                        manifest::get_scripts(&script_dir)
+                                            .unwrap_or_else(|e| {
+                                                error!("Could not enumerate scripts: {}", e);
+                                                vec![]
+                                            })
+                                            .iter()
+                                            .map(|manifest| {
+                                                (
+                                                    manifest.name.clone(),
+                                                    manifest
+                                                        .script_file
+                                                        .file_name()
+                                                        .unwrap()
+                                                        .to_string_lossy()
+                                                        .to_string(),
+                                                    manifest.version.clone(),
+                                                    manifest.compatibility.as_str().to_string(),
+                                                )
+                                            })
+                                            .collect();
+
+                                    Ok(vec![m.msg.method_return().append1(s)])
+                                })
+                                .outarg::<Vec<(
+                                    String,
+                                    String,
+                                    String,
+                                    String,
+                                )>, _>(
+                                    "scripts"
+                                ),
                             ),
                     ),
             );
@@ -0,0 +1,380 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::constants;
+use crate::profiles::Profile;
+
+lazy_static! {
+    /// Frame rate settings of the installation, overridden by the active profile
+    static ref SETTINGS: Mutex<FrameRateSettings> = Mutex::new(FrameRateSettings::default());
+
+    /// Frame time statistics of the main loop
+    static ref FRAME_STATS: Mutex<FrameStats> = Mutex::new(FrameStats::default());
+}
+
+/// The frame rate the main loop currently runs at, adaptive mode may lower it
+/// below the target frame rate
+static CURRENT_FPS: AtomicU64 = AtomicU64::new(constants::TARGET_FPS);
+
+/// Frame rate settings
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameRateSettings {
+    pub target_fps: u64,
+    pub adaptive: bool,
+}
+
+impl Default for FrameRateSettings {
+    fn default() -> Self {
+        Self {
+            target_fps: constants::TARGET_FPS,
+            adaptive: false,
+        }
+    }
+}
+
+impl FrameRateSettings {
+    /// Get the settings from the `global.target_fps` and `global.adaptive_fps`
+    /// settings of the configuration file
+    pub fn from_config() -> Self {
+        let config = crate::CONFIG.lock();
+        let config = config.as_ref();
+
+        let target_fps = config
+            .and_then(|c| c.get_int("global.target_fps").ok())
+            .map(|fps| fps.max(0) as u64)
+            .unwrap_or(constants::TARGET_FPS);
+
+        let adaptive = config
+            .and_then(|c| c.get_bool("global.adaptive_fps").ok())
+            .unwrap_or(false);
+
+        Self {
+            target_fps,
+            adaptive,
+        }
+        .clamped()
+    }
+
+    /// Apply the overrides of `profile`
+    pub fn with_profile(self, profile: &Profile) -> Self {
+        Self {
+            target_fps: profile.target_fps.unwrap_or(self.target_fps),
+            adaptive: profile.adaptive_fps.unwrap_or(self.adaptive),
+        }
+        .clamped()
+    }
+
+    fn clamped(self) -> Self {
+        let target_fps = self
+            .target_fps
+            .max(constants::MIN_TARGET_FPS)
+            .min(constants::MAX_TARGET_FPS);
+
+        if target_fps != self.target_fps {
+            warn!(
+                "Target frame rate of {} fps is out of range, using {} fps",
+                self.target_fps, target_fps
+            );
+        }
+
+        Self { target_fps, ..self }
+    }
+}
+
+/// Use the frame rate settings of the installation, with the overrides of `profile`
+pub fn apply_profile(profile: &Profile) {
+    let settings = FrameRateSettings::from_config().with_profile(profile);
+
+    info!(
+        "Target frame rate: {} fps{}",
+        settings.target_fps,
+        if settings.adaptive { " (adaptive)" } else { "" }
+    );
+
+    *SETTINGS.lock() = settings;
+}
+
+/// Get the frame rate settings that are currently in effect
+pub fn get_settings() -> FrameRateSettings {
+    *SETTINGS.lock()
+}
+
+/// Get the frame rate the main loop currently runs at
+pub fn get_current_fps() -> u64 {
+    CURRENT_FPS.load(Ordering::SeqCst)
+}
+
+/// Get the duration of a frame at the current frame rate
+pub fn get_frame_duration() -> Duration {
+    Duration::from_micros(1_000_000 / get_current_fps().max(1))
+}
+
+/// Average load of the system per CPU, over the last minute
+fn get_system_load() -> Option<f64> {
+    let load: f64 = fs::read_to_string("/proc/loadavg")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.max(1);
+
+    Some(load / cpus as f64)
+}
+
+/// Controls the frame rate of the main loop. In adaptive mode the frame rate
+/// is lowered while the active profile is static, and while the system is
+/// under load. It is restored as soon as that is no longer the case
+pub struct FrameRateController {
+    /// Time of the most recent frame that differed from its predecessor
+    last_new_frame: Instant,
+
+    /// Time of the most recent adjustment of the frame rate
+    last_adjustment: Instant,
+
+    /// Number of dropped frames at the time of the most recent adjustment
+    dropped_frames: u64,
+
+    /// Set while the frame rate is lowered because the profile is static
+    idle: bool,
+}
+
+impl Default for FrameRateController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameRateController {
+    pub fn new() -> Self {
+        Self {
+            last_new_frame: Instant::now(),
+            last_adjustment: Instant::now(),
+            dropped_frames: 0,
+            idle: false,
+        }
+    }
+
+    /// Called once per iteration of the main loop, `new_frame` is true if the
+    /// canvas has changed since the previous iteration
+    pub fn update(&mut self, new_frame: bool) {
+        let settings = get_settings();
+        let current_fps = get_current_fps();
+
+        if new_frame {
+            self.last_new_frame = Instant::now();
+        }
+
+        let fps = if !settings.adaptive {
+            settings.target_fps
+        } else if new_frame && self.idle {
+            // the profile is not static anymore, react immediately
+            self.idle = false;
+            self.last_adjustment = Instant::now();
+
+            settings.target_fps
+        } else if self.last_adjustment.elapsed()
+            >= Duration::from_millis(constants::ADAPTIVE_FPS_INTERVAL_MILLIS)
+        {
+            self.last_adjustment = Instant::now();
+
+            let dropped_frames = FRAME_STATS.lock().dropped;
+            let frames_dropped = dropped_frames > self.dropped_frames;
+            self.dropped_frames = dropped_frames;
+
+            let overloaded = frames_dropped
+                || get_system_load()
+                    .map(|load| load > constants::ADAPTIVE_FPS_MAX_LOAD)
+                    .unwrap_or(false);
+
+            self.idle = self.last_new_frame.elapsed()
+                >= Duration::from_millis(constants::ADAPTIVE_FPS_IDLE_MILLIS);
+
+            if self.idle {
+                constants::ADAPTIVE_FPS_IDLE
+            } else if overloaded {
+                // back off by a quarter
+                current_fps - current_fps / 4
+            } else {
+                // recover by a quarter of the target frame rate
+                current_fps + (settings.target_fps / 4).max(1)
+            }
+            .max(constants::MIN_TARGET_FPS)
+            .min(settings.target_fps)
+        } else {
+            current_fps.min(settings.target_fps)
+        };
+
+        if fps != current_fps {
+            debug!("Frame rate: {} fps", fps);
+
+            CURRENT_FPS.store(fps, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns true while the frame rate is lowered because the profile is static
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Restore the target frame rate immediately, e.g. after an input event
+    pub fn wake(&mut self) {
+        self.idle = false;
+        self.last_new_frame = Instant::now();
+
+        CURRENT_FPS.store(get_settings().target_fps, Ordering::SeqCst);
+    }
+}
+
+/// Frame time statistics of the main loop
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    /// Number of frames since the daemon has been started
+    pub frames: u64,
+
+    /// Number of frames that have been dropped, e.g. because an iteration of
+    /// the main loop took longer than the duration of a frame
+    pub dropped: u64,
+
+    /// The most recent samples of (frame time, target frame time)
+    window: VecDeque<(Duration, Duration)>,
+}
+
+impl FrameStats {
+    /// Record a frame, returns the number of frames that have been dropped
+    fn record(&mut self, elapsed: Duration, target: Duration) -> u64 {
+        self.frames += 1;
+
+        // frames that would have been due while this iteration was running
+        let dropped = if target.as_micros() > 0 {
+            ((elapsed.as_micros() / target.as_micros()) as u64).saturating_sub(1)
+        } else {
+            0
+        };
+
+        self.dropped += dropped;
+
+        if self.window.len() >= constants::STATS_WINDOW_SIZE {
+            self.window.pop_front();
+        }
+
+        self.window.push_back((elapsed, target));
+
+        dropped
+    }
+
+    /// Average frame time of the most recent frames
+    pub fn get_average(&self) -> Duration {
+        if self.window.is_empty() {
+            Duration::from_secs(0)
+        } else {
+            self.window.iter().map(|s| s.0).sum::<Duration>() / self.window.len() as u32
+        }
+    }
+
+    /// Maximum frame time of the most recent frames
+    pub fn get_max(&self) -> Duration {
+        self.window
+            .iter()
+            .map(|s| s.0)
+            .max()
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+
+    /// Jitter of the most recent frames, the mean absolute deviation of the
+    /// frame times from the target frame time
+    pub fn get_jitter(&self) -> Duration {
+        if self.window.is_empty() {
+            Duration::from_secs(0)
+        } else {
+            let deviation: f64 = self
+                .window
+                .iter()
+                .map(|(elapsed, target)| (elapsed.as_secs_f64() - target.as_secs_f64()).abs())
+                .sum();
+
+            Duration::from_secs_f64(deviation / self.window.len() as f64)
+        }
+    }
+}
+
+/// Record the duration of an iteration of the main loop
+pub fn record_frame(elapsed: Duration) {
+    let target = get_frame_duration();
+    let dropped = FRAME_STATS.lock().record(elapsed, target);
+
+    if dropped > 0 {
+        warn!(
+            "Dropped {} frame(s), loop took: {} milliseconds, goal: {}",
+            dropped,
+            elapsed.as_millis(),
+            target.as_millis()
+        );
+    }
+}
+
+/// Record a frame that has been dropped, e.g. because a script failed to
+/// realize its color map in time
+pub fn record_dropped_frame() {
+    FRAME_STATS.lock().dropped += 1;
+}
+
+/// Get a snapshot of the frame time statistics
+pub fn get_stats() -> FrameStats {
+    FRAME_STATS.lock().clone()
+}
+
+/// Log the frame time statistics
+pub fn log_summary(level: Level) {
+    let stats = get_stats();
+
+    log!(
+        level,
+        "Frame statistics: {} fps (target: {} fps), frames: {}, dropped: {}, avg: {:.3} ms, max: {:.3} ms, jitter: {:.3} ms",
+        get_current_fps(),
+        get_settings().target_fps,
+        stats.frames,
+        stats.dropped,
+        stats.get_average().as_secs_f64() * 1000.0,
+        stats.get_max().as_secs_f64() * 1000.0,
+        stats.get_jitter().as_secs_f64() * 1000.0
+    );
+}
+
+#[test]
+fn test_frame_stats() {
+    let mut stats = FrameStats::default();
+    let target = Duration::from_millis(40);
+
+    stats.record(Duration::from_millis(40), target);
+    stats.record(Duration::from_millis(50), target);
+    stats.record(Duration::from_millis(130), target);
+
+    assert_eq!(stats.frames, 3);
+    assert_eq!(stats.dropped, 2);
+    assert_eq!(stats.get_max(), Duration::from_millis(130));
+    assert_eq!(stats.get_jitter().as_millis(), 33);
+}
@@ -0,0 +1,251 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+
+/// Physical properties of a single key. All coordinates and dimensions are
+/// specified in key units (1u == 19.05mm), relative to the top left corner
+/// of the Esc key
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct KeyGeometry {
+    /// Hardware key index (0-based)
+    pub index: usize,
+
+    /// Layout independent name of the key
+    pub name: &'static str,
+
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl KeyGeometry {
+    /// Center of the key
+    pub fn center(&self) -> (f64, f64) {
+        (self.x + self.width / 2.0, self.y + self.height / 2.0)
+    }
+}
+
+/// Keyboard variants
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum KeyboardVariant {
+    ISO,
+    ANSI,
+}
+
+impl KeyboardVariant {
+    /// Get the keyboard variant from the `global.keyboard_variant` setting
+    pub fn from_config() -> Self {
+        let variant = crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str("global.keyboard_variant").ok())
+            .unwrap_or_else(|| "ISO".to_string());
+
+        if variant.eq_ignore_ascii_case("ANSI") {
+            KeyboardVariant::ANSI
+        } else {
+            KeyboardVariant::ISO
+        }
+    }
+}
+
+/// Width of the keyboard, in key units
+pub const KEYBOARD_WIDTH: f64 = 22.5;
+
+/// Height of the keyboard, in key units
+pub const KEYBOARD_HEIGHT: f64 = 6.5;
+
+/// Size of a key unit, in millimeters
+pub const KEY_UNIT_MM: f64 = 19.05;
+
+// (index, name, x, y, width, height)
+type KeyDef = (usize, &'static str, f64, f64, f64, f64);
+
+#[rustfmt::skip]
+const COMMON_KEYS: &[KeyDef] = &[
+    // function key row
+    (0x00, "ESC", 0.0, 0.0, 1.0, 1.0),
+    (0x0b, "F1", 2.0, 0.0, 1.0, 1.0),
+    (0x11, "F2", 3.0, 0.0, 1.0, 1.0),
+    (0x17, "F3", 4.0, 0.0, 1.0, 1.0),
+    (0x1c, "F4", 5.0, 0.0, 1.0, 1.0),
+    (0x30, "F5", 6.5, 0.0, 1.0, 1.0),
+    (0x35, "F6", 7.5, 0.0, 1.0, 1.0),
+    (0x3b, "F7", 8.5, 0.0, 1.0, 1.0),
+    (0x41, "F8", 9.5, 0.0, 1.0, 1.0),
+    (0x4e, "F9", 11.0, 0.0, 1.0, 1.0),
+    (0x54, "F10", 12.0, 0.0, 1.0, 1.0),
+    (0x55, "F11", 13.0, 0.0, 1.0, 1.0),
+    (0x56, "F12", 14.0, 0.0, 1.0, 1.0),
+    (0x63, "PRINT", 15.25, 0.0, 1.0, 1.0),
+    (0x67, "SCROLL_LOCK", 16.25, 0.0, 1.0, 1.0),
+    (0x6c, "PAUSE", 17.25, 0.0, 1.0, 1.0),
+
+    // number row
+    (0x01, "GRAVE", 0.0, 1.5, 1.0, 1.0),
+    (0x06, "1", 1.0, 1.5, 1.0, 1.0),
+    (0x0c, "2", 2.0, 1.5, 1.0, 1.0),
+    (0x12, "3", 3.0, 1.5, 1.0, 1.0),
+    (0x18, "4", 4.0, 1.5, 1.0, 1.0),
+    (0x1d, "5", 5.0, 1.5, 1.0, 1.0),
+    (0x21, "6", 6.0, 1.5, 1.0, 1.0),
+    (0x31, "7", 7.0, 1.5, 1.0, 1.0),
+    (0x36, "8", 8.0, 1.5, 1.0, 1.0),
+    (0x3c, "9", 9.0, 1.5, 1.0, 1.0),
+    (0x42, "0", 10.0, 1.5, 1.0, 1.0),
+    (0x48, "MINUS", 11.0, 1.5, 1.0, 1.0),
+    (0x4f, "EQUAL", 12.0, 1.5, 1.0, 1.0),
+    (0x57, "BACKSPACE", 13.0, 1.5, 2.0, 1.0),
+    (0x64, "INSERT", 15.25, 1.5, 1.0, 1.0),
+    (0x68, "HOME", 16.25, 1.5, 1.0, 1.0),
+    (0x6d, "PAGE_UP", 17.25, 1.5, 1.0, 1.0),
+    (0x71, "NUM_LOCK", 18.5, 1.5, 1.0, 1.0),
+    (0x77, "KP_DIVIDE", 19.5, 1.5, 1.0, 1.0),
+    (0x7c, "KP_MULTIPLY", 20.5, 1.5, 1.0, 1.0),
+    (0x81, "KP_MINUS", 21.5, 1.5, 1.0, 1.0),
+
+    // upper letter row
+    (0x02, "TAB", 0.0, 2.5, 1.5, 1.0),
+    (0x07, "Q", 1.5, 2.5, 1.0, 1.0),
+    (0x0d, "W", 2.5, 2.5, 1.0, 1.0),
+    (0x13, "E", 3.5, 2.5, 1.0, 1.0),
+    (0x19, "R", 4.5, 2.5, 1.0, 1.0),
+    (0x1e, "T", 5.5, 2.5, 1.0, 1.0),
+    (0x22, "Y", 6.5, 2.5, 1.0, 1.0),
+    (0x32, "U", 7.5, 2.5, 1.0, 1.0),
+    (0x37, "I", 8.5, 2.5, 1.0, 1.0),
+    (0x3d, "O", 9.5, 2.5, 1.0, 1.0),
+    (0x43, "P", 10.5, 2.5, 1.0, 1.0),
+    (0x49, "LEFT_BRACE", 11.5, 2.5, 1.0, 1.0),
+    (0x50, "RIGHT_BRACE", 12.5, 2.5, 1.0, 1.0),
+    (0x65, "DELETE", 15.25, 2.5, 1.0, 1.0),
+    (0x69, "END", 16.25, 2.5, 1.0, 1.0),
+    (0x6e, "PAGE_DOWN", 17.25, 2.5, 1.0, 1.0),
+    (0x72, "KP_7", 18.5, 2.5, 1.0, 1.0),
+    (0x78, "KP_8", 19.5, 2.5, 1.0, 1.0),
+    (0x7d, "KP_9", 20.5, 2.5, 1.0, 1.0),
+    (0x82, "KP_PLUS", 21.5, 2.5, 1.0, 2.0),
+
+    // home row
+    (0x03, "CAPS_LOCK", 0.0, 3.5, 1.75, 1.0),
+    (0x08, "A", 1.75, 3.5, 1.0, 1.0),
+    (0x0e, "S", 2.75, 3.5, 1.0, 1.0),
+    (0x14, "D", 3.75, 3.5, 1.0, 1.0),
+    (0x1a, "F", 4.75, 3.5, 1.0, 1.0),
+    (0x1f, "G", 5.75, 3.5, 1.0, 1.0),
+    (0x23, "H", 6.75, 3.5, 1.0, 1.0),
+    (0x33, "J", 7.75, 3.5, 1.0, 1.0),
+    (0x38, "K", 8.75, 3.5, 1.0, 1.0),
+    (0x3e, "L", 9.75, 3.5, 1.0, 1.0),
+    (0x44, "SEMICOLON", 10.75, 3.5, 1.0, 1.0),
+    (0x4a, "APOSTROPHE", 11.75, 3.5, 1.0, 1.0),
+    (0x73, "KP_4", 18.5, 3.5, 1.0, 1.0),
+    (0x79, "KP_5", 19.5, 3.5, 1.0, 1.0),
+    (0x7e, "KP_6", 20.5, 3.5, 1.0, 1.0),
+
+    // lower letter row
+    (0x0f, "Z", 2.25, 4.5, 1.0, 1.0),
+    (0x15, "X", 3.25, 4.5, 1.0, 1.0),
+    (0x1b, "C", 4.25, 4.5, 1.0, 1.0),
+    (0x20, "V", 5.25, 4.5, 1.0, 1.0),
+    (0x24, "B", 6.25, 4.5, 1.0, 1.0),
+    (0x34, "N", 7.25, 4.5, 1.0, 1.0),
+    (0x39, "M", 8.25, 4.5, 1.0, 1.0),
+    (0x3f, "COMMA", 9.25, 4.5, 1.0, 1.0),
+    (0x45, "DOT", 10.25, 4.5, 1.0, 1.0),
+    (0x4b, "SLASH", 11.25, 4.5, 1.0, 1.0),
+    (0x52, "RIGHT_SHIFT", 12.25, 4.5, 2.75, 1.0),
+    (0x6a, "UP", 16.25, 4.5, 1.0, 1.0),
+    (0x74, "KP_1", 18.5, 4.5, 1.0, 1.0),
+    (0x7a, "KP_2", 19.5, 4.5, 1.0, 1.0),
+    (0x7f, "KP_3", 20.5, 4.5, 1.0, 1.0),
+    (0x83, "KP_ENTER", 21.5, 4.5, 1.0, 2.0),
+
+    // bottom row
+    (0x05, "LEFT_CTRL", 0.0, 5.5, 1.25, 1.0),
+    (0x0a, "LEFT_META", 1.25, 5.5, 1.25, 1.0),
+    (0x10, "LEFT_ALT", 2.5, 5.5, 1.25, 1.0),
+    (0x25, "SPACE", 3.75, 5.5, 6.25, 1.0),
+    (0x46, "RIGHT_ALT", 10.0, 5.5, 1.25, 1.0),
+    (0x4c, "FN", 11.25, 5.5, 1.25, 1.0),
+    (0x53, "COMPOSE", 12.5, 5.5, 1.25, 1.0),
+    (0x59, "RIGHT_CTRL", 13.75, 5.5, 1.25, 1.0),
+    (0x66, "LEFT", 15.25, 5.5, 1.0, 1.0),
+    (0x6b, "DOWN", 16.25, 5.5, 1.0, 1.0),
+    (0x6f, "RIGHT", 17.25, 5.5, 1.0, 1.0),
+    (0x75, "KP_0", 18.5, 5.5, 2.0, 1.0),
+    (0x80, "KP_DOT", 20.5, 5.5, 1.0, 1.0),
+];
+
+#[rustfmt::skip]
+const ISO_KEYS: &[KeyDef] = &[
+    (0x58, "ENTER", 13.75, 2.5, 1.25, 2.0),
+    (0x60, "HASH", 12.75, 3.5, 1.0, 1.0),
+    (0x04, "LEFT_SHIFT", 0.0, 4.5, 1.25, 1.0),
+    (0x09, "102ND", 1.25, 4.5, 1.0, 1.0),
+];
+
+#[rustfmt::skip]
+const ANSI_KEYS: &[KeyDef] = &[
+    (0x51, "BACKSLASH", 13.5, 2.5, 1.5, 1.0),
+    (0x58, "ENTER", 12.75, 3.5, 2.25, 1.0),
+    (0x04, "LEFT_SHIFT", 0.0, 4.5, 2.25, 1.0),
+];
+
+fn build_layout(variant_keys: &[KeyDef]) -> Vec<KeyGeometry> {
+    let mut result: Vec<KeyGeometry> = COMMON_KEYS
+        .iter()
+        .chain(variant_keys.iter())
+        .map(|&(index, name, x, y, width, height)| KeyGeometry {
+            index,
+            name,
+            x,
+            y,
+            width,
+            height,
+        })
+        .collect();
+
+    result.sort_by_key(|k| k.index);
+
+    result
+}
+
+lazy_static! {
+    static ref ISO_LAYOUT: Vec<KeyGeometry> = build_layout(ISO_KEYS);
+    static ref ANSI_LAYOUT: Vec<KeyGeometry> = build_layout(ANSI_KEYS);
+}
+
+/// Get the geometry of all keys that have a LED, sorted by key index
+pub fn get_layout(variant: KeyboardVariant) -> &'static [KeyGeometry] {
+    match variant {
+        KeyboardVariant::ISO => &ISO_LAYOUT,
+        KeyboardVariant::ANSI => &ANSI_LAYOUT,
+    }
+}
+
+/// Get the geometry of the key with the hardware index `index`
+pub fn get_key(variant: KeyboardVariant, index: usize) -> Option<&'static KeyGeometry> {
+    get_layout(variant).iter().find(|k| k.index == index)
+}
+
+/// Get the geometry of the key named `name`
+pub fn find_key(variant: KeyboardVariant, name: &str) -> Option<&'static KeyGeometry> {
+    get_layout(variant).iter().find(|k| k.name == name)
+}
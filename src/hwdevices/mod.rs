@@ -20,8 +20,11 @@ use log::*;
 use parking_lot::RwLock;
 use std::sync::Arc;
 
+pub mod layout;
 mod roccat_vulcan;
+mod simulated;
 pub use roccat_vulcan::*;
+pub use simulated::SimulatedDevice;
 
 pub type HwDevice = Arc<RwLock<dyn Device + Sync + Send>>;
 
@@ -72,7 +75,7 @@ pub enum HwDeviceError {
 }
 
 /// Represents an RGBA color value
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct RGBA {
     pub r: u8,
     pub g: u8,
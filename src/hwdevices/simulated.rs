@@ -0,0 +1,118 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use std::thread;
+use std::time::Duration;
+
+use super::{Device, DeviceInfo, HidEvent, LedKind, Result, NUM_KEYS, RGBA};
+
+/// A simulated device without any hardware attached. Used to run scripts
+/// headless, e.g. by the script test harness
+pub struct SimulatedDevice {
+    led_map: Vec<RGBA>,
+}
+
+impl SimulatedDevice {
+    pub fn new() -> Self {
+        SimulatedDevice {
+            led_map: vec![
+                RGBA {
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                    a: 0,
+                };
+                NUM_KEYS
+            ],
+        }
+    }
+
+    /// Get the LED map that has been sent last
+    #[allow(dead_code)]
+    pub fn get_led_map(&self) -> &[RGBA] {
+        &self.led_map
+    }
+}
+
+impl Device for SimulatedDevice {
+    fn get_usb_path(&self) -> String {
+        "simulated".to_string()
+    }
+
+    fn open(&mut self, _api: &hidapi::HidApi) -> Result<()> {
+        Ok(())
+    }
+
+    fn close_all(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn send_init_sequence(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_status_led(&self, _led_kind: LedKind, _on: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_data_raw(&self, _buf: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_data_raw(&self, size: usize) -> Result<Vec<u8>> {
+        Ok(vec![0; size])
+    }
+
+    fn get_device_info(&self) -> Result<DeviceInfo> {
+        Ok(DeviceInfo {
+            report_id: 0x0f,
+            size: 0,
+            reserved1: 0,
+            firmware_version: 0,
+            reserved2: 0,
+        })
+    }
+
+    fn get_next_event(&self) -> Result<HidEvent> {
+        self.get_next_event_timeout(1000)
+    }
+
+    fn get_next_event_timeout(&self, millis: i32) -> Result<HidEvent> {
+        // there will never be any events, so just honor the timeout
+        thread::sleep(Duration::from_millis(millis.max(0) as u64));
+
+        Ok(HidEvent::Unknown)
+    }
+
+    fn send_led_map(&mut self, led_map: &[RGBA]) -> Result<()> {
+        trace!("Simulated device: LED map of {} keys", led_map.len());
+
+        self.led_map.clear();
+        self.led_map.extend_from_slice(led_map);
+
+        Ok(())
+    }
+
+    fn set_led_init_pattern(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_led_off_pattern(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
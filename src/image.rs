@@ -0,0 +1,507 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::canvas::Color;
+use crate::constants;
+use crate::hwdevices::layout::{self, KeyboardVariant, KEYBOARD_HEIGHT, KEYBOARD_WIDTH};
+use crate::hwdevices::NUM_KEYS;
+
+pub type Result<T> = std::result::Result<T, ImageError>;
+
+#[derive(Debug, Fail)]
+pub enum ImageError {
+    #[fail(display = "Could not open image file: {}", msg)]
+    OpenError { msg: String },
+
+    #[fail(display = "Unsupported image format, only PNG and GIF images are supported")]
+    UnsupportedFormat {},
+
+    #[fail(display = "Could not decode image: {}", msg)]
+    DecodeError { msg: String },
+
+    #[fail(display = "Image exceeds the maximum supported size")]
+    ImageTooLarge {},
+}
+
+/// A single frame of an image, pixels are 32 bits (ARGB) sRGB values
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub pixels: Vec<u32>,
+
+    /// Time to display the frame, before advancing to the next one
+    pub delay: Duration,
+}
+
+/// How an image is scaled to the size of the keyboard
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Fit {
+    /// Cover the whole keyboard, ignoring the aspect ratio of the image
+    Stretch,
+
+    /// Show the whole image, preserving its aspect ratio
+    Contain,
+
+    /// Cover the whole keyboard, preserving the aspect ratio of the image
+    Cover,
+}
+
+impl Fit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "stretch" => Some(Fit::Stretch),
+            "contain" => Some(Fit::Contain),
+            "cover" => Some(Fit::Cover),
+
+            _ => None,
+        }
+    }
+}
+
+/// Placement of an image on the keyboard
+#[derive(Debug, Copy, Clone)]
+pub struct SampleOptions {
+    pub fit: Fit,
+
+    /// Zoom factor, applied after fitting the image
+    pub scale: f64,
+
+    /// Rotation around the center of the keyboard, in radians
+    pub angle: f64,
+
+    /// Scroll offset, as a fraction of the width of the image
+    pub x_offset: f64,
+
+    /// Scroll offset, as a fraction of the height of the image
+    pub y_offset: f64,
+
+    /// Repeat the image, instead of leaving keys outside of it transparent
+    pub wrap: bool,
+
+    /// Opacity in the range [0.0..1.0], multiplied with the alpha of the image
+    pub opacity: f64,
+}
+
+impl Default for SampleOptions {
+    fn default() -> Self {
+        Self {
+            fit: Fit::Stretch,
+            scale: 1.0,
+            angle: 0.0,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            wrap: false,
+            opacity: 1.0,
+        }
+    }
+}
+
+/// A decoded image, with one or more frames
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub frames: Vec<Frame>,
+
+    /// Start of the playback of an animated image
+    start: Instant,
+}
+
+fn check_size(width: usize, height: usize) -> Result<()> {
+    if width == 0
+        || height == 0
+        || width > constants::MAX_IMAGE_SIZE
+        || height > constants::MAX_IMAGE_SIZE
+        || width * height > constants::MAX_IMAGE_PIXELS
+    {
+        Err(ImageError::ImageTooLarge {})
+    } else {
+        Ok(())
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<Image> {
+    let decode_error = |e: png::DecodingError| ImageError::DecodeError {
+        msg: format!("{}", e),
+    };
+
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+
+    // expand paletted and low bit depth images to 8 bits per channel
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info().map_err(decode_error)?;
+    check_size(reader.info().width as usize, reader.info().height as usize)?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(decode_error)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+
+        png::ColorType::Indexed => return Err(ImageError::UnsupportedFormat {}),
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut pixels = Vec::with_capacity(width * height);
+
+    for row in buf.chunks(info.line_size).take(height) {
+        for p in row.chunks(channels).take(width) {
+            let (r, g, b, a) = match channels {
+                1 => (p[0], p[0], p[0], 0xff),
+                2 => (p[0], p[0], p[0], p[1]),
+                3 => (p[0], p[1], p[2], 0xff),
+                _ => (p[0], p[1], p[2], p[3]),
+            };
+
+            pixels.push(u32::from(a) << 24 | u32::from(r) << 16 | u32::from(g) << 8 | u32::from(b));
+        }
+    }
+
+    Ok(Image {
+        width,
+        height,
+        frames: vec![Frame {
+            pixels,
+            delay: Duration::from_secs(0),
+        }],
+        start: Instant::now(),
+    })
+}
+
+fn decode_gif(data: &[u8]) -> Result<Image> {
+    let decode_error = |e: gif::DecodingError| ImageError::DecodeError {
+        msg: format!("{}", e),
+    };
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+
+    let mut decoder = options.read_info(Cursor::new(data)).map_err(decode_error)?;
+
+    let (width, height) = (decoder.width() as usize, decoder.height() as usize);
+    check_size(width, height)?;
+
+    // frames of a GIF may only cover a part of the logical screen, so they
+    // are composited onto a canvas, honoring their disposal methods
+    let mut canvas = vec![0; width * height];
+    let mut frames = vec![];
+
+    while let Some(frame) = decoder.read_next_frame().map_err(decode_error)? {
+        // each frame stores a copy of the whole canvas
+        if frames.len() >= constants::MAX_IMAGE_FRAMES
+            || (frames.len() + 1) * width * height > constants::MAX_IMAGE_PIXELS
+        {
+            return Err(ImageError::ImageTooLarge {});
+        }
+
+        let previous = if frame.dispose == gif::DisposalMethod::Previous {
+            Some(canvas.clone())
+        } else {
+            None
+        };
+
+        let (left, top) = (frame.left as usize, frame.top as usize);
+        let (frame_width, frame_height) = (frame.width as usize, frame.height as usize);
+
+        for y in 0..frame_height {
+            for x in 0..frame_width {
+                let (cx, cy) = (left + x, top + y);
+                let p = &frame.buffer[(y * frame_width + x) * 4..][..4];
+
+                // fully transparent pixels leave the canvas untouched
+                if cx < width && cy < height && p[3] != 0 {
+                    canvas[cy * width + cx] = u32::from(p[3]) << 24
+                        | u32::from(p[0]) << 16
+                        | u32::from(p[1]) << 8
+                        | u32::from(p[2]);
+                }
+            }
+        }
+
+        // like web browsers, treat very short delays as the default delay of 100ms
+        let delay = if frame.delay < 2 { 10 } else { frame.delay };
+
+        frames.push(Frame {
+            pixels: canvas.clone(),
+            delay: Duration::from_millis(u64::from(delay) * 10),
+        });
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame_height).min(height) {
+                    for x in left..(left + frame_width).min(width) {
+                        canvas[y * width + x] = 0;
+                    }
+                }
+            }
+
+            gif::DisposalMethod::Previous => canvas = previous.unwrap(),
+
+            _ => {}
+        }
+    }
+
+    if frames.is_empty() {
+        return Err(ImageError::DecodeError {
+            msg: "The image does not contain any frames".to_string(),
+        });
+    }
+
+    Ok(Image {
+        width,
+        height,
+        frames,
+        start: Instant::now(),
+    })
+}
+
+impl Image {
+    /// Load a PNG or GIF image, the format is detected from the contents of the file
+    pub fn load(file: &Path) -> Result<Self> {
+        let data = fs::read(file).map_err(|e| ImageError::OpenError {
+            msg: format!("{}: {}", file.display(), e),
+        })?;
+
+        if data.starts_with(b"\x89PNG") {
+            decode_png(&data)
+        } else if data.starts_with(b"GIF8") {
+            decode_gif(&data)
+        } else {
+            Err(ImageError::UnsupportedFormat {})
+        }
+    }
+
+    /// Restart the playback of an animated image
+    pub fn rewind(&mut self) {
+        self.start = Instant::now();
+    }
+
+    /// Get the index of the frame that is to be displayed at `elapsed` after
+    /// the start of the playback. Animations are repeated indefinitely
+    pub fn get_frame_at(&self, elapsed: Duration) -> usize {
+        let total: Duration = self.frames.iter().map(|f| f.delay).sum();
+
+        if self.frames.len() < 2 || total.as_millis() == 0 {
+            return 0;
+        }
+
+        let mut t = elapsed.as_millis() % total.as_millis();
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            if t < frame.delay.as_millis() {
+                return i;
+            }
+
+            t -= frame.delay.as_millis();
+        }
+
+        self.frames.len() - 1
+    }
+
+    /// Get the index of the frame that is currently being played
+    pub fn get_current_frame(&self) -> usize {
+        self.get_frame_at(self.start.elapsed())
+    }
+
+    fn get_pixel(&self, frame: usize, x: f64, y: f64, wrap: bool) -> u32 {
+        let (w, h) = (self.width as f64, self.height as f64);
+
+        let (x, y) = if wrap {
+            (x.rem_euclid(w), y.rem_euclid(h))
+        } else if x < 0.0 || y < 0.0 || x >= w || y >= h {
+            return 0;
+        } else {
+            (x, y)
+        };
+
+        let (x, y) = (
+            (x as usize).min(self.width - 1),
+            (y as usize).min(self.height - 1),
+        );
+
+        self.frames[frame].pixels[y * self.width + x]
+    }
+
+    /// Sample the frame `frame` onto the keys of the keyboard. Each key gets
+    /// the average color of the area of the image it covers. The result is
+    /// a color map, keys without a LED are transparent
+    pub fn sample(&self, frame: usize, options: &SampleOptions) -> Vec<u32> {
+        let mut result = vec![0; NUM_KEYS];
+
+        let frame = frame.min(self.frames.len() - 1);
+        let (w, h) = (self.width as f64, self.height as f64);
+
+        // image pixels per key unit
+        let (px, py) = match options.fit {
+            Fit::Stretch => (w / KEYBOARD_WIDTH, h / KEYBOARD_HEIGHT),
+
+            Fit::Contain => {
+                let p = (w / KEYBOARD_WIDTH).max(h / KEYBOARD_HEIGHT);
+                (p, p)
+            }
+
+            Fit::Cover => {
+                let p = (w / KEYBOARD_WIDTH).min(h / KEYBOARD_HEIGHT);
+                (p, p)
+            }
+        };
+
+        let scale = if options.scale.abs() < std::f64::EPSILON {
+            1.0
+        } else {
+            options.scale
+        };
+
+        let (sin, cos) = (-options.angle).sin_cos();
+
+        // map a point on the keyboard (in key units) to image coordinates
+        let transform = |u: f64, v: f64| {
+            let du = u - KEYBOARD_WIDTH / 2.0;
+            let dv = v - KEYBOARD_HEIGHT / 2.0;
+
+            let ru = (du * cos - dv * sin) / scale;
+            let rv = (du * sin + dv * cos) / scale;
+
+            (
+                ru * px + w / 2.0 + options.x_offset * w,
+                rv * py + h / 2.0 + options.y_offset * h,
+            )
+        };
+
+        let n = constants::IMAGE_SAMPLES_PER_AXIS;
+
+        for key in layout::get_layout(KeyboardVariant::from_config()) {
+            if key.index >= NUM_KEYS {
+                continue;
+            }
+
+            let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+
+            for sy in 0..n {
+                for sx in 0..n {
+                    let u = key.x + key.width * (sx as f64 + 0.5) / n as f64;
+                    let v = key.y + key.height * (sy as f64 + 0.5) / n as f64;
+
+                    let (x, y) = transform(u, v);
+                    let c = Color::from_u32(self.get_pixel(frame, x, y, options.wrap));
+
+                    // weight by alpha, so transparent pixels don't darken the result
+                    r += c.r * c.a;
+                    g += c.g * c.a;
+                    b += c.b * c.a;
+                    a += c.a;
+                }
+            }
+
+            if a > 0.0 {
+                let samples = (n * n) as f32;
+
+                result[key.index] = Color {
+                    r: r / a,
+                    g: g / a,
+                    b: b / a,
+                    a: a / samples * options.opacity as f32,
+                }
+                .to_u32();
+            }
+        }
+
+        result
+    }
+}
+
+impl rlua::UserData for Image {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("width", |_, image, ()| Ok(image.width));
+        methods.add_method("height", |_, image, ()| Ok(image.height));
+        methods.add_method("frames", |_, image, ()| Ok(image.frames.len()));
+
+        methods.add_method("current_frame", |_, image, ()| {
+            Ok(image.get_current_frame() + 1)
+        });
+
+        methods.add_method_mut("rewind", |_, image, ()| {
+            image.rewind();
+            Ok(())
+        });
+
+        methods.add_method("sample", |_, image, options: Option<rlua::Table>| {
+            let mut result = SampleOptions::default();
+            let mut frame = None;
+
+            if let Some(options) = options {
+                if let Some(fit) = options.get::<_, Option<String>>("fit")? {
+                    result.fit = Fit::from_name(&fit).ok_or_else(|| {
+                        rlua::Error::RuntimeError(format!("Invalid fit mode: '{}'", fit))
+                    })?;
+                }
+
+                result.scale = options.get::<_, Option<f64>>("scale")?.unwrap_or(1.0);
+                result.angle = options.get::<_, Option<f64>>("angle")?.unwrap_or(0.0);
+                result.x_offset = options.get::<_, Option<f64>>("x_offset")?.unwrap_or(0.0);
+                result.y_offset = options.get::<_, Option<f64>>("y_offset")?.unwrap_or(0.0);
+                result.wrap = options.get::<_, Option<bool>>("wrap")?.unwrap_or(false);
+                result.opacity = options.get::<_, Option<f64>>("opacity")?.unwrap_or(1.0);
+
+                // frames are 1-based in Lua
+                frame = options
+                    .get::<_, Option<usize>>("frame")?
+                    .map(|f| f.max(1) - 1);
+            }
+
+            let frame = frame.unwrap_or_else(|| image.get_current_frame());
+
+            Ok(image.sample(frame, &result))
+        });
+    }
+}
+
+#[test]
+fn test_frame_timing() {
+    let frame = |millis| Frame {
+        pixels: vec![0xffffffff],
+        delay: Duration::from_millis(millis),
+    };
+
+    let image = Image {
+        width: 1,
+        height: 1,
+        frames: vec![frame(100), frame(50), frame(200)],
+        start: Instant::now(),
+    };
+
+    assert_eq!(image.get_frame_at(Duration::from_millis(0)), 0);
+    assert_eq!(image.get_frame_at(Duration::from_millis(120)), 1);
+    assert_eq!(image.get_frame_at(Duration::from_millis(349)), 2);
+    assert_eq!(image.get_frame_at(Duration::from_millis(350)), 0);
+}
+
+#[test]
+fn test_check_size() {
+    assert!(check_size(4096, 4096).is_ok());
+    assert!(check_size(0, 16).is_err());
+    assert!(check_size(4097, 16).is_err());
+}
@@ -15,7 +15,7 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use failure::Fail;
 use hotwatch::{
     blocking::{Flow, Hotwatch},
@@ -39,24 +39,41 @@ use std::u64;
 
 mod util;
 
+mod canvas;
+mod canvas2d;
+mod color;
 mod hwdevices;
+mod image;
 use hwdevices::{HidEvent, HwDevice};
 
 mod constants;
 mod dbus_interface;
 mod events;
+mod frame_rate;
+mod night_light;
 mod plugin_manager;
 mod plugins;
+mod postprocessing;
 mod procmon;
 mod profiles;
+mod recording;
+mod render;
 mod scripting;
 mod state;
+mod text;
+mod visualize;
 
+use frame_rate::FrameRateController;
 use plugins::macros;
 use procmon::ProcMon;
 use profiles::Profile;
+use scripting::bus;
+use scripting::debugger;
+use scripting::dependencies;
 use scripting::manifest::Manifest;
+use scripting::native;
 use scripting::script;
+use scripting::stats;
 
 lazy_static! {
     /// The currently active slot (1-4)
@@ -195,6 +212,155 @@ fn parse_commandline<'a>() -> clap::ArgMatches<'a> {
                 .help("Sets the configuration file to use")
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("test-script")
+                .about("Run a script headless and capture or verify its output")
+                .arg(
+                    Arg::with_name("SCRIPT")
+                        .help("The script file or native effect to run")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .short("p")
+                        .long("profile")
+                        .value_name("FILE")
+                        .help("Take script parameters from the specified profile")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("frames")
+                        .short("n")
+                        .long("frames")
+                        .value_name("N")
+                        .help("Number of frames to render")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("key-down")
+                        .long("key-down")
+                        .value_name("FRAME:KEY")
+                        .help("Inject a key down event before the specified frame")
+                        .takes_value(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("key-up")
+                        .long("key-up")
+                        .value_name("FRAME:KEY")
+                        .help("Inject a key up event before the specified frame")
+                        .takes_value(true)
+                        .multiple(true),
+                )
+                .arg(
+                    Arg::with_name("golden")
+                        .short("g")
+                        .long("golden")
+                        .value_name("FILE")
+                        .help("Compare the captured frames to the golden frames in FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("update")
+                        .short("u")
+                        .long("update")
+                        .help("Write the captured frames to the golden frames file instead"),
+                )
+                .arg(
+                    Arg::with_name("tolerance")
+                        .short("t")
+                        .long("tolerance")
+                        .value_name("N")
+                        .help("Allowed deviation of each color component")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("render")
+                .about("Render a profile offline to an animated image, or to a recording")
+                .arg(
+                    Arg::with_name("PROFILE")
+                        .help("The profile file to render, or the profile directory with --all")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .short("a")
+                        .long("all")
+                        .help("Render every profile in the profile directory"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("PATH")
+                        .help("The output file, or the output directory with --all")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("The output format")
+                        .possible_values(&["gif", "apng", "png", "rec"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("layer")
+                        .short("l")
+                        .long("layer")
+                        .value_name("N")
+                        .help("Render only the layer of the Nth script (0-based)")
+                        .conflicts_with("all")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("seconds")
+                        .short("s")
+                        .long("seconds")
+                        .value_name("N")
+                        .help("Number of seconds to render")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("scale")
+                        .long("scale")
+                        .value_name("PIXELS")
+                        .help("Size of a single key, in pixels")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("script-dir")
+                        .long("script-dir")
+                        .value_name("DIR")
+                        .help("Load scripts from the specified directory")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("visualize")
+                .about(
+                    "Show the LED map of the running daemon as a keyboard diagram in the terminal",
+                )
+                .arg(
+                    Arg::with_name("layer")
+                        .short("l")
+                        .long("layer")
+                        .value_name("N")
+                        .help("Show only the layer of the Nth script (0-based)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("frames")
+                        .short("n")
+                        .long("frames")
+                        .value_name("N")
+                        .help("Exit after N frames")
+                        .takes_value(true),
+                ),
+        )
         .get_matches()
 }
 
@@ -373,12 +539,10 @@ fn spawn_lua_thread(
     script_path: PathBuf,
     hwdevice: &HwDevice,
 ) -> Result<()> {
-    let result = util::is_file_accessible(&script_path);
-    if let Err(result) = result {
+    if !util::is_script_file_accessible(&script_path) {
         error!(
-            "Script file '{}' is not accessible: {}",
+            "Script file '{}' is not accessible or not a known native effect",
             script_path.display(),
-            result
         );
 
         return Err(MainError::ScriptExecError {});
@@ -404,10 +568,16 @@ fn spawn_lua_thread(
     ));
     builder
         .spawn(move || -> Result<()> {
+            stats::register_vm();
+
             #[allow(clippy::never_loop)]
             loop {
-                let result = script::run_script(script_path.clone(), &hwdevice.clone(), &lua_rx)
-                    .map_err(|_e| MainError::ScriptExecError {})?;
+                let result = if native::is_native_effect(&script_path) {
+                    native::run_effect(script_path.clone(), &hwdevice.clone(), &lua_rx)
+                } else {
+                    script::run_script(script_path.clone(), &hwdevice.clone(), &lua_rx)
+                }
+                .map_err(|_e| MainError::ScriptExecError {})?;
 
                 match result {
                     //script::RunScriptResult::ReExecuteOtherScript(script_file) => {
@@ -459,6 +629,18 @@ fn switch_profile<P: AsRef<Path>>(
     let profile =
         profiles::Profile::from(&profile_path).map_err(|_e| MainError::SwitchProfileError {})?;
 
+    // validate the parameter values against the manifests of the scripts
+    profile.validate(&script_dir).map_err(|e| {
+        error!("{}", e);
+        MainError::SwitchProfileError {}
+    })?;
+
+    // resolve the library modules that are required by the scripts
+    dependencies::check_profile(&profile, &script_dir).map_err(|e| {
+        error!("{}", e);
+        MainError::SwitchProfileError {}
+    })?;
+
     // verify script files first; better fail early if we can
     let script_files = profile.active_scripts.clone();
     for script_file in script_files.iter() {
@@ -473,8 +655,18 @@ fn switch_profile<P: AsRef<Path>>(
             );
             return Err(MainError::SwitchProfileError {});
         }
+
+        if let Err(e) = Manifest::from(&script_path).and_then(|m| m.check_compatibility()) {
+            error!("{}", e);
+            return Err(MainError::SwitchProfileError {});
+        }
     }
 
+    // the statistics of the old set of scripts are not meaningful anymore, calls
+    // that are still recorded by the old VMs while they shut down are discarded
+    stats::log_summary(Level::Info);
+    stats::reset();
+
     // now request termination of all Lua VMs
     let mut lua_txs = LUA_TXS.lock();
 
@@ -487,6 +679,9 @@ fn switch_profile<P: AsRef<Path>>(
     // be safe and clear any leftover channels
     lua_txs.clear();
 
+    // messages published by the old set of scripts are not meant for the new one
+    bus::clear_pending();
+
     // now spawn a new set of Lua VMs, with scripts from the new profile
     for (thread_idx, script_file) in script_files.iter().enumerate() {
         let script_path = script_dir.join(&script_file);
@@ -500,6 +695,9 @@ fn switch_profile<P: AsRef<Path>>(
         lua_txs.push(lua_tx);
     }
 
+    frame_rate::apply_profile(&profile);
+    postprocessing::apply_profile(&profile);
+
     // finally assign the globally active profile
     *ACTIVE_PROFILE.lock() = Some(profile);
 
@@ -514,6 +712,16 @@ fn switch_profile<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Get the file name of the script that is running on the Lua VM with index `index`
+fn get_script_name(index: usize) -> String {
+    ACTIVE_PROFILE
+        .lock()
+        .as_ref()
+        .and_then(|p| p.active_scripts.get(index).cloned())
+        .map(|f| f.display().to_string())
+        .unwrap_or_else(|| format!("#{}", index))
+}
+
 /// Process system related events
 fn process_system_events(
     sysevents_rx: &Receiver<SystemEvent>,
@@ -591,6 +799,29 @@ fn process_system_events(
     Ok(system_events_pending)
 }
 
+/// Route messages published on the inter-script message bus to all Lua VMs
+fn process_bus_messages(failed_txs: &HashSet<usize>) -> Result<()> {
+    let messages = bus::take_pending();
+
+    if !messages.is_empty() {
+        let lua_txs = LUA_TXS.lock();
+
+        for (topic, payload) in messages {
+            for (idx, lua_tx) in lua_txs.iter().enumerate() {
+                if !failed_txs.contains(&idx) {
+                    lua_tx
+                        .send(script::Message::BusMessage(topic.clone(), payload.clone()))
+                        .unwrap_or_else(|e| {
+                            error!("Could not send a bus message to a Lua VM: {}", e)
+                        });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Process file system related events
 fn process_filesystem_events(
     fsevents_rx: &Receiver<FileSystemEvent>,
@@ -1112,6 +1343,7 @@ fn process_keyboard_events(
     kbd_rx: &Receiver<Option<evdev_rs::InputEvent>>,
     failed_txs: &HashSet<usize>,
     start_time: &Instant,
+    fps_controller: &mut FrameRateController,
     hid_events_pending: bool,
     mouse_events_pending: bool,
     system_events_pending: bool,
@@ -1124,7 +1356,11 @@ fn process_keyboard_events(
     'KEYBOARD_EVENTS_LOOP: loop {
         let mut event_processed = false;
 
-        // sync to MAIN_LOOP_DELAY_MILLIS iteration time
+        // sync to the frame duration of the current frame rate
+        let frame_millis: u64 = frame_rate::get_frame_duration()
+            .as_millis()
+            .try_into()
+            .unwrap();
         let elapsed: u64 = start_time.elapsed().as_millis().try_into().unwrap();
         let sleep_millis = if hid_events_pending
             || mouse_events_pending
@@ -1136,9 +1372,8 @@ fn process_keyboard_events(
             0
         } else {
             u64::min(
-                constants::MAIN_LOOP_DELAY_MILLIS
-                    .saturating_sub(elapsed + constants::MAIN_LOOP_DELAY_OFFSET_MILLIS),
-                constants::MAIN_LOOP_DELAY_MILLIS,
+                frame_millis.saturating_sub(elapsed + constants::MAIN_LOOP_DELAY_OFFSET_MILLIS),
+                frame_millis,
             )
         };
 
@@ -1231,6 +1466,9 @@ fn process_keyboard_events(
                             }
                         }
 
+                        // react to input at the full frame rate
+                        fps_controller.wake();
+
                         // handler for Message::MirrorKey will drop the key if a Lua VM
                         // called inject_key(..), so that the key won't be reported twice
                         macros::UINPUT_TX
@@ -1303,12 +1541,31 @@ fn run_main_loop(
     // stores the generation number of the frame that is currently visible on the keyboard
     let saved_frame_generation = AtomicUsize::new(0);
 
+    // temporal dithering of the final (8 bits) color values
+    let enable_dithering = CONFIG
+        .lock()
+        .as_ref()
+        .unwrap()
+        .get::<bool>("global.enable_dithering")
+        .unwrap_or_else(|_| true);
+
     // used to calculate frames per second
     let mut fps_counter = 0;
     let mut fps_timer = Instant::now();
 
+    // used to periodically log the performance statistics of the scripts
+    let mut stats_timer = Instant::now();
+
     let mut start_time = Instant::now();
 
+    // lowers the frame rate of static profiles in adaptive mode
+    let mut fps_controller = FrameRateController::new();
+
+    // timekeeping of the timer ticks that are sent to the Lua VMs
+    let daemon_start_time = Instant::now();
+    let mut last_tick_time = 0.0;
+    let mut last_tick_ticks = 0;
+
     let mut mouse_move_event_last_dispatched: Instant = Instant::now();
     let mut mouse_motion_buf: (i32, i32, i32) = (0, 0, 0);
 
@@ -1368,6 +1625,9 @@ fn run_main_loop(
         // process events from the system monitoring thread
         let system_events_pending = process_system_events(&sysevents_rx, &failed_txs)?;
 
+        // route messages between the Lua VMs
+        process_bus_messages(&failed_txs)?;
+
         // process events from the file system watcher thread
         process_filesystem_events(&fsevents_rx, &dbus_api_tx)?;
 
@@ -1389,6 +1649,7 @@ fn run_main_loop(
             &kbd_rx,
             &failed_txs,
             &start_time,
+            &mut fps_controller,
             hid_events_pending,
             mouse_events_pending,
             system_events_pending,
@@ -1398,20 +1659,16 @@ fn run_main_loop(
         let current_frame_generation = script::FRAME_GENERATION_COUNTER.load(Ordering::SeqCst);
 
         // instruct the Lua VMs to realize their color maps, but only if at least one VM
-        // submitted a new map (performed a frame generation increment)
-        if saved_frame_generation.load(Ordering::SeqCst) < current_frame_generation {
+        // submitted a new map (performed a frame generation increment). If dithering
+        // is enabled, the canvas gets re-quantized and sent on every iteration
+        let new_frame = saved_frame_generation.load(Ordering::SeqCst) < current_frame_generation;
+
+        if new_frame {
             // execute render "pipeline" now...
             let mut drop_frame = false;
 
             // first, clear the canvas
-            script::LED_MAP.write().copy_from_slice(
-                &[hwdevices::RGBA {
-                    r: 0,
-                    g: 0,
-                    b: 0,
-                    a: 0,
-                }; hwdevices::NUM_KEYS],
-            );
+            *script::LED_MAP.write() = canvas::new_canvas();
 
             // instruct Lua VMs to realize their color maps, e.g. to blend their
             // local color maps with the canvas
@@ -1442,7 +1699,10 @@ fn run_main_loop(
 
                     if result.timed_out() {
                         drop_frame = true;
-                        warn!("Frame dropped: Timeout while waiting for a lock!");
+                        warn!(
+                            "Frame dropped: Timeout while waiting for script '{}' to realize its color map",
+                            get_script_name(index)
+                        );
                         break;
                     }
                 } else {
@@ -1462,35 +1722,93 @@ fn run_main_loop(
                 );
             }
 
-            // send the final (combined) color map to the keyboard
+            // we successfully composited the canvas, so store the current frame
+            // generation as the "currently active" one
             if !drop_frame {
-                if let Some(mut hwdevice) = hwdevice.try_write() {
-                    hwdevice
-                        .send_led_map(&script::LED_MAP.read())
-                        .unwrap_or_else(|e| {
-                            error!("Could not send the LED map to the device: {}", e)
-                        });
-                } else {
-                    error!("Could not get a lock on the hardware device");
-                }
+                saved_frame_generation.store(current_frame_generation, Ordering::SeqCst);
+            } else {
+                frame_rate::record_dropped_frame();
+            }
+        }
 
-                // thread::sleep(Duration::from_millis(
-                //     crate::constants::DEVICE_SETTLE_MILLIS,
-                // ));
+        // messages shown via D-Bus scroll, so they need to be re-rendered on every iteration
+        let message_active = text::is_message_active();
 
-                // we successfully updated the keyboard state, so store the current frame generation as the "currently active" one
-                saved_frame_generation.store(current_frame_generation, Ordering::SeqCst);
+        // the same holds for post-processing filters that are in transition
+        let filters_active = postprocessing::is_animating();
+
+        // and for the night light, while its color temperature changes
+        let night_light_changed = night_light::update();
+
+        fps_controller.update(new_frame || message_active || filters_active || night_light_changed);
+
+        if saved_frame_generation.load(Ordering::SeqCst) == current_frame_generation
+            && (new_frame
+                || enable_dithering
+                || message_active
+                || filters_active
+                || night_light_changed)
+        {
+            // convert the canvas to sRGB, apply brightness and dithering
+            let brightness = BRIGHTNESS.load(Ordering::SeqCst) as f32 / 100.0;
+            // the dither pattern would flicker visibly at the frame rate of static profiles
+            let dither_frame = if enable_dithering && !fps_controller.is_idle() {
+                Some(ticks as usize)
+            } else {
+                None
+            };
+
+            let mut canvas = script::LED_MAP.read().clone();
+
+            // post-processing applies to the layers of the profile, but not to messages
+            postprocessing::apply(&mut canvas);
+
+            // recordings capture the output of the profile, without messages,
+            // the night light or the brightness
+            if new_frame || filters_active {
+                recording::record_frame(&canvas);
+            }
+
+            if message_active {
+                text::blend_message(&mut canvas);
+            }
+
+            // the night light applies to everything, like the brightness
+            night_light::apply(&mut canvas);
+
+            let led_map = canvas::to_led_map(&canvas, brightness, dither_frame);
+            visualize::export_led_map(&led_map);
+
+            // send the final (combined) color map to the keyboard
+            if let Some(mut hwdevice) = hwdevice.try_write() {
+                hwdevice
+                    .send_led_map(&led_map)
+                    .unwrap_or_else(|e| error!("Could not send the LED map to the device: {}", e));
+            } else {
+                error!("Could not get a lock on the hardware device");
             }
         }
 
-        // send timer tick events to the Lua VMs
+        // send timer tick events to the Lua VMs. Ticks carry real time, so that
+        // the speed of animations does not depend on the frame rate
+        let time = daemon_start_time.elapsed().as_secs_f64();
+        let total_ticks = (time * constants::TARGET_FPS as f64) as u64;
+
+        let tick = script::Tick {
+            frame: ticks,
+            delta: time - last_tick_time,
+            time,
+            ticks: (total_ticks - last_tick_ticks) as u32,
+        };
+
+        last_tick_time = time;
+        last_tick_ticks = total_ticks;
+
         for (index, lua_tx) in LUA_TXS.lock().iter().enumerate() {
             // if this tx failed previously, then skip it completely
             if !failed_txs.contains(&index) {
                 lua_tx
-                    .send(script::Message::Tick(
-                        (start_time.elapsed().as_millis() / constants::TARGET_FPS as u128) as u32,
-                    ))
+                    .send(script::Message::Tick(tick))
                     .unwrap_or_else(|e| {
                         error!("Send error for Message::Tick: {}", e);
                         failed_txs.insert(index);
@@ -1498,31 +1816,8 @@ fn run_main_loop(
             }
         }
 
-        let elapsed_after_sleep = start_time.elapsed().as_millis();
-        if elapsed_after_sleep != constants::MAIN_LOOP_DELAY_MILLIS.into() {
-            if elapsed_after_sleep > (constants::MAIN_LOOP_DELAY_MILLIS + 82_u64).into() {
-                warn!("More than 82 milliseconds of jitter detected!");
-                warn!("This means that we dropped at least one frame");
-                warn!(
-                    "Loop took: {} milliseconds, goal: {}",
-                    elapsed_after_sleep,
-                    constants::MAIN_LOOP_DELAY_MILLIS
-                );
-            } /* else if elapsed_after_sleep < 5_u128 {
-                  warn!("Short loop detected, this could lead to flickering LEDs!");
-                  warn!(
-                      "Loop took: {} milliseconds, goal: {}",
-                      elapsed_after_sleep,
-                      constants::MAIN_LOOP_DELAY_MILLIS
-                  );
-              } else {
-                    trace!(
-                        "Loop took: {} milliseconds, goal: {}",
-                        elapsed_after_sleep,
-                        constants::MAIN_LOOP_DELAY_MILLIS
-                    );
-                } */
-        }
+        // frame times, jitter and dropped frames are available via D-Bus
+        frame_rate::record_frame(start_time.elapsed());
 
         // calculate and log fps each second
         if fps_timer.elapsed().as_millis() >= 1000 {
@@ -1532,6 +1827,13 @@ fn run_main_loop(
             fps_counter = 0;
         }
 
+        if stats_timer.elapsed().as_secs() >= constants::STATS_LOG_INTERVAL_SECS {
+            stats::log_summary(Level::Debug);
+            frame_rate::log_summary(Level::Debug);
+
+            stats_timer = Instant::now();
+        }
+
         // shall we quit the main loop?
         if QUIT.load(Ordering::SeqCst) {
             break 'MAIN_LOOP;
@@ -1745,11 +2047,28 @@ pub async fn main() -> std::result::Result<(), failure::Error> {
 
     *CONFIG.lock() = Some(config.clone());
 
+    // run a script headless, without touching the hardware
+    if let Some(matches) = matches.subcommand_matches("test-script") {
+        process::exit(scripting::harness::run_cli(matches));
+    }
+
+    // render a profile offline, without touching the hardware
+    if let Some(matches) = matches.subcommand_matches("render") {
+        process::exit(render::run_cli(matches));
+    }
+
+    // show the LED map of the running daemon in the terminal
+    if let Some(matches) = matches.subcommand_matches("visualize") {
+        process::exit(visualize::run_cli(matches));
+    }
+
     // load and initialize global runtime state
     debug!("Loading saved state...");
     state::init_global_runtime_state()
         .unwrap_or_else(|e| warn!("Could not parse state file: {}", e));
 
+    night_light::init();
+
     // default directories
     let profile_dir = config
         .get_str("global.profile_dir")
@@ -1760,6 +2079,15 @@ pub async fn main() -> std::result::Result<(), failure::Error> {
         .get_str("global.script_dir")
         .unwrap_or_else(|_| constants::DEFAULT_SCRIPT_DIR.to_string());
 
+    // interactive debugger for the Lua VMs
+    let enable_debugger = config
+        .get::<bool>("global.enable_debugger")
+        .unwrap_or_else(|_| false);
+
+    let debugger_socket = config
+        .get_str("global.debugger_socket")
+        .unwrap_or_else(|_| constants::DEFAULT_DEBUGGER_SOCKET.to_string());
+
     // grab the mouse exclusively
     let grab_mouse = config
         .get::<bool>("global.grab_mouse")
@@ -1814,6 +2142,13 @@ pub async fn main() -> std::result::Result<(), failure::Error> {
                     plugins::register_plugins()
                         .unwrap_or_else(|_e| error!("Could not register one or more plugins"));
 
+                    // the debugger is disabled by default
+                    if enable_debugger {
+                        info!("Creating the debugger socket...");
+                        debugger::spawn_debugger_thread(Path::new(&debugger_socket))
+                            .unwrap_or_else(|e| error!("Could not enable the debugger: {}", e));
+                    }
+
                     // spawn a thread that monitors the system
                     info!("Spawning system monitor thread...");
                     let (sysevents_tx, sysevents_rx) = channel();
@@ -1897,6 +2232,8 @@ pub async fn main() -> std::result::Result<(), failure::Error> {
                         }
                     }
 
+                    stats::log_summary(Level::Info);
+
                     // store plugin state to disk
                     plugins::PersistencePlugin::store_persistent_data().map_err(|e| {
                         MainError::StorageError {
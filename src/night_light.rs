@@ -0,0 +1,545 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::canvas::{self, Canvas};
+use crate::constants;
+use crate::util::{self, LocalTime};
+
+pub type Result<T> = std::result::Result<T, NightLightError>;
+
+#[derive(Debug, Fail)]
+pub enum NightLightError {
+    #[fail(display = "Invalid night light mode: {}", mode)]
+    InvalidMode { mode: String },
+}
+
+lazy_static! {
+    /// Night light settings of the installation
+    static ref SETTINGS: Mutex<NightLightSettings> = Mutex::new(NightLightSettings::default());
+
+    /// Current state of the night light
+    static ref STATE: Mutex<NightLightState> = Mutex::new(NightLightState::default());
+}
+
+const MINUTES_PER_DAY: f64 = 24.0 * 60.0;
+
+/// Override of the schedule
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// Follow the schedule
+    Auto,
+
+    /// Night light is on, regardless of the schedule
+    On,
+
+    /// Night light is off, regardless of the schedule
+    Off,
+}
+
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Auto => "auto",
+            Mode::On => "on",
+            Mode::Off => "off",
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = NightLightError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Mode::Auto),
+            "on" => Ok(Mode::On),
+            "off" => Ok(Mode::Off),
+
+            _ => Err(NightLightError::InvalidMode {
+                mode: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// When the night light is on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Schedule {
+    /// Fixed times of the day, in minutes after midnight
+    Fixed { start: f64, end: f64 },
+
+    /// From sunset to sunrise, at the given location in degrees
+    /// (north and east are positive)
+    Solar { latitude: f64, longitude: f64 },
+}
+
+/// Night light settings
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NightLightSettings {
+    pub enabled: bool,
+
+    /// Color temperature at night, in Kelvin
+    pub temperature: f64,
+
+    pub schedule: Schedule,
+
+    /// Duration of the transition at the start and at the end of the night,
+    /// in minutes
+    pub transition: f64,
+}
+
+impl Default for NightLightSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            temperature: constants::NIGHT_LIGHT_TEMPERATURE,
+            schedule: Schedule::Fixed {
+                start: 21.0 * 60.0,
+                end: 7.0 * 60.0,
+            },
+            transition: constants::NIGHT_LIGHT_TRANSITION_MINUTES,
+        }
+    }
+}
+
+impl NightLightSettings {
+    /// Get the settings from the `[night_light]` section of the configuration file
+    pub fn from_config() -> Self {
+        let defaults = Self::default();
+
+        let config = crate::CONFIG.lock();
+        let config = match config.as_ref() {
+            Some(config) => config,
+            None => return defaults,
+        };
+
+        let enabled = config
+            .get_bool("night_light.enabled")
+            .unwrap_or(defaults.enabled);
+
+        let temperature = config
+            .get_float("night_light.temperature")
+            .unwrap_or(defaults.temperature)
+            .max(constants::MIN_COLOR_TEMPERATURE)
+            .min(constants::NEUTRAL_COLOR_TEMPERATURE);
+
+        let transition = config
+            .get_float("night_light.transition")
+            .unwrap_or(defaults.transition)
+            .max(0.0);
+
+        let location = (
+            config.get_float("night_light.latitude"),
+            config.get_float("night_light.longitude"),
+        );
+
+        let schedule = if let (Ok(latitude), Ok(longitude)) = location {
+            Schedule::Solar {
+                latitude: latitude.max(-90.0).min(90.0),
+                longitude: longitude.max(-180.0).min(180.0),
+            }
+        } else {
+            let (default_start, default_end) = match defaults.schedule {
+                Schedule::Fixed { start, end } => (start, end),
+                _ => unreachable!(),
+            };
+
+            let get_time = |key: &str, default: f64| {
+                config
+                    .get_str(key)
+                    .ok()
+                    .map(|s| {
+                        parse_time(&s).unwrap_or_else(|| {
+                            warn!("Invalid time of day for '{}': {}", key, s);
+                            default
+                        })
+                    })
+                    .unwrap_or(default)
+            };
+
+            Schedule::Fixed {
+                start: get_time("night_light.start", default_start),
+                end: get_time("night_light.end", default_end),
+            }
+        };
+
+        Self {
+            enabled,
+            temperature,
+            schedule,
+            transition,
+        }
+    }
+}
+
+/// Parse a time of the day in the format "HH:MM", returns minutes after midnight
+fn parse_time(s: &str) -> Option<f64> {
+    let mut parts = s.trim().splitn(2, ':');
+
+    let hours: u32 = parts.next()?.parse().ok()?;
+    let minutes: u32 = parts.next()?.parse().ok()?;
+
+    if hours < 24 && minutes < 60 {
+        Some((hours * 60 + minutes) as f64)
+    } else {
+        None
+    }
+}
+
+/// Times of sunrise and sunset of a day
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SunTimes {
+    /// Sunrise and sunset, in minutes after local midnight
+    Normal { sunrise: f64, sunset: f64 },
+
+    /// The sun does not set
+    PolarDay,
+
+    /// The sun does not rise
+    PolarNight,
+}
+
+/// Calculate the times of sunrise and sunset, using the approximation of the
+/// NOAA. `utc_offset` is the offset of the time zone, in minutes east of UTC
+fn get_sun_times(year_day: u32, latitude: f64, longitude: f64, utc_offset: f64) -> SunTimes {
+    // fractional year at noon, in radians
+    let gamma = 2.0 * PI / 365.0 * (year_day as f64 - 0.5);
+
+    // equation of time, in minutes
+    let eqtime = 229.18
+        * (0.000_075 + 0.001_868 * gamma.cos()
+            - 0.032_077 * gamma.sin()
+            - 0.014_615 * (2.0 * gamma).cos()
+            - 0.040_849 * (2.0 * gamma).sin());
+
+    // declination of the sun, in radians
+    let decl = 0.006_918 - 0.399_912 * gamma.cos() + 0.070_257 * gamma.sin()
+        - 0.006_758 * (2.0 * gamma).cos()
+        + 0.000_907 * (2.0 * gamma).sin()
+        - 0.002_697 * (3.0 * gamma).cos()
+        + 0.001_48 * (3.0 * gamma).sin();
+
+    // the zenith at sunrise and sunset accounts for refraction and the size
+    // of the solar disk
+    let zenith = 90.833_f64.to_radians();
+    let lat = latitude.to_radians();
+
+    let cos_ha = zenith.cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+
+    if cos_ha > 1.0 {
+        SunTimes::PolarNight
+    } else if cos_ha < -1.0 {
+        SunTimes::PolarDay
+    } else {
+        let ha = cos_ha.acos().to_degrees();
+
+        let sunrise = 720.0 - 4.0 * (longitude + ha) - eqtime + utc_offset;
+        let sunset = 720.0 - 4.0 * (longitude - ha) - eqtime + utc_offset;
+
+        SunTimes::Normal {
+            sunrise: sunrise.rem_euclid(MINUTES_PER_DAY),
+            sunset: sunset.rem_euclid(MINUTES_PER_DAY),
+        }
+    }
+}
+
+/// How far the night has progressed at `now`, from 0.0 (day) to 1.0 (night).
+/// The night light fades in during `transition` minutes after the start of the
+/// night, and fades out during `transition` minutes after its end
+fn get_night_factor(settings: &NightLightSettings, now: &LocalTime) -> f64 {
+    let minute = now.hour as f64 * 60.0 + now.minute as f64 + now.second as f64 / 60.0;
+
+    let (start, end) = match settings.schedule {
+        Schedule::Fixed { start, end } => (start, end),
+
+        Schedule::Solar {
+            latitude,
+            longitude,
+        } => match get_sun_times(
+            now.year_day,
+            latitude,
+            longitude,
+            now.utc_offset as f64 / 60.0,
+        ) {
+            SunTimes::Normal { sunrise, sunset } => (sunset, sunrise),
+            SunTimes::PolarDay => return 0.0,
+            SunTimes::PolarNight => return 1.0,
+        },
+    };
+
+    let night_length = (end - start).rem_euclid(MINUTES_PER_DAY);
+    let since_start = (minute - start).rem_euclid(MINUTES_PER_DAY);
+    let since_end = (minute - end).rem_euclid(MINUTES_PER_DAY);
+
+    if since_start < night_length {
+        if settings.transition > 0.0 {
+            (since_start / settings.transition).min(1.0)
+        } else {
+            1.0
+        }
+    } else if settings.transition > 0.0 {
+        (1.0 - since_end / settings.transition).max(0.0)
+    } else {
+        0.0
+    }
+}
+
+/// Get the factors for the red, green and blue channels (in linear light) that
+/// shift white to the color of a black body of the given temperature
+fn get_white_point(temperature: f64) -> [f32; 3] {
+    // approximation by Tanner Helland, yields sRGB values in the range [0..255]
+    let t = temperature / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_727_446 * (t - 60.0).powf(-0.133_204_759_2)
+    };
+
+    let g = if t <= 66.0 {
+        99.470_802_586_1 * t.ln() - 161.119_568_166_1
+    } else {
+        288.122_169_528_3 * (t - 60.0).powf(-0.075_514_849_2)
+    };
+
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_731_223_1 * (t - 10.0).ln() - 305.044_792_730_7
+    };
+
+    let to_linear = |v: f64| canvas::srgb_to_linear((v.max(0.0).min(255.0) / 255.0) as f32);
+
+    [to_linear(r), to_linear(g), to_linear(b)]
+}
+
+/// Current state of the night light
+struct NightLightState {
+    mode: Mode,
+
+    /// Factor of the schedule, from 0.0 (day) to 1.0 (night)
+    target: f64,
+
+    /// Factor that is currently applied, follows `target` with a delay
+    current: f64,
+
+    /// Color temperature that is currently applied, in Kelvin
+    temperature: u64,
+
+    white_point: [f32; 3],
+
+    last_update: Instant,
+    last_schedule_check: Option<Instant>,
+}
+
+impl Default for NightLightState {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Auto,
+            target: 0.0,
+            current: 0.0,
+            temperature: constants::NEUTRAL_COLOR_TEMPERATURE as u64,
+            white_point: [1.0, 1.0, 1.0],
+            last_update: Instant::now(),
+            last_schedule_check: None,
+        }
+    }
+}
+
+/// Load the settings from the configuration file
+pub fn init() {
+    let settings = NightLightSettings::from_config();
+
+    if settings.enabled {
+        match settings.schedule {
+            Schedule::Fixed { start, end } => info!(
+                "Night light: {} K from {:02}:{:02} to {:02}:{:02}",
+                settings.temperature,
+                start as u32 / 60,
+                start as u32 % 60,
+                end as u32 / 60,
+                end as u32 % 60
+            ),
+
+            Schedule::Solar {
+                latitude,
+                longitude,
+            } => info!(
+                "Night light: {} K from sunset to sunrise at {:.2}, {:.2}",
+                settings.temperature, latitude, longitude
+            ),
+        }
+    }
+
+    *SETTINGS.lock() = settings;
+
+    // re-evaluate the schedule on the next update
+    STATE.lock().last_schedule_check = None;
+}
+
+/// Get the override of the schedule
+pub fn get_mode() -> Mode {
+    STATE.lock().mode
+}
+
+/// Override the schedule, the color temperature changes smoothly
+pub fn set_mode(mode: Mode) {
+    info!("Night light mode: {}", mode.as_str());
+
+    let mut state = STATE.lock();
+
+    state.mode = mode;
+    state.last_schedule_check = None;
+}
+
+/// Get the color temperature that is currently applied, in Kelvin
+pub fn get_temperature() -> u64 {
+    STATE.lock().temperature
+}
+
+/// Called once per iteration of the main loop. Returns true if the color
+/// temperature has changed, and the canvas needs to be re-rendered
+pub fn update() -> bool {
+    let settings = *SETTINGS.lock();
+    let mut state = STATE.lock();
+
+    let check_due = state.last_schedule_check.map_or(true, |t| {
+        t.elapsed() >= Duration::from_millis(constants::NIGHT_LIGHT_CHECK_INTERVAL_MILLIS)
+    });
+
+    if check_due {
+        state.last_schedule_check = Some(Instant::now());
+
+        state.target = match state.mode {
+            Mode::On => 1.0,
+            Mode::Off => 0.0,
+
+            Mode::Auto if settings.enabled => match util::get_local_time() {
+                Ok(now) => get_night_factor(&settings, &now),
+
+                Err(e) => {
+                    error!("Could not get the local time: {}", e);
+                    state.target
+                }
+            },
+
+            Mode::Auto => 0.0,
+        };
+    }
+
+    // follow the target smoothly, e.g. after the mode has been overridden
+    let delta = state.last_update.elapsed().as_secs_f64();
+    state.last_update = Instant::now();
+
+    let step = delta / constants::NIGHT_LIGHT_FADE_SECS;
+    state.current = if state.current < state.target {
+        (state.current + step).min(state.target)
+    } else {
+        (state.current - step).max(state.target)
+    };
+
+    // interpolate in mired, that is perceptually more uniform than Kelvin
+    let neutral = 1_000_000.0 / constants::NEUTRAL_COLOR_TEMPERATURE;
+    let night = 1_000_000.0 / settings.temperature;
+    let temperature = (1_000_000.0 / (neutral + (night - neutral) * state.current)).round();
+
+    if temperature as u64 != state.temperature {
+        state.temperature = temperature as u64;
+        state.white_point = get_white_point(temperature);
+
+        true
+    } else {
+        false
+    }
+}
+
+/// Shift the colors of the canvas to the current color temperature
+pub fn apply(canvas: &mut Canvas) {
+    let state = STATE.lock();
+
+    if state.temperature >= constants::NEUTRAL_COLOR_TEMPERATURE as u64 {
+        return;
+    }
+
+    let [r, g, b] = state.white_point;
+
+    for color in canvas.iter_mut() {
+        color.r *= r;
+        color.g *= g;
+        color.b *= b;
+    }
+}
+
+#[test]
+fn test_sun_times() {
+    // Berlin, at the summer solstice (CEST)
+    match get_sun_times(172, 52.52, 13.405, 120.0) {
+        SunTimes::Normal { sunrise, sunset } => {
+            assert!((sunrise - (4.0 * 60.0 + 43.0)).abs() < 5.0);
+            assert!((sunset - (21.0 * 60.0 + 33.0)).abs() < 5.0);
+        }
+
+        _ => panic!("Expected sunrise and sunset"),
+    }
+
+    assert_eq!(get_sun_times(172, 80.0, 0.0, 0.0), SunTimes::PolarDay);
+    assert_eq!(get_sun_times(355, 80.0, 0.0, 0.0), SunTimes::PolarNight);
+}
+
+#[test]
+fn test_night_factor() {
+    let settings = NightLightSettings {
+        enabled: true,
+        transition: 30.0,
+        ..NightLightSettings::default()
+    };
+
+    let at = |hour, minute| LocalTime {
+        year: 2020,
+        month: 1,
+        day: 1,
+        weekday: 3,
+        year_day: 1,
+        utc_offset: 0,
+        hour,
+        minute,
+        second: 0,
+        millisecond: 0,
+    };
+
+    assert_eq!(get_night_factor(&settings, &at(12, 0)), 0.0);
+    assert_eq!(get_night_factor(&settings, &at(21, 15)), 0.5);
+    assert_eq!(get_night_factor(&settings, &at(2, 0)), 1.0);
+    assert_eq!(get_night_factor(&settings, &at(7, 15)), 0.5);
+    assert_eq!(get_night_factor(&settings, &at(7, 30)), 0.0);
+
+    let white = get_white_point(constants::NEUTRAL_COLOR_TEMPERATURE);
+    assert!(white.iter().all(|v| (v - 1.0).abs() < 0.01));
+
+    let [r, g, b] = get_white_point(constants::NIGHT_LIGHT_TEMPERATURE);
+    assert!(r > g && g > b);
+}
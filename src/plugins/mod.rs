@@ -74,3 +74,24 @@ pub fn register_plugins() -> Result<()> {
 
     Ok(())
 }
+
+/// Register all plugins that do not require access to input devices or
+/// to `/dev/uinput`, e.g. for running scripts headless
+pub fn register_headless_plugins() -> Result<()> {
+    trace!("Registering headless plugins...");
+
+    let mut plugin_manager = plugin_manager::PLUGIN_MANAGER.write();
+
+    plugin_manager.register_plugin(Box::new(KeyboardPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(MousePlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(IntrospectionPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(PersistencePlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(ProfilesPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(SystemPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(SensorsPlugin::new()))?;
+    plugin_manager.register_plugin(Box::new(AudioPlugin::new()))?;
+
+    trace!("Done registering headless plugins");
+
+    Ok(())
+}
@@ -0,0 +1,456 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::canvas::{Canvas, Color};
+use crate::color;
+use crate::hwdevices::layout::{self, KeyboardVariant};
+use crate::hwdevices::NUM_KEYS;
+use crate::profiles::Profile;
+
+lazy_static! {
+    /// The filters of the active profile
+    static ref PIPELINE: Mutex<Pipeline> = Mutex::new(Pipeline::new(&[]));
+}
+
+fn default_radius() -> f64 {
+    1.0
+}
+
+fn default_amount() -> f64 {
+    0.5
+}
+
+fn default_threshold() -> f64 {
+    0.5
+}
+
+fn default_factor() -> f64 {
+    1.0
+}
+
+fn default_time() -> f64 {
+    0.1
+}
+
+/// A post-processing filter, as declared in a profile
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Filter {
+    /// Mix the color of each key with the colors of its neighbors
+    Blur {
+        /// Radius of the neighborhood, in key units
+        #[serde(default = "default_radius")]
+        radius: f64,
+
+        /// Mix between the original (0.0) and the blurred (1.0) canvas
+        #[serde(default = "default_amount")]
+        amount: f64,
+    },
+
+    /// Let bright keys glow onto their neighbors
+    Bloom {
+        /// Keys brighter than this (relative luminance) start to glow
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+
+        /// Radius of the glow, in key units
+        #[serde(default = "default_radius")]
+        radius: f64,
+
+        /// Strength of the glow
+        #[serde(default = "default_amount")]
+        intensity: f64,
+    },
+
+    /// Color grading, performed in the OKLab color space
+    Grade {
+        /// Factor for the chroma of colors, 0.0 is grayscale
+        #[serde(default = "default_factor")]
+        saturation: f64,
+
+        /// Rotation of the hue, in degrees
+        #[serde(default)]
+        hue_shift: f64,
+
+        /// Factor for the distance of the lightness of colors from medium gray
+        #[serde(default = "default_factor")]
+        contrast: f64,
+    },
+
+    /// Temporal smoothing, colors follow changes of the canvas with a delay
+    Smooth {
+        /// Time constant, in seconds. After this time, a change of color
+        /// has been followed by about 63%
+        #[serde(default = "default_time")]
+        time: f64,
+    },
+}
+
+/// Weighted neighbors of each key, indexed by hardware key index
+type Kernel = Vec<Vec<(usize, f32)>>;
+
+/// Build a blur kernel with a Gaussian falloff, neighbors farther away than
+/// `radius` (in key units) are not considered
+fn build_kernel(radius: f64) -> Kernel {
+    let keys = layout::get_layout(KeyboardVariant::from_config());
+    let sigma = (radius / 2.0).max(0.01);
+
+    let mut result = vec![vec![]; NUM_KEYS];
+
+    for key in keys.iter().filter(|k| k.index < NUM_KEYS) {
+        let (x, y) = key.center();
+
+        let mut weights: Vec<(usize, f32)> = keys
+            .iter()
+            .filter(|k| k.index < NUM_KEYS)
+            .filter_map(|k| {
+                let (kx, ky) = k.center();
+                let d2 = (kx - x).powi(2) + (ky - y).powi(2);
+
+                if d2 <= radius * radius || k.index == key.index {
+                    Some((k.index, (-d2 / (2.0 * sigma * sigma)).exp() as f32))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let total: f32 = weights.iter().map(|w| w.1).sum();
+
+        for w in weights.iter_mut() {
+            w.1 /= total;
+        }
+
+        result[key.index] = weights;
+    }
+
+    result
+}
+
+/// Convolve the color channels of `canvas` with `kernel`. Keys without a
+/// position on the keyboard keep their color
+fn convolve(canvas: &[Color], kernel: &Kernel) -> Canvas {
+    canvas
+        .iter()
+        .zip(kernel.iter())
+        .map(|(c, weights)| {
+            if weights.is_empty() {
+                *c
+            } else {
+                let mut result = Color {
+                    a: c.a,
+                    ..Color::default()
+                };
+
+                for (index, weight) in weights.iter() {
+                    let n = canvas[*index];
+
+                    result.r += n.r * weight;
+                    result.g += n.g * weight;
+                    result.b += n.b * weight;
+                }
+
+                result
+            }
+        })
+        .collect()
+}
+
+fn get_luminance(c: &Color) -> f32 {
+    0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+}
+
+/// Apply color grading to a single color
+fn grade(c: &Color, saturation: f64, hue_shift: f64, contrast: f64) -> Color {
+    let (l, a, b) = color::linear_to_oklab(
+        f64::from(c.r.max(0.0)),
+        f64::from(c.g.max(0.0)),
+        f64::from(c.b.max(0.0)),
+    );
+
+    let (sin, cos) = hue_shift.to_radians().sin_cos();
+
+    let l = ((l - 0.5) * contrast + 0.5).max(0.0);
+    let (a, b) = (
+        (a * cos - b * sin) * saturation,
+        (a * sin + b * cos) * saturation,
+    );
+
+    let (r, g, b) = color::oklab_to_linear(l, a, b);
+
+    Color {
+        r: r.max(0.0).min(1.0) as f32,
+        g: g.max(0.0).min(1.0) as f32,
+        b: b.max(0.0).min(1.0) as f32,
+        a: c.a,
+    }
+}
+
+/// A filter, with the state it needs between frames
+enum Stage {
+    Blur {
+        kernel: Kernel,
+        amount: f32,
+    },
+    Bloom {
+        kernel: Kernel,
+        threshold: f32,
+        intensity: f32,
+    },
+    Grade {
+        saturation: f64,
+        hue_shift: f64,
+        contrast: f64,
+    },
+    Smooth {
+        time: f64,
+        previous: Option<Canvas>,
+    },
+}
+
+impl Stage {
+    fn new(filter: &Filter) -> Self {
+        match *filter {
+            Filter::Blur { radius, amount } => Stage::Blur {
+                kernel: build_kernel(radius),
+                amount: amount.max(0.0).min(1.0) as f32,
+            },
+
+            Filter::Bloom {
+                threshold,
+                radius,
+                intensity,
+            } => Stage::Bloom {
+                kernel: build_kernel(radius),
+                threshold: threshold.max(0.0).min(0.99) as f32,
+                intensity: intensity.max(0.0) as f32,
+            },
+
+            Filter::Grade {
+                saturation,
+                hue_shift,
+                contrast,
+            } => Stage::Grade {
+                saturation: saturation.max(0.0),
+                hue_shift,
+                contrast: contrast.max(0.0),
+            },
+
+            Filter::Smooth { time } => Stage::Smooth {
+                time: time.max(0.0),
+                previous: None,
+            },
+        }
+    }
+
+    /// Apply the filter to `canvas`, `delta` is the time since the previous
+    /// frame in seconds. Returns true if the filter did not converge yet, and
+    /// needs to be applied again even if the canvas does not change
+    fn apply(&mut self, canvas: &mut Canvas, delta: f64) -> bool {
+        match self {
+            Stage::Blur { kernel, amount } => {
+                let blurred = convolve(canvas, kernel);
+
+                for (c, b) in canvas.iter_mut().zip(blurred.iter()) {
+                    c.r += (b.r - c.r) * *amount;
+                    c.g += (b.g - c.g) * *amount;
+                    c.b += (b.b - c.b) * *amount;
+                }
+
+                false
+            }
+
+            Stage::Bloom {
+                kernel,
+                threshold,
+                intensity,
+            } => {
+                // the part of each color that exceeds the threshold
+                let bright: Canvas = canvas
+                    .iter()
+                    .map(|c| {
+                        let k = ((get_luminance(c) - *threshold) / (1.0 - *threshold)).max(0.0);
+
+                        Color {
+                            r: c.r * k,
+                            g: c.g * k,
+                            b: c.b * k,
+                            a: c.a,
+                        }
+                    })
+                    .collect();
+
+                let glow = convolve(&bright, kernel);
+
+                for (c, g) in canvas.iter_mut().zip(glow.iter()) {
+                    c.r = (c.r + g.r * *intensity).min(1.0);
+                    c.g = (c.g + g.g * *intensity).min(1.0);
+                    c.b = (c.b + g.b * *intensity).min(1.0);
+                }
+
+                false
+            }
+
+            Stage::Grade {
+                saturation,
+                hue_shift,
+                contrast,
+            } => {
+                for c in canvas.iter_mut() {
+                    *c = grade(c, *saturation, *hue_shift, *contrast);
+                }
+
+                false
+            }
+
+            Stage::Smooth { time, previous } => {
+                let mut converged = true;
+
+                if let Some(previous) = previous.as_mut() {
+                    let p = if *time > 0.0 {
+                        (1.0 - (-delta / *time).exp()) as f32
+                    } else {
+                        1.0
+                    };
+
+                    for (c, prev) in canvas.iter_mut().zip(previous.iter_mut()) {
+                        prev.r += (c.r - prev.r) * p;
+                        prev.g += (c.g - prev.g) * p;
+                        prev.b += (c.b - prev.b) * p;
+                        prev.a = c.a;
+
+                        let diff = (c.r - prev.r)
+                            .abs()
+                            .max((c.g - prev.g).abs())
+                            .max((c.b - prev.b).abs());
+
+                        // less than half an 8 bit step, in the dark range
+                        if diff > 0.5 / 255.0 / 12.92 {
+                            converged = false;
+                        }
+
+                        *c = *prev;
+                    }
+                } else {
+                    previous.replace(canvas.clone());
+                }
+
+                !converged
+            }
+        }
+    }
+}
+
+/// The filters of a profile, applied in the order they are declared in its
+/// `[[filters]]` sections
+pub struct Pipeline {
+    stages: Vec<Stage>,
+    last_update: Instant,
+    is_animating: bool,
+}
+
+impl Pipeline {
+    pub fn new(filters: &[Filter]) -> Self {
+        Self {
+            stages: filters.iter().map(Stage::new).collect(),
+            last_update: Instant::now(),
+            is_animating: false,
+        }
+    }
+
+    /// Apply all filters to `canvas`
+    pub fn apply(&mut self, canvas: &mut Canvas) {
+        let delta = self.last_update.elapsed().as_secs_f64();
+        self.last_update = Instant::now();
+
+        let mut is_animating = false;
+
+        for stage in self.stages.iter_mut() {
+            is_animating |= stage.apply(canvas, delta);
+        }
+
+        self.is_animating = is_animating;
+    }
+}
+
+/// Use the filters of `profile`
+pub fn apply_profile(profile: &Profile) {
+    if !profile.filters.is_empty() {
+        info!("Post-processing filters: {}", profile.filters.len());
+    }
+
+    *PIPELINE.lock() = Pipeline::new(&profile.filters);
+}
+
+/// Apply the filters of the active profile to `canvas`
+pub fn apply(canvas: &mut Canvas) {
+    let mut pipeline = PIPELINE.lock();
+
+    if !pipeline.stages.is_empty() {
+        pipeline.apply(canvas);
+    }
+}
+
+/// Returns true if a filter, e.g. temporal smoothing, is still in transition.
+/// In that case the canvas needs to be processed and sent again, even if no
+/// script submitted a new color map
+pub fn is_animating() -> bool {
+    PIPELINE.lock().is_animating
+}
+
+#[test]
+fn test_blur() {
+    let kernel = build_kernel(1.5);
+
+    let uniform = vec![
+        Color {
+            r: 0.5,
+            g: 0.25,
+            b: 1.0,
+            a: 1.0
+        };
+        NUM_KEYS
+    ];
+
+    // blurring a uniform canvas must not change it
+    for (c, u) in convolve(&uniform, &kernel).iter().zip(uniform.iter()) {
+        assert!((c.r - u.r).abs() < 1e-5 && (c.g - u.g).abs() < 1e-5 && (c.b - u.b).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn test_grade() {
+    let c = Color {
+        r: 0.8,
+        g: 0.2,
+        b: 0.1,
+        a: 1.0,
+    };
+
+    let identity = grade(&c, 1.0, 0.0, 1.0);
+    assert!((identity.r - c.r).abs() < 1e-4 && (identity.g - c.g).abs() < 1e-4);
+
+    // no saturation yields a shade of gray
+    let gray = grade(&c, 0.0, 0.0, 1.0);
+    assert!((gray.r - gray.g).abs() < 1e-3 && (gray.g - gray.b).abs() < 1e-3);
+}
@@ -16,6 +16,8 @@
 */
 
 use crate::constants;
+use crate::postprocessing::Filter;
+use crate::scripting::manifest::{self, Manifest, ManifestError};
 use failure::Fail;
 use log::*;
 use serde::{Deserialize, Serialize};
@@ -43,6 +45,23 @@ pub enum ProfileError {
 
     #[fail(display = "Could not set a config value in a profile: {}", msg)]
     SetValueError { msg: String },
+
+    #[fail(
+        display = "Could not load the manifest of script '{}' used by profile '{}'",
+        script, profile
+    )]
+    ManifestError { profile: String, script: String },
+
+    #[fail(
+        display = "Invalid parameter '{}' of script '{}' in profile '{}': {}",
+        param, script, profile, msg
+    )]
+    InvalidParameter {
+        profile: String,
+        script: String,
+        param: String,
+        msg: String,
+    },
     // #[fail(display = "Unknown error: {}", description)]
     // UnknownError { description: String },
 }
@@ -50,11 +69,36 @@ pub enum ProfileError {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum ConfigParam {
-    Int { name: String, value: i64 },
-    Float { name: String, value: f64 },
-    Bool { name: String, value: bool },
-    String { name: String, value: String },
-    Color { name: String, value: u32 },
+    Int {
+        name: String,
+        value: i64,
+    },
+    Float {
+        name: String,
+        value: f64,
+    },
+    Bool {
+        name: String,
+        value: bool,
+    },
+    String {
+        name: String,
+        value: String,
+    },
+    Color {
+        name: String,
+        value: u32,
+    },
+    #[serde(rename = "color_list")]
+    ColorList {
+        name: String,
+        value: Vec<u32>,
+    },
+    #[serde(rename = "key_set")]
+    KeySet {
+        name: String,
+        value: Vec<u8>,
+    },
 }
 
 pub trait GetAttr {
@@ -74,6 +118,10 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref name, .. } => name,
 
             ConfigParam::Color { ref name, .. } => name,
+
+            ConfigParam::ColorList { ref name, .. } => name,
+
+            ConfigParam::KeySet { ref name, .. } => name,
         }
     }
 
@@ -88,6 +136,18 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref value, .. } => value.to_owned(),
 
             ConfigParam::Color { ref value, .. } => format!("#{:06x}", value),
+
+            ConfigParam::ColorList { ref value, .. } => value
+                .iter()
+                .map(|c| format!("#{:06x}", c))
+                .collect::<Vec<String>>()
+                .join(", "),
+
+            ConfigParam::KeySet { ref value, .. } => value
+                .iter()
+                .map(|k| format!("{}", k))
+                .collect::<Vec<String>>()
+                .join(", "),
         }
     }
 }
@@ -119,6 +179,22 @@ pub struct Profile {
     #[serde(default = "default_script_file")]
     pub active_scripts: Vec<PathBuf>,
 
+    /// Overrides the `global.target_fps` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_fps: Option<u64>,
+
+    /// Overrides the `global.adaptive_fps` setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive_fps: Option<bool>,
+
+    /// Overrides the imports of scripts, keyed by script name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub imports: HashMap<String, Vec<String>>,
+
+    /// Post-processing filters, applied to the composited canvas
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<Filter>,
+
     pub config: Option<HashMap<String, Vec<ConfigParam>>>,
 }
 
@@ -160,6 +236,18 @@ impl FindConfig for Vec<ConfigParam> {
                         return Some(p);
                     }
                 }
+
+                ConfigParam::ColorList { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
+
+                ConfigParam::KeySet { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
             }
         }
 
@@ -198,6 +286,18 @@ impl FindConfig for Vec<ConfigParam> {
                         return Some(p);
                     }
                 }
+
+                ConfigParam::ColorList { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
+
+                ConfigParam::KeySet { name, .. } => {
+                    if name == param {
+                        return Some(p);
+                    }
+                }
             }
         }
 
@@ -315,6 +415,8 @@ impl Profile {
                             result.config = Some(HashMap::new());
                         }
 
+                        result.migrate_requires();
+
                         Ok(result)
                     }
 
@@ -352,6 +454,79 @@ impl Profile {
         result
     }
 
+    /// Move the legacy `requires` string parameters to `imports`
+    fn migrate_requires(&mut self) {
+        let profile_file = &self.profile_file;
+        let imports = &mut self.imports;
+
+        if let Some(config) = self.config.as_mut() {
+            for (script_name, params) in config.iter_mut() {
+                let pos = params.iter().position(|p| match p {
+                    ConfigParam::String { name, .. } => name == "requires",
+                    _ => false,
+                });
+
+                if let Some(pos) = pos {
+                    if let ConfigParam::String { value, .. } = params.remove(pos) {
+                        warn!(
+                            "{}: The 'requires' parameter of script '{}' is deprecated, please use 'imports' instead",
+                            profile_file.display(),
+                            script_name
+                        );
+
+                        imports
+                            .entry(script_name.clone())
+                            .or_insert_with(|| vec![value]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Validate all parameter values of the active scripts against the
+    /// constraints declared in the respective manifests
+    pub fn validate(&self, script_dir: &Path) -> Result<()> {
+        let profile_name = self.profile_file.display().to_string();
+
+        for script_file in self.active_scripts.iter() {
+            let manifest = Manifest::from(&script_dir.join(script_file)).map_err(|_e| {
+                ProfileError::ManifestError {
+                    profile: profile_name.clone(),
+                    script: script_file.display().to_string(),
+                }
+            })?;
+
+            let values = match self.config.as_ref().and_then(|c| c.get(&manifest.name)) {
+                Some(values) => values,
+                None => continue,
+            };
+
+            let empty = vec![];
+            let params = manifest.config.as_ref().unwrap_or(&empty);
+
+            for value in values.iter() {
+                let invalid = |msg: String| ProfileError::InvalidParameter {
+                    profile: profile_name.clone(),
+                    script: manifest.name.clone(),
+                    param: value.get_name().clone(),
+                    msg,
+                };
+
+                let param = params
+                    .iter()
+                    .find(|p| manifest::GetAttr::get_name(*p) == value.get_name())
+                    .ok_or_else(|| invalid("not declared in the manifest".into()))?;
+
+                param.validate(value).map_err(|e| match e {
+                    ManifestError::InvalidValue { msg, .. } => invalid(msg),
+                    e => invalid(e.to_string()),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn save(&self) -> Result<()> {
         let toml = toml::ser::to_string_pretty(&self).map_err(|_| ProfileError::WriteError {
             msg: "Could not convert profile data".into(),
@@ -378,6 +553,12 @@ impl Profile {
 
     get_config_value!(color, u32, ConfigParam::Color);
     set_config_value!(color, u32, ConfigParam::Color);
+
+    get_config_value!(color_list, [u32], ConfigParam::ColorList);
+    set_config_value!(color_list, [u32], ConfigParam::ColorList);
+
+    get_config_value!(key_set, [u8], ConfigParam::KeySet);
+    set_config_value!(key_set, [u8], ConfigParam::KeySet);
 }
 
 impl Default for Profile {
@@ -393,6 +574,10 @@ impl Default for Profile {
             name: "Default".into(),
             description: "Auto-generated profile".into(),
             active_scripts: vec![PathBuf::from(constants::DEFAULT_EFFECT_SCRIPT)],
+            imports: HashMap::new(),
+            filters: vec![],
+            target_fps: None,
+            adaptive_fps: None,
             config,
         }
     }
@@ -0,0 +1,602 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use failure::Fail;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::canvas::{self, Canvas};
+use crate::constants;
+use crate::hwdevices::{NUM_KEYS, RGBA};
+
+pub type Result<T> = std::result::Result<T, RecordingError>;
+
+#[derive(Debug, Fail)]
+pub enum RecordingError {
+    #[fail(display = "Could not open recording: {}", msg)]
+    OpenError { msg: String },
+
+    #[fail(display = "Could not write recording: {}", msg)]
+    WriteError { msg: String },
+
+    #[fail(display = "Not a recording, or the recording is corrupt")]
+    InvalidFormat {},
+
+    #[fail(display = "Unsupported version of the recording format: {}", version)]
+    UnsupportedVersion { version: u8 },
+
+    #[fail(display = "Invalid name of a recording: {}", name)]
+    InvalidName { name: String },
+
+    #[fail(display = "The recording exceeds the maximum number of frames")]
+    RecordingTooLong {},
+
+    #[fail(display = "A recording is already in progress")]
+    AlreadyRecording {},
+
+    #[fail(display = "No recording in progress")]
+    NotRecording {},
+
+    #[fail(display = "The recording directory is full, please remove some recordings")]
+    DirectoryFull {},
+}
+
+const MAGIC: &[u8; 8] = b"ERUPTREC";
+const FORMAT_VERSION: u8 = 1;
+
+lazy_static! {
+    /// The recording that is currently being captured from the running profile
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+}
+
+/// A single frame of a recording
+#[derive(Debug, Clone)]
+struct RecordedFrame {
+    /// Time after the start of the recording
+    time: Duration,
+
+    leds: Vec<RGBA>,
+}
+
+/// A sequence of LED frames, with their timestamps
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    frames: Vec<RecordedFrame>,
+
+    /// Total duration, the last frame is displayed until the end
+    duration: Duration,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a recording from frames with a constant frame rate
+    pub fn from_frames(frames: &[Vec<RGBA>], fps: u64) -> Result<Self> {
+        let mut result = Self::new();
+        let frame_duration = Duration::from_micros(1_000_000 / fps.max(1));
+
+        for (index, frame) in frames.iter().enumerate() {
+            result.push(frame_duration * index as u32, frame)?;
+        }
+
+        result.duration = frame_duration * frames.len() as u32;
+
+        Ok(result)
+    }
+
+    /// Append a frame, `time` is relative to the start of the recording
+    pub fn push(&mut self, time: Duration, leds: &[RGBA]) -> Result<()> {
+        if self.frames.len() >= constants::MAX_RECORDING_FRAMES {
+            return Err(RecordingError::RecordingTooLong {});
+        }
+
+        let mut frame = vec![RGBA::default(); NUM_KEYS];
+        for (dst, src) in frame.iter_mut().zip(leds.iter()) {
+            *dst = *src;
+        }
+
+        self.frames.push(RecordedFrame { time, leds: frame });
+        self.duration = self.duration.max(time);
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Get the index of the frame that is displayed at `time`
+    pub fn get_frame_at(&self, time: Duration) -> usize {
+        match self.frames.binary_search_by_key(&time, |f| f.time) {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        }
+    }
+
+    /// Get the frame at `index` as a color map, as used by Lua scripts
+    pub fn get_color_map(&self, index: usize) -> Vec<u32> {
+        match self.frames.get(index) {
+            Some(frame) => frame
+                .leds
+                .iter()
+                .map(|c| {
+                    (u32::from(c.a) << 24)
+                        | (u32::from(c.r) << 16)
+                        | (u32::from(c.g) << 8)
+                        | u32::from(c.b)
+                })
+                .collect(),
+
+            None => vec![0; NUM_KEYS],
+        }
+    }
+
+    /// Write the recording. Each frame only stores the keys that differ from
+    /// the previous frame
+    pub fn encode<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u8(FORMAT_VERSION)?;
+        writer.write_u16::<LittleEndian>(NUM_KEYS as u16)?;
+        writer.write_u32::<LittleEndian>(self.frames.len() as u32)?;
+        writer.write_u32::<LittleEndian>(self.duration.as_millis() as u32)?;
+
+        let mut previous = vec![RGBA::default(); NUM_KEYS];
+
+        for frame in self.frames.iter() {
+            let changed: Vec<(usize, &RGBA)> = frame
+                .leds
+                .iter()
+                .enumerate()
+                .filter(|(index, c)| **c != previous[*index])
+                .collect();
+
+            writer.write_u32::<LittleEndian>(frame.time.as_millis() as u32)?;
+            writer.write_u16::<LittleEndian>(changed.len() as u16)?;
+
+            for (index, c) in changed {
+                writer.write_u16::<LittleEndian>(index as u16)?;
+                writer.write_all(&[c.r, c.g, c.b, c.a])?;
+            }
+
+            previous.copy_from_slice(&frame.leds);
+        }
+
+        Ok(())
+    }
+
+    /// Read a recording that has been written by `encode`
+    pub fn decode<R: Read>(reader: &mut R) -> Result<Self> {
+        let invalid = |_e| RecordingError::InvalidFormat {};
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).map_err(invalid)?;
+
+        if &magic != MAGIC {
+            return Err(RecordingError::InvalidFormat {});
+        }
+
+        let version = reader.read_u8().map_err(invalid)?;
+        if version != FORMAT_VERSION {
+            return Err(RecordingError::UnsupportedVersion { version });
+        }
+
+        let num_keys = reader.read_u16::<LittleEndian>().map_err(invalid)? as usize;
+        let num_frames = reader.read_u32::<LittleEndian>().map_err(invalid)? as usize;
+        let duration = reader.read_u32::<LittleEndian>().map_err(invalid)?;
+
+        if num_frames > constants::MAX_RECORDING_FRAMES {
+            return Err(RecordingError::RecordingTooLong {});
+        }
+
+        let mut result = Self::new();
+        let mut leds = vec![RGBA::default(); NUM_KEYS];
+
+        for _ in 0..num_frames {
+            let time = reader.read_u32::<LittleEndian>().map_err(invalid)?;
+            let changed = reader.read_u16::<LittleEndian>().map_err(invalid)?;
+
+            for _ in 0..changed {
+                let index = reader.read_u16::<LittleEndian>().map_err(invalid)? as usize;
+
+                let mut c = [0; 4];
+                reader.read_exact(&mut c).map_err(invalid)?;
+
+                if index >= num_keys {
+                    return Err(RecordingError::InvalidFormat {});
+                }
+
+                // keys that do not exist on this device are skipped
+                if let Some(led) = leds.get_mut(index) {
+                    *led = RGBA {
+                        r: c[0],
+                        g: c[1],
+                        b: c[2],
+                        a: c[3],
+                    };
+                }
+            }
+
+            result.push(Duration::from_millis(u64::from(time)), &leds)?;
+        }
+
+        result.duration = result
+            .duration
+            .max(Duration::from_millis(u64::from(duration)));
+
+        Ok(result)
+    }
+
+    /// Load a recording from the file `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|e| RecordingError::OpenError {
+            msg: format!("{}: {}", path.display(), e),
+        })?;
+
+        Self::decode(&mut BufReader::new(file))
+    }
+
+    /// Save the recording to the file `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let write_error = |e: std::io::Error| RecordingError::WriteError {
+            msg: format!("{}: {}", path.display(), e),
+        };
+
+        let mut writer = BufWriter::new(File::create(path).map_err(write_error)?);
+
+        self.encode(&mut writer).map_err(write_error)?;
+        writer.flush().map_err(write_error)?;
+
+        Ok(())
+    }
+}
+
+/// Plays back a recording, e.g. as a layer of a Lua script
+pub struct Player {
+    recording: Recording,
+
+    /// Position of the playback, in seconds
+    position: f64,
+
+    speed: f64,
+    looping: bool,
+}
+
+impl Player {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            position: 0.0,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    /// Load a recording from the file `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::new(Recording::load(path)?))
+    }
+
+    /// Advance the playback by `delta` seconds, multiplied by the speed. A
+    /// negative speed plays the recording backwards
+    pub fn update(&mut self, delta: f64) {
+        let duration = self.recording.get_duration().as_secs_f64();
+
+        self.position += delta * self.speed;
+
+        self.position = if self.looping && duration > 0.0 {
+            self.position.rem_euclid(duration)
+        } else {
+            self.position.max(0.0).min(duration)
+        };
+    }
+
+    /// True if the playback has reached the end (or the start, if played backwards)
+    pub fn is_finished(&self) -> bool {
+        let duration = self.recording.get_duration().as_secs_f64();
+
+        !self.looping
+            && ((self.speed > 0.0 && self.position >= duration)
+                || (self.speed < 0.0 && self.position <= 0.0))
+    }
+
+    pub fn get_current_frame(&self) -> usize {
+        self.recording
+            .get_frame_at(Duration::from_secs_f64(self.position.max(0.0)))
+    }
+}
+
+impl rlua::UserData for Player {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("frames", |_, player, ()| Ok(player.recording.len()));
+
+        methods.add_method("duration", |_, player, ()| {
+            Ok(player.recording.get_duration().as_secs_f64())
+        });
+
+        methods.add_method("position", |_, player, ()| Ok(player.position));
+
+        methods.add_method("current_frame", |_, player, ()| {
+            Ok(player.get_current_frame() + 1)
+        });
+
+        methods.add_method_mut("set_speed", |_, player, speed: f64| {
+            player.speed = speed;
+            Ok(())
+        });
+
+        methods.add_method_mut("set_loop", |_, player, looping: bool| {
+            player.looping = looping;
+            Ok(())
+        });
+
+        methods.add_method_mut("seek", |_, player, position: f64| {
+            player.position = position;
+            player.update(0.0);
+            Ok(())
+        });
+
+        methods.add_method_mut("rewind", |_, player, ()| {
+            player.position = if player.speed < 0.0 {
+                player.recording.get_duration().as_secs_f64()
+            } else {
+                0.0
+            };
+
+            Ok(())
+        });
+
+        methods.add_method_mut("update", |_, player, delta: f64| {
+            player.update(delta);
+            Ok(())
+        });
+
+        methods.add_method("is_finished", |_, player, ()| Ok(player.is_finished()));
+
+        methods.add_method("color_map", |_, player, ()| {
+            Ok(player.recording.get_color_map(player.get_current_frame()))
+        });
+    }
+}
+
+/// Captures frames of the running profile
+struct Recorder {
+    path: PathBuf,
+
+    /// File name of the script to record, or `None` to record the whole profile
+    layer: Option<String>,
+
+    start: Instant,
+    recording: Recording,
+}
+
+/// Get the directory recordings are stored in, from the `global.recording_dir`
+/// setting of the configuration file
+pub fn get_recording_dir() -> PathBuf {
+    PathBuf::from(
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str("global.recording_dir").ok())
+            .unwrap_or_else(|| constants::DEFAULT_RECORDING_DIR.to_string()),
+    )
+}
+
+/// Returns an error if no more recordings may be stored in `recording_dir`.
+/// An existing recording at `path` is going to be replaced, so it isn't counted
+fn check_recording_dir(recording_dir: &Path, path: &Path) -> Result<()> {
+    let read_error = |e: std::io::Error| RecordingError::WriteError {
+        msg: format!("{}: {}", recording_dir.display(), e),
+    };
+
+    let (mut count, mut size) = (0, 0);
+
+    for entry in fs::read_dir(recording_dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let metadata = entry.metadata().map_err(read_error)?;
+
+        if metadata.is_file() && entry.path() != path {
+            count += 1;
+            size += metadata.len();
+        }
+    }
+
+    if count >= constants::MAX_RECORDINGS || size >= constants::MAX_RECORDINGS_SIZE {
+        Err(RecordingError::DirectoryFull {})
+    } else {
+        Ok(())
+    }
+}
+
+/// Start to record the running profile to the file `name` in the recording
+/// directory. If `layer` is specified, only the script with that file name is
+/// recorded
+pub fn start_recording(name: &str, layer: Option<&str>) -> Result<()> {
+    // recordings are written with the privileges of the daemon, so we only
+    // accept plain file names
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        return Err(RecordingError::InvalidName {
+            name: name.to_string(),
+        });
+    }
+
+    let mut recorder = RECORDER.lock();
+
+    if recorder.is_some() {
+        return Err(RecordingError::AlreadyRecording {});
+    }
+
+    let recording_dir = get_recording_dir();
+    fs::create_dir_all(&recording_dir).map_err(|e| RecordingError::WriteError {
+        msg: format!("{}: {}", recording_dir.display(), e),
+    })?;
+
+    let path = recording_dir
+        .join(name)
+        .with_extension(constants::RECORDING_EXTENSION);
+
+    check_recording_dir(&recording_dir, &path)?;
+
+    info!(
+        "Recording {} to '{}'",
+        layer.unwrap_or("the active profile"),
+        path.display()
+    );
+
+    *recorder = Some(Recorder {
+        path,
+        layer: layer.map(|l| l.to_string()),
+        start: Instant::now(),
+        recording: Recording::new(),
+    });
+
+    Ok(())
+}
+
+/// Stop the recording that is in progress and save it, returns the number of
+/// recorded frames
+pub fn stop_recording() -> Result<usize> {
+    let mut recorder = RECORDER
+        .lock()
+        .take()
+        .ok_or(RecordingError::NotRecording {})?;
+
+    recorder.recording.duration = recorder.start.elapsed();
+
+    if recorder.recording.is_empty() {
+        warn!("The recording does not contain any frames");
+    }
+    recorder.recording.save(&recorder.path)?;
+
+    info!(
+        "Saved {} frames to '{}'",
+        recorder.recording.len(),
+        recorder.path.display()
+    );
+
+    Ok(recorder.recording.len())
+}
+
+fn record(layer: Option<&str>, canvas: &Canvas) {
+    let mut recorder = RECORDER.lock();
+
+    let result = match recorder.as_mut() {
+        Some(recorder) if recorder.layer.as_deref() == layer => {
+            let leds = canvas::to_led_map(canvas, 1.0, None);
+
+            recorder.recording.push(recorder.start.elapsed(), &leds)
+        }
+
+        _ => return,
+    };
+
+    if let Err(e) = result {
+        warn!("Stopping the recording: {}", e);
+
+        drop(recorder);
+        stop_recording().unwrap_or_else(|e| error!("Could not save the recording: {}", e));
+    }
+}
+
+/// Record a frame of the whole profile, if a recording of the profile is in progress
+pub fn record_frame(canvas: &Canvas) {
+    record(None, canvas);
+}
+
+/// Record a frame of the script `script_name`, if a recording of that layer is
+/// in progress
+pub fn record_layer(script_name: &str, canvas: &Canvas) {
+    record(Some(script_name), canvas);
+}
+
+/// Returns true if the layer of the script `script_name` is being recorded
+pub fn is_recording_layer(script_name: &str) -> bool {
+    RECORDER
+        .lock()
+        .as_ref()
+        .map_or(false, |r| r.layer.as_deref() == Some(script_name))
+}
+
+#[test]
+fn test_encode_decode() {
+    let red = RGBA {
+        r: 0xff,
+        g: 0,
+        b: 0,
+        a: 0xff,
+    };
+
+    let mut frames = vec![vec![RGBA::default(); NUM_KEYS]; 3];
+    frames[1][5] = red;
+    frames[2][5] = red;
+    frames[2][7] = red;
+
+    let recording = Recording::from_frames(&frames, 25).unwrap();
+
+    let mut data = vec![];
+    recording.encode(&mut data).unwrap();
+
+    // header, and three frames with 0, 1 and 1 changed keys
+    assert_eq!(data.len(), 19 + 6 * 3 + 6 * 2);
+
+    let decoded = Recording::decode(&mut data.as_slice()).unwrap();
+
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded.get_duration(), Duration::from_millis(120));
+    assert_eq!(decoded.get_frame_at(Duration::from_millis(90)), 2);
+    assert_eq!(decoded.get_color_map(2)[7], 0xffff_0000);
+    assert_eq!(decoded.get_color_map(0)[7], 0);
+
+    assert!(Recording::decode(&mut &data[1..]).is_err());
+}
+
+#[test]
+fn test_check_recording_dir() {
+    let dir = std::env::temp_dir().join(format!("eruption-recordings-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = |i: usize| dir.join(format!("{}.rec", i));
+
+    for i in 0..constants::MAX_RECORDINGS {
+        fs::write(path(i), b"").unwrap();
+    }
+
+    let full = check_recording_dir(&dir, &path(constants::MAX_RECORDINGS));
+
+    // existing recordings may be replaced
+    let replace = check_recording_dir(&dir, &path(0));
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(full.is_err());
+    assert!(replace.is_ok());
+}
@@ -0,0 +1,541 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use log::*;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::constants;
+use crate::frame_rate::FrameRateSettings;
+use crate::hwdevices::layout::{self, KeyboardVariant, KEYBOARD_HEIGHT, KEYBOARD_WIDTH};
+use crate::hwdevices::RGBA;
+use crate::profiles::{self, Profile};
+use crate::recording::Recording;
+use crate::scripting::harness::{Frame, ScriptHarness};
+
+pub type Result<T> = std::result::Result<T, RenderError>;
+
+#[derive(Debug, Fail)]
+pub enum RenderError {
+    #[fail(display = "Could not load profile: {}", msg)]
+    ProfileError { msg: String },
+
+    #[fail(display = "Could not run scripts: {}", msg)]
+    ScriptError { msg: String },
+
+    #[fail(display = "Could not write image: {}", msg)]
+    WriteError { msg: String },
+
+    #[fail(display = "Unknown output format: {}", format)]
+    UnknownFormat { format: String },
+
+    #[fail(display = "Invalid duration: {} seconds", seconds)]
+    InvalidDuration { seconds: f64 },
+
+    #[fail(display = "No frames have been rendered")]
+    NoFrames {},
+
+    #[fail(
+        display = "Invalid layer index {}, there are {} layers",
+        index, num_layers
+    )]
+    InvalidLayer { index: usize, num_layers: usize },
+}
+
+/// Margin around the keyboard, in key units
+const MARGIN: f64 = 0.25;
+
+/// Gap between adjacent keys, in key units
+const KEY_GAP: f64 = 0.1;
+
+/// Color of the keyboard's case
+const BACKGROUND_COLOR: [u8; 4] = [0x18, 0x18, 0x18, 0xff];
+
+/// Supported output formats
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    /// Animated GIF
+    Gif,
+
+    /// Animated PNG
+    Apng,
+
+    /// A sequence of PNG files, one per frame
+    PngSequence,
+
+    /// A recording, that may be played back by Lua scripts
+    Recording,
+}
+
+impl FromStr for ImageFormat {
+    type Err = RenderError;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "gif" => Ok(ImageFormat::Gif),
+            "apng" => Ok(ImageFormat::Apng),
+            "png" => Ok(ImageFormat::PngSequence),
+            "rec" => Ok(ImageFormat::Recording),
+
+            _ => Err(RenderError::UnknownFormat {
+                format: format.to_string(),
+            }),
+        }
+    }
+}
+
+impl ImageFormat {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Gif => "gif",
+            ImageFormat::Apng | ImageFormat::PngSequence => "png",
+            ImageFormat::Recording => constants::RECORDING_EXTENSION,
+        }
+    }
+}
+
+/// Draws frames laid out like the physical keyboard
+pub struct Rasterizer {
+    variant: KeyboardVariant,
+
+    /// Pixels per key unit
+    scale: f64,
+
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rasterizer {
+    pub fn new(variant: KeyboardVariant, scale: u32) -> Self {
+        let scale = f64::from(scale.max(4));
+
+        Self {
+            variant,
+            scale,
+            width: ((KEYBOARD_WIDTH + 2.0 * MARGIN) * scale).ceil() as u32,
+            height: ((KEYBOARD_HEIGHT + 2.0 * MARGIN) * scale).ceil() as u32,
+        }
+    }
+
+    /// Draw `frame`, returns RGBA pixel data
+    pub fn rasterize(&self, frame: &[RGBA]) -> Vec<u8> {
+        let mut pixels: Vec<u8> = BACKGROUND_COLOR
+            .iter()
+            .cycle()
+            .take((self.width * self.height * 4) as usize)
+            .copied()
+            .collect();
+
+        let to_px = |v: f64| ((v + MARGIN) * self.scale).round() as u32;
+
+        for key in layout::get_layout(self.variant) {
+            let color = match frame.get(key.index) {
+                Some(c) => [c.r, c.g, c.b, 0xff],
+                None => continue,
+            };
+
+            let x0 = to_px(key.x + KEY_GAP / 2.0);
+            let y0 = to_px(key.y + KEY_GAP / 2.0);
+            let x1 = to_px(key.x + key.width - KEY_GAP / 2.0).min(self.width);
+            let y1 = to_px(key.y + key.height - KEY_GAP / 2.0).min(self.height);
+
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let offset = ((y * self.width + x) * 4) as usize;
+                    pixels[offset..offset + 4].copy_from_slice(&color);
+                }
+            }
+        }
+
+        pixels
+    }
+}
+
+/// Render `num_frames` frames of the composited output of `profile`, at `fps`
+/// frames per second
+pub fn render_profile(
+    profile: Profile,
+    script_dir: &Path,
+    num_frames: usize,
+    fps: u64,
+) -> Result<Vec<Frame>> {
+    let mut harness = ScriptHarness::from_profile(profile, script_dir)
+        .map_err(|e| RenderError::ScriptError { msg: e.to_string() })?;
+    harness.set_frame_rate(fps);
+
+    for _ in 0..num_frames {
+        harness
+            .step()
+            .map_err(|e| RenderError::ScriptError { msg: e.to_string() })?;
+    }
+
+    Ok(harness.get_frames().to_vec())
+}
+
+/// Render `num_frames` frames of the script at `index` of `profile`, without
+/// the other layers of the profile, at `fps` frames per second
+pub fn render_layer(
+    profile: Profile,
+    script_dir: &Path,
+    index: usize,
+    num_frames: usize,
+    fps: u64,
+) -> Result<Vec<Frame>> {
+    let mut harness = ScriptHarness::from_profile(profile, script_dir)
+        .map_err(|e| RenderError::ScriptError { msg: e.to_string() })?;
+    harness.set_frame_rate(fps);
+
+    if index >= harness.get_num_layers() {
+        return Err(RenderError::InvalidLayer {
+            index,
+            num_layers: harness.get_num_layers(),
+        });
+    }
+
+    let mut result = Vec::with_capacity(num_frames);
+
+    for _ in 0..num_frames {
+        harness
+            .tick_frame()
+            .and_then(|_| harness.capture_layer(index))
+            .map(|frame| result.push(frame))
+            .map_err(|e| RenderError::ScriptError { msg: e.to_string() })?;
+    }
+
+    Ok(result)
+}
+
+fn write_error<E: std::fmt::Display>(e: E) -> RenderError {
+    RenderError::WriteError { msg: e.to_string() }
+}
+
+/// Get the delay of the frame at `index`, in units of 10 milliseconds, as used
+/// by GIF. The delays of consecutive frames alternate, to match `fps` on average
+fn get_gif_frame_delay(index: u64, fps: u64) -> u16 {
+    let fps = fps.max(1);
+    let get_time = |index: u64| (index * 100 + fps / 2) / fps;
+
+    (get_time(index + 1) - get_time(index)) as u16
+}
+
+/// Write `frames` as an animated GIF
+pub fn write_gif(path: &Path, frames: &[Frame], rasterizer: &Rasterizer, fps: u64) -> Result<()> {
+    let file = BufWriter::new(File::create(path).map_err(write_error)?);
+
+    let mut encoder =
+        gif::Encoder::new(file, rasterizer.width as u16, rasterizer.height as u16, &[])
+            .map_err(write_error)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(write_error)?;
+
+    for (index, frame) in frames.iter().enumerate() {
+        let mut pixels = rasterizer.rasterize(&frame);
+
+        let mut gif_frame = gif::Frame::from_rgba_speed(
+            rasterizer.width as u16,
+            rasterizer.height as u16,
+            &mut pixels,
+            10,
+        );
+        gif_frame.delay = get_gif_frame_delay(index as u64, fps);
+
+        encoder.write_frame(&gif_frame).map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+/// Write `frames` as an animated PNG
+pub fn write_apng(path: &Path, frames: &[Frame], rasterizer: &Rasterizer, fps: u64) -> Result<()> {
+    let file = BufWriter::new(File::create(path).map_err(write_error)?);
+
+    let mut encoder = png::Encoder::new(file, rasterizer.width, rasterizer.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(write_error)?;
+    encoder
+        .set_frame_delay(1, fps as u16)
+        .map_err(write_error)?;
+
+    let mut writer = encoder.write_header().map_err(write_error)?;
+
+    for frame in frames.iter() {
+        writer
+            .write_image_data(&rasterizer.rasterize(&frame))
+            .map_err(write_error)?;
+    }
+
+    writer.finish().map_err(write_error)?;
+
+    Ok(())
+}
+
+/// Write `frames` as a sequence of PNG files, named `<path>-<frame>.png`
+pub fn write_png_sequence(path: &Path, frames: &[Frame], rasterizer: &Rasterizer) -> Result<()> {
+    let stem = path.with_extension("");
+
+    for (index, frame) in frames.iter().enumerate() {
+        let file_name = PathBuf::from(format!("{}-{:04}.png", stem.display(), index));
+        let file = BufWriter::new(File::create(&file_name).map_err(write_error)?);
+
+        let mut encoder = png::Encoder::new(file, rasterizer.width, rasterizer.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(write_error)?;
+        writer
+            .write_image_data(&rasterizer.rasterize(&frame))
+            .map_err(write_error)?;
+    }
+
+    Ok(())
+}
+
+/// Write `frames` as a recording
+pub fn write_recording(path: &Path, frames: &[Frame], fps: u64) -> Result<()> {
+    Recording::from_frames(frames, fps)
+        .map_err(write_error)?
+        .save(path)
+        .map_err(write_error)
+}
+
+/// Write `frames` to `path`, using the image format `format`. The frames
+/// are played back at `fps` frames per second
+pub fn write_frames(
+    path: &Path,
+    frames: &[Frame],
+    rasterizer: &Rasterizer,
+    format: ImageFormat,
+    fps: u64,
+) -> Result<()> {
+    if frames.is_empty() {
+        return Err(RenderError::NoFrames {});
+    }
+
+    match format {
+        ImageFormat::Gif => write_gif(path, frames, rasterizer, fps),
+        ImageFormat::Apng => write_apng(path, frames, rasterizer, fps),
+        ImageFormat::PngSequence => write_png_sequence(path, frames, rasterizer),
+        ImageFormat::Recording => write_recording(path, frames, fps),
+    }
+}
+
+/// Render the profile file `profile_file` to the image file `output`. If
+/// `layer` is specified, only the script at that index is rendered
+pub fn render_profile_file(
+    profile_file: &Path,
+    script_dir: &Path,
+    output: &Path,
+    seconds: f64,
+    layer: Option<usize>,
+    rasterizer: &Rasterizer,
+    format: ImageFormat,
+) -> Result<()> {
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(RenderError::InvalidDuration { seconds });
+    }
+
+    let profile = Profile::from(profile_file)
+        .map_err(|e| RenderError::ProfileError { msg: e.to_string() })?;
+
+    // render at the frame rate the daemon would use for this profile
+    let fps = FrameRateSettings::from_config()
+        .with_profile(&profile)
+        .target_fps;
+
+    let num_frames = (seconds * fps as f64).ceil() as usize;
+
+    let frames = match layer {
+        Some(index) => render_layer(profile, script_dir, index, num_frames, fps)?,
+        None => render_profile(profile, script_dir, num_frames, fps)?,
+    };
+
+    write_frames(output, &frames, rasterizer, format, fps)?;
+
+    info!(
+        "Rendered {} frames of '{}' to '{}'",
+        frames.len(),
+        profile_file.display(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Entry point of the `render` subcommand, returns the process exit code
+pub fn run_cli(matches: &clap::ArgMatches) -> i32 {
+    let config_value = |key: &str, default: &str| {
+        crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str(key).ok())
+            .unwrap_or_else(|| default.to_string())
+    };
+
+    let script_dir = PathBuf::from(
+        matches
+            .value_of("script-dir")
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| config_value("global.script_dir", constants::DEFAULT_SCRIPT_DIR)),
+    );
+
+    let seconds = match matches.value_of("seconds").map(|v| v.parse::<f64>()) {
+        Some(Ok(seconds)) if seconds > 0.0 && seconds.is_finite() => seconds,
+
+        Some(_) => {
+            error!("Invalid duration, it must be a positive number of seconds");
+            return 2;
+        }
+
+        None => 5.0,
+    };
+
+    let scale = matches
+        .value_of("scale")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(24);
+
+    let format = match matches
+        .value_of("format")
+        .unwrap_or("gif")
+        .parse::<ImageFormat>()
+    {
+        Ok(format) => format,
+
+        Err(e) => {
+            error!("{}", e);
+            return 2;
+        }
+    };
+
+    let layer: Option<usize> = match matches.value_of("layer").map(|v| v.parse()) {
+        Some(Ok(layer)) => Some(layer),
+
+        Some(Err(_)) => {
+            error!("Invalid layer index");
+            return 2;
+        }
+
+        None => None,
+    };
+
+    let rasterizer = Rasterizer::new(KeyboardVariant::from_config(), scale);
+
+    if matches.is_present("all") {
+        let profile_dir = PathBuf::from(
+            matches
+                .value_of("PROFILE")
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| {
+                    config_value("global.profile_dir", constants::DEFAULT_PROFILE_DIR)
+                }),
+        );
+
+        let output_dir = PathBuf::from(matches.value_of("output").unwrap_or("."));
+        if let Err(e) = fs::create_dir_all(&output_dir) {
+            error!("Could not create the output directory: {}", e);
+            return 1;
+        }
+
+        let profile_files = match profiles::get_profile_files(&profile_dir) {
+            Ok(files) => files,
+
+            Err(e) => {
+                error!("Could not enumerate profiles: {}", e);
+                return 1;
+            }
+        };
+
+        let mut errors_present = false;
+
+        for profile_file in profile_files.iter() {
+            let output = output_dir
+                .join(profile_file.file_stem().unwrap())
+                .with_extension(format.get_extension());
+
+            render_profile_file(
+                &profile_file,
+                &script_dir,
+                &output,
+                seconds,
+                None,
+                &rasterizer,
+                format,
+            )
+            .unwrap_or_else(|e| {
+                error!("Could not render '{}': {}", profile_file.display(), e);
+                errors_present = true;
+            });
+        }
+
+        if errors_present {
+            1
+        } else {
+            0
+        }
+    } else {
+        let profile_file = match matches.value_of("PROFILE") {
+            Some(profile_file) => PathBuf::from(profile_file),
+
+            None => {
+                error!("No profile specified");
+                return 2;
+            }
+        };
+
+        let output = matches
+            .value_of("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                PathBuf::from(profile_file.file_stem().unwrap())
+                    .with_extension(format.get_extension())
+            });
+
+        match render_profile_file(
+            &profile_file,
+            &script_dir,
+            &output,
+            seconds,
+            layer,
+            &rasterizer,
+            format,
+        ) {
+            Ok(()) => 0,
+
+            Err(e) => {
+                error!("Could not render '{}': {}", profile_file.display(), e);
+                1
+            }
+        }
+    }
+}
+
+#[test]
+fn test_gif_frame_delay() {
+    let delays: Vec<u16> = (0..24).map(|i| get_gif_frame_delay(i, 24)).collect();
+
+    assert!(delays.iter().all(|d| *d == 4 || *d == 5));
+    assert_eq!(delays.iter().map(|d| u32::from(*d)).sum::<u32>(), 100);
+
+    assert_eq!(get_gif_frame_delay(7, 20), 5);
+}
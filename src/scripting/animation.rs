@@ -0,0 +1,590 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use rlua::{AnyUserData, Context, Function, Table, Value};
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+use crate::color::{self, ColorSpace};
+use crate::util;
+
+/// Shape of an easing curve
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Curve {
+    Linear,
+    Sine,
+    Quad,
+    Cubic,
+    Quart,
+    Expo,
+    Back,
+    Elastic,
+    Bounce,
+}
+
+/// Which end of the curve gets eased
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum EasingMode {
+    In,
+    Out,
+    InOut,
+}
+
+/// An easing function, e.g. `ease_in_out_cubic`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Easing {
+    curve: Curve,
+    mode: EasingMode,
+}
+
+const CURVES: &[(&str, Curve)] = &[
+    ("sine", Curve::Sine),
+    ("quad", Curve::Quad),
+    ("cubic", Curve::Cubic),
+    ("quart", Curve::Quart),
+    ("expo", Curve::Expo),
+    ("back", Curve::Back),
+    ("elastic", Curve::Elastic),
+    ("bounce", Curve::Bounce),
+];
+
+fn bounce_out(t: f64) -> f64 {
+    const N1: f64 = 7.5625;
+    const D1: f64 = 2.75;
+
+    if t < 1.0 / D1 {
+        N1 * t * t
+    } else if t < 2.0 / D1 {
+        let t = t - 1.5 / D1;
+        N1 * t * t + 0.75
+    } else if t < 2.5 / D1 {
+        let t = t - 2.25 / D1;
+        N1 * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D1;
+        N1 * t * t + 0.984_375
+    }
+}
+
+impl Curve {
+    /// The "ease in" variant of the curve
+    fn ease_in(self, t: f64) -> f64 {
+        match self {
+            Curve::Linear => t,
+            Curve::Sine => 1.0 - (t * PI / 2.0).cos(),
+            Curve::Quad => t.powi(2),
+            Curve::Cubic => t.powi(3),
+            Curve::Quart => t.powi(4),
+
+            Curve::Expo => {
+                if t <= 0.0 {
+                    0.0
+                } else {
+                    2.0_f64.powf(10.0 * t - 10.0)
+                }
+            }
+
+            Curve::Back => {
+                const C1: f64 = 1.701_58;
+                (C1 + 1.0) * t.powi(3) - C1 * t.powi(2)
+            }
+
+            Curve::Elastic => {
+                if t <= 0.0 || t >= 1.0 {
+                    t
+                } else {
+                    -(2.0_f64.powf(10.0 * t - 10.0)) * ((10.0 * t - 10.75) * 2.0 * PI / 3.0).sin()
+                }
+            }
+
+            Curve::Bounce => 1.0 - bounce_out(1.0 - t),
+        }
+    }
+}
+
+impl Easing {
+    pub const LINEAR: Easing = Easing {
+        curve: Curve::Linear,
+        mode: EasingMode::In,
+    };
+
+    /// Get an easing function by name, like `linear` or `ease_in_out_cubic`
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name == "linear" {
+            return Some(Self::LINEAR);
+        }
+
+        let (mode, curve) = if name.starts_with("ease_in_out_") {
+            (EasingMode::InOut, &name["ease_in_out_".len()..])
+        } else if name.starts_with("ease_in_") {
+            (EasingMode::In, &name["ease_in_".len()..])
+        } else if name.starts_with("ease_out_") {
+            (EasingMode::Out, &name["ease_out_".len()..])
+        } else {
+            return None;
+        };
+
+        CURVES
+            .iter()
+            .find(|(n, _)| *n == curve)
+            .map(|(_, curve)| Easing {
+                curve: *curve,
+                mode,
+            })
+    }
+
+    /// Apply the easing function to `t`, in the range [0.0..1.0]. Some curves,
+    /// like `back` and `elastic`, overshoot the range of [0.0..1.0]
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.max(0.0).min(1.0);
+
+        match self.mode {
+            EasingMode::In => self.curve.ease_in(t),
+            EasingMode::Out => 1.0 - self.curve.ease_in(1.0 - t),
+
+            EasingMode::InOut => {
+                if t < 0.5 {
+                    self.curve.ease_in(2.0 * t) / 2.0
+                } else {
+                    1.0 - self.curve.ease_in(2.0 - 2.0 * t) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// What happens when a timeline reaches its last keyframe
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RepeatMode {
+    /// Stop at the last keyframe
+    Once,
+
+    /// Start over at the first keyframe
+    Loop,
+
+    /// Play backwards, then forwards again
+    PingPong,
+}
+
+impl RepeatMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "once" => Some(RepeatMode::Once),
+            "loop" => Some(RepeatMode::Loop),
+            "ping_pong" => Some(RepeatMode::PingPong),
+
+            _ => None,
+        }
+    }
+}
+
+/// A keyframe of a timeline
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    /// Position on the timeline, in seconds
+    pub time: f64,
+
+    pub value: f64,
+
+    /// Easing of the transition from the previous keyframe
+    pub easing: Easing,
+}
+
+/// A sequence of keyframes, that runs on the wall clock
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    keyframes: Vec<Keyframe>,
+    repeat: RepeatMode,
+
+    /// Interpolate the values as colors (in OKLab), instead of as numbers
+    is_color: bool,
+
+    start: Instant,
+    paused_at: Option<Duration>,
+}
+
+impl Timeline {
+    pub fn new(mut keyframes: Vec<Keyframe>, repeat: RepeatMode, is_color: bool) -> Self {
+        keyframes.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        Self {
+            keyframes,
+            repeat,
+            is_color,
+            start: Instant::now(),
+            paused_at: None,
+        }
+    }
+
+    /// A timeline with two keyframes
+    pub fn tween(
+        from: f64,
+        to: f64,
+        duration: f64,
+        easing: Easing,
+        repeat: RepeatMode,
+        is_color: bool,
+    ) -> Self {
+        let keyframes = vec![
+            Keyframe {
+                time: 0.0,
+                value: from,
+                easing: Easing::LINEAR,
+            },
+            Keyframe {
+                time: duration.max(0.0),
+                value: to,
+                easing,
+            },
+        ];
+
+        Self::new(keyframes, repeat, is_color)
+    }
+
+    /// Duration of one pass, in seconds
+    pub fn get_duration(&self) -> f64 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    /// Time since the timeline has been started, excluding pauses
+    pub fn get_elapsed(&self) -> f64 {
+        self.paused_at
+            .unwrap_or_else(|| self.start.elapsed())
+            .as_secs_f64()
+    }
+
+    /// Restart the timeline
+    pub fn play(&mut self) {
+        self.start = Instant::now();
+        self.paused_at = None;
+    }
+
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(self.start.elapsed());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(elapsed) = self.paused_at.take() {
+            self.start = Instant::now() - elapsed;
+        }
+    }
+
+    /// Returns true if a timeline that doesn't repeat has reached its last keyframe
+    pub fn is_finished(&self) -> bool {
+        self.repeat == RepeatMode::Once && self.get_elapsed() >= self.get_duration()
+    }
+
+    /// Get the value at `t` seconds after the start of the timeline
+    pub fn get_value_at(&self, t: f64) -> f64 {
+        let (first, last) = match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+
+        let duration = self.get_duration();
+
+        let t = if duration <= 0.0 {
+            duration
+        } else {
+            match self.repeat {
+                RepeatMode::Once => t.min(duration),
+                RepeatMode::Loop => t.rem_euclid(duration),
+
+                RepeatMode::PingPong => {
+                    let t = t.rem_euclid(2.0 * duration);
+
+                    if t > duration {
+                        2.0 * duration - t
+                    } else {
+                        t
+                    }
+                }
+            }
+        };
+
+        if t <= first.time {
+            return first.value;
+        } else if t >= last.time {
+            return last.value;
+        }
+
+        // `t` may still be NaN here
+        let (i, p) = match util::find_segment(&self.keyframes, t, |k| k.time) {
+            Some(segment) => segment,
+            None => return last.value,
+        };
+
+        let (k0, k1) = (&self.keyframes[i - 1], &self.keyframes[i]);
+        let p = k1.easing.apply(p);
+
+        if self.is_color {
+            f64::from(color::mix(
+                k0.value as u32,
+                k1.value as u32,
+                p,
+                ColorSpace::Oklab,
+            ))
+        } else {
+            k0.value + (k1.value - k0.value) * p
+        }
+    }
+
+    /// Get the current value
+    pub fn get_value(&self) -> f64 {
+        self.get_value_at(self.get_elapsed())
+    }
+}
+
+impl rlua::UserData for Timeline {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("value", |_, timeline, ()| Ok(timeline.get_value()));
+        methods.add_method("value_at", |_, timeline, t: f64| {
+            Ok(timeline.get_value_at(t))
+        });
+        methods.add_method("duration", |_, timeline, ()| Ok(timeline.get_duration()));
+        methods.add_method("is_finished", |_, timeline, ()| Ok(timeline.is_finished()));
+
+        methods.add_method_mut("play", |_, timeline, ()| {
+            timeline.play();
+            Ok(())
+        });
+
+        methods.add_method_mut("pause", |_, timeline, ()| {
+            timeline.pause();
+            Ok(())
+        });
+
+        methods.add_method_mut("resume", |_, timeline, ()| {
+            timeline.resume();
+            Ok(())
+        });
+    }
+}
+
+pub fn get_easing(name: Option<String>) -> rlua::Result<Easing> {
+    match name {
+        Some(name) => Easing::from_name(&name).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!("Unknown easing function: '{}'", name))
+        }),
+
+        None => Ok(Easing::LINEAR),
+    }
+}
+
+pub fn get_repeat_mode(name: Option<String>) -> rlua::Result<RepeatMode> {
+    match name {
+        Some(name) => RepeatMode::from_name(&name)
+            .ok_or_else(|| rlua::Error::RuntimeError(format!("Unknown repeat mode: '{}'", name))),
+
+        None => Ok(RepeatMode::Once),
+    }
+}
+
+/// Parse keyframes from a list of `{ time, value [, easing] }` tables
+pub fn get_keyframes(keyframes: Vec<Table>) -> rlua::Result<Vec<Keyframe>> {
+    keyframes
+        .iter()
+        .map(|k| {
+            let time: f64 = k.get(1)?;
+
+            if !time.is_finite() {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "Invalid time of keyframe: {}",
+                    time
+                )));
+            }
+
+            Ok(Keyframe {
+                time,
+                value: k.get(2)?,
+                easing: get_easing(k.get(3)?)?,
+            })
+        })
+        .collect()
+}
+
+/// Name of the Lua registry table that holds the bound animations
+const ANIMATIONS_KEY: &str = "eruption.animations";
+
+fn get_animations(lua_ctx: Context) -> rlua::Result<Table> {
+    match lua_ctx.named_registry_value::<_, Table>(ANIMATIONS_KEY) {
+        Ok(table) => Ok(table),
+
+        Err(_) => {
+            let table = lua_ctx.create_table()?;
+            lua_ctx.set_named_registry_value(ANIMATIONS_KEY, table.clone())?;
+
+            Ok(table)
+        }
+    }
+}
+
+/// Bind `timeline` to the property `key` of `target`, the property is updated
+/// on every tick. Returns a handle, that may be passed to `cancel`
+pub fn animate<'lua>(
+    lua_ctx: Context<'lua>,
+    target: Table<'lua>,
+    key: Value<'lua>,
+    timeline: AnyUserData<'lua>,
+    on_complete: Option<Function<'lua>>,
+) -> rlua::Result<i64> {
+    if !timeline.is::<Timeline>() {
+        return Err(rlua::Error::RuntimeError(
+            "Not a timeline or tween".to_string(),
+        ));
+    }
+
+    let animations = get_animations(lua_ctx)?;
+
+    let handle = lua_ctx
+        .named_registry_value::<_, Option<i64>>("eruption.animations.next")?
+        .unwrap_or(1);
+    lua_ctx.set_named_registry_value("eruption.animations.next", handle + 1)?;
+
+    // bound animations replace each other
+    cancel_property(lua_ctx, &target, &key)?;
+
+    let animation = lua_ctx.create_table()?;
+    animation.set("target", target)?;
+    animation.set("key", key)?;
+    animation.set("timeline", timeline)?;
+    animation.set("on_complete", on_complete)?;
+
+    animations.set(handle, animation)?;
+
+    Ok(handle)
+}
+
+/// Stop the animation with the handle `handle`, the property keeps its current value
+pub fn cancel(lua_ctx: Context, handle: i64) -> rlua::Result<()> {
+    get_animations(lua_ctx)?.set(handle, Value::Nil)
+}
+
+fn cancel_property(lua_ctx: Context, target: &Table, key: &Value) -> rlua::Result<()> {
+    let animations = get_animations(lua_ctx)?;
+    let rawequal: Function = lua_ctx.globals().get("rawequal")?;
+    let mut handles = vec![];
+
+    for pair in animations.clone().pairs::<i64, Table>() {
+        let (handle, animation) = pair?;
+
+        let same_target: bool =
+            rawequal.call((animation.get::<_, Table>("target")?, target.clone()))?;
+        let same_key: bool = rawequal.call((animation.get::<_, Value>("key")?, key.clone()))?;
+
+        if same_target && same_key {
+            handles.push(handle);
+        }
+    }
+
+    for handle in handles {
+        animations.set(handle, Value::Nil)?;
+    }
+
+    Ok(())
+}
+
+/// Evaluate all bound animations of the VM of `lua_ctx`, and update their properties
+pub fn update(lua_ctx: Context) -> rlua::Result<()> {
+    let animations = match lua_ctx.named_registry_value::<_, Table>(ANIMATIONS_KEY) {
+        Ok(animations) => animations,
+        Err(_) => return Ok(()),
+    };
+
+    let mut completed = vec![];
+
+    for pair in animations.clone().pairs::<i64, Table>() {
+        let (handle, animation) = pair?;
+
+        let target: Table = animation.get("target")?;
+        let key: Value = animation.get("key")?;
+        let timeline: AnyUserData = animation.get("timeline")?;
+
+        let (value, is_finished, is_color) = {
+            let timeline = timeline.borrow::<Timeline>()?;
+            (
+                timeline.get_value(),
+                timeline.is_finished(),
+                timeline.is_color,
+            )
+        };
+
+        // colors have to be passed to the Lua VM as integers
+        if is_color {
+            target.set(key, value as u32)?;
+        } else {
+            target.set(key, value)?;
+        }
+
+        if is_finished {
+            completed.push((handle, animation.get::<_, Option<Function>>("on_complete")?));
+        }
+    }
+
+    for (handle, on_complete) in completed {
+        animations.set(handle, Value::Nil)?;
+
+        if let Some(on_complete) = on_complete {
+            on_complete.call::<_, ()>(handle)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_easing() {
+    for name in &[
+        "linear",
+        "ease_in_quad",
+        "ease_out_bounce",
+        "ease_in_out_elastic",
+    ] {
+        let easing = Easing::from_name(name).unwrap();
+
+        assert!(easing.apply(0.0).abs() < 1e-9);
+        assert!((easing.apply(1.0) - 1.0).abs() < 1e-9);
+    }
+
+    assert!(Easing::from_name("ease_in_unknown").is_none());
+    assert!((Easing::from_name("ease_in_out_cubic").unwrap().apply(0.5) - 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_timeline() {
+    let tween = Timeline::tween(0.0, 10.0, 2.0, Easing::LINEAR, RepeatMode::PingPong, false);
+
+    assert!((tween.get_value_at(1.0) - 5.0).abs() < 1e-9);
+    assert!((tween.get_value_at(2.0) - 10.0).abs() < 1e-9);
+    assert!((tween.get_value_at(3.0) - 5.0).abs() < 1e-9);
+    assert!((tween.get_value_at(4.5) - 2.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_timeline_nan() {
+    let tween = Timeline::tween(0.0, 10.0, 2.0, Easing::LINEAR, RepeatMode::Loop, false);
+    assert!((tween.get_value_at(std::f64::NAN) - 10.0).abs() < 1e-9);
+
+    let tween = Timeline::tween(0.0, 10.0, 2.0, Easing::LINEAR, RepeatMode::PingPong, false);
+    assert!((tween.get_value_at(std::f64::NAN) - 10.0).abs() < 1e-9);
+}
@@ -0,0 +1,191 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use rlua::{Context, Table, Value};
+
+use crate::constants;
+
+lazy_static! {
+    /// Messages that have been published but not yet routed to the Lua VMs
+    static ref PENDING_MESSAGES: Mutex<Vec<(String, BusValue)>> = Mutex::new(Vec::new());
+}
+
+/// A Lua value that can be sent between VMs
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusValue {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Table(Vec<(BusValue, BusValue)>),
+}
+
+impl BusValue {
+    /// Convert a Lua value. Functions, userdata and threads can't leave their
+    /// VM, they are converted to `Nil`, as are tables nested too deeply
+    pub fn from_lua(value: Value, depth: usize) -> rlua::Result<Self> {
+        let result = match value {
+            Value::Nil => BusValue::Nil,
+            Value::Boolean(b) => BusValue::Bool(b),
+            Value::Integer(i) => BusValue::Int(i),
+            Value::Number(f) => BusValue::Float(f),
+            Value::String(s) => BusValue::String(s.to_str()?.to_string()),
+
+            Value::Table(table) if depth < constants::MAX_BUS_TABLE_DEPTH => {
+                let mut entries = vec![];
+
+                for pair in table.pairs::<Value, Value>() {
+                    let (k, v) = pair?;
+                    entries.push((Self::from_lua(k, depth + 1)?, Self::from_lua(v, depth + 1)?));
+                }
+
+                BusValue::Table(entries)
+            }
+
+            _ => BusValue::Nil,
+        };
+
+        Ok(result)
+    }
+
+    /// Convert back into a Lua value, living in the VM of `lua_ctx`
+    pub fn to_lua<'lua>(&self, lua_ctx: Context<'lua>) -> rlua::Result<Value<'lua>> {
+        let result = match self {
+            BusValue::Nil => Value::Nil,
+            BusValue::Bool(b) => Value::Boolean(*b),
+            BusValue::Int(i) => Value::Integer(*i),
+            BusValue::Float(f) => Value::Number(*f),
+            BusValue::String(s) => Value::String(lua_ctx.create_string(s)?),
+
+            BusValue::Table(entries) => {
+                let table = lua_ctx.create_table()?;
+
+                for (k, v) in entries.iter() {
+                    // nil keys are not allowed in Lua tables
+                    if *k != BusValue::Nil {
+                        table.set(k.to_lua(lua_ctx)?, v.to_lua(lua_ctx)?)?;
+                    }
+                }
+
+                Value::Table(table)
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+/// Queue a message for delivery to all subscribers of `topic`, the main loop
+/// routes queued messages to all VMs
+pub fn publish(topic: &str, payload: BusValue) {
+    let mut pending = PENDING_MESSAGES.lock();
+
+    if pending.len() < constants::MAX_PENDING_BUS_MESSAGES {
+        pending.push((topic.to_string(), payload));
+    } else {
+        warn!(
+            "Message bus overflow, dropping message on topic '{}'",
+            topic
+        );
+    }
+}
+
+/// Take all queued messages, in the order they have been published
+pub fn take_pending() -> Vec<(String, BusValue)> {
+    std::mem::replace(&mut *PENDING_MESSAGES.lock(), Vec::new())
+}
+
+/// Discard all queued messages, e.g. when the active profile changes
+pub fn clear_pending() {
+    PENDING_MESSAGES.lock().clear();
+}
+
+/// Name of the Lua registry table that maps topics to lists of handlers
+const SUBSCRIPTIONS_KEY: &str = "eruption.bus.subscriptions";
+
+/// Register `handler` to be called for messages on `topic`
+pub fn subscribe<'lua>(
+    lua_ctx: Context<'lua>,
+    topic: String,
+    handler: rlua::Function<'lua>,
+) -> rlua::Result<()> {
+    let subscriptions: Table = match lua_ctx.named_registry_value::<_, Table>(SUBSCRIPTIONS_KEY) {
+        Ok(table) => table,
+
+        Err(_) => {
+            let table = lua_ctx.create_table()?;
+            lua_ctx.set_named_registry_value(SUBSCRIPTIONS_KEY, table.clone())?;
+
+            table
+        }
+    };
+
+    let handlers: Table = match subscriptions.get::<_, Option<Table>>(topic.as_str())? {
+        Some(handlers) => handlers,
+
+        None => {
+            let handlers = lua_ctx.create_table()?;
+            subscriptions.set(topic.as_str(), handlers.clone())?;
+
+            handlers
+        }
+    };
+
+    handlers.set(handlers.len()? + 1, handler)?;
+
+    Ok(())
+}
+
+/// Call all handlers of the VM of `lua_ctx` that are subscribed to `topic`
+pub fn dispatch(lua_ctx: Context, topic: &str, payload: &BusValue) -> rlua::Result<()> {
+    if let Ok(subscriptions) = lua_ctx.named_registry_value::<_, Table>(SUBSCRIPTIONS_KEY) {
+        if let Some(handlers) = subscriptions.get::<_, Option<Table>>(topic)? {
+            for handler in handlers.sequence_values::<rlua::Function>() {
+                handler?.call::<_, ()>((topic, payload.to_lua(lua_ctx)?))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_bus_value_round_trip() {
+    let lua = rlua::Lua::new();
+
+    lua.context(|lua_ctx| {
+        let value: Value = lua_ctx
+            .load(r#"{ keys = { 1, 2, 3 }, color = 0xffff0000, name = "test", nested = { ok = true } }"#)
+            .eval()
+            .unwrap();
+
+        let bus_value = BusValue::from_lua(value, 0).unwrap();
+        let table: Table = match bus_value.to_lua(lua_ctx).unwrap() {
+            Value::Table(table) => table,
+            _ => panic!("Not a table"),
+        };
+
+        assert_eq!(table.get::<_, String>("name").unwrap(), "test");
+        assert_eq!(table.get::<_, u32>("color").unwrap(), 0xffff0000);
+        assert_eq!(table.get::<_, Vec<u8>>("keys").unwrap(), vec![1, 2, 3]);
+        assert!(table.get::<_, Table>("nested").unwrap().get::<_, bool>("ok").unwrap());
+    });
+}
@@ -0,0 +1,534 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use lazy_static::lazy_static;
+use log::*;
+use nix::sys::socket::{getsockopt, sockopt};
+use parking_lot::Mutex;
+use rlua::{Context, Function, MultiValue, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::constants;
+
+pub type Result<T> = std::result::Result<T, DebuggerError>;
+
+#[derive(Debug, Fail)]
+pub enum DebuggerError {
+    #[fail(display = "Could not create the debugger socket: {}", msg)]
+    SocketError { msg: String },
+
+    #[fail(display = "Could not spawn the debugger thread")]
+    ThreadSpawnError {},
+}
+
+const HELP: &str = "Available commands:
+  list                List the running VMs
+  attach <script>     Attach to the VM running <script>, e.g. 'attach batique.lua'
+  detach              Detach from the current VM
+  eval <code>         Evaluate Lua code in the context of the VM
+  globals             List the global variables of the VM
+  break <handler>     Pause the VM before calling <handler>, e.g. 'break on_key_down'
+  delete <handler>    Remove the breakpoint on <handler>
+  breakpoints         List the breakpoints of the VM
+  continue            Resume the VM after a breakpoint has been hit
+  help                Show this help
+  quit                Close the connection";
+
+/// Requests that are processed by a VM on behalf of a debugger session
+enum DebugRequest {
+    Eval(String, Sender<String>),
+    Globals(Sender<String>),
+    Continue,
+}
+
+/// Debugger related state of a running VM
+struct VmState {
+    name: String,
+    tx: Sender<DebugRequest>,
+    breakpoints: HashSet<String>,
+
+    /// Output channels of the attached sessions, by session id
+    sessions: Vec<(usize, Sender<String>)>,
+}
+
+lazy_static! {
+    /// All VMs that can be attached to, by VM id
+    static ref VMS: Mutex<HashMap<usize, VmState>> = Mutex::new(HashMap::new());
+}
+
+/// Set when the debugger socket is available, VMs only register themselves if it is set
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Total number of breakpoints, used to skip the lookup of breakpoints if none are set
+static NUM_BREAKPOINTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Used to generate VM and session ids
+static ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Id and request channel of the VM running on the current thread
+    static CURRENT_VM: RefCell<Option<(usize, Receiver<DebugRequest>)>> = RefCell::new(None);
+}
+
+/// Unregisters the VM of the current thread when dropped
+pub struct VmGuard {
+    id: usize,
+}
+
+impl Drop for VmGuard {
+    fn drop(&mut self) {
+        let mut vms = VMS.lock();
+
+        if let Some(vm) = vms.remove(&self.id) {
+            for (_, session) in vm.sessions.iter() {
+                session
+                    .send(format!("VM '{}' terminated", vm.name))
+                    .unwrap_or_default();
+            }
+        }
+
+        update_breakpoint_count(&vms);
+
+        CURRENT_VM.with(|c| *c.borrow_mut() = None);
+    }
+}
+
+/// Make the VM running on the current thread available to debugger sessions
+pub fn register_vm(name: &str) -> Option<VmGuard> {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    let id = ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let (tx, rx) = channel();
+
+    VMS.lock().insert(
+        id,
+        VmState {
+            name: name.to_string(),
+            tx,
+            breakpoints: HashSet::new(),
+            sessions: vec![],
+        },
+    );
+
+    CURRENT_VM.with(|c| *c.borrow_mut() = Some((id, rx)));
+
+    Some(VmGuard { id })
+}
+
+/// Process pending requests of debugger sessions, without blocking
+pub fn process_requests(lua_ctx: Context) {
+    CURRENT_VM.with(|c| {
+        if let Some((_, rx)) = &*c.borrow() {
+            while let Ok(request) = rx.try_recv() {
+                handle_request(lua_ctx, request);
+            }
+        }
+    });
+}
+
+/// Pause the VM of the current thread if a breakpoint is set on `handler`,
+/// until an attached session continues its execution
+pub fn check_breakpoint(lua_ctx: Context, handler: &str) {
+    if NUM_BREAKPOINTS.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    CURRENT_VM.with(|c| {
+        if let Some((id, rx)) = &*c.borrow() {
+            let hit = VMS
+                .lock()
+                .get(id)
+                .map(|vm| vm.breakpoints.contains(handler))
+                .unwrap_or(false);
+
+            if hit {
+                notify(*id, &format!("Breakpoint hit: {}", handler));
+
+                loop {
+                    match rx.recv() {
+                        Ok(DebugRequest::Continue) | Err(_) => break,
+                        Ok(request) => handle_request(lua_ctx, request),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Send a log message of the VM of the current thread to the attached sessions
+pub fn forward_log(level: &str, msg: &str) {
+    CURRENT_VM.with(|c| {
+        if let Some((id, _)) = &*c.borrow() {
+            notify(*id, &format!("[{}] {}", level, msg));
+        }
+    });
+}
+
+fn notify(id: usize, msg: &str) {
+    if let Some(vm) = VMS.lock().get(&id) {
+        for (_, session) in vm.sessions.iter() {
+            session.send(msg.to_string()).unwrap_or_default();
+        }
+    }
+}
+
+fn update_breakpoint_count(vms: &HashMap<usize, VmState>) {
+    let count = vms.values().map(|vm| vm.breakpoints.len()).sum();
+    NUM_BREAKPOINTS.store(count, Ordering::SeqCst);
+}
+
+fn handle_request(lua_ctx: Context, request: DebugRequest) {
+    match request {
+        DebugRequest::Eval(code, reply) => reply.send(eval(lua_ctx, &code)).unwrap_or_default(),
+        DebugRequest::Globals(reply) => reply.send(list_globals(lua_ctx)).unwrap_or_default(),
+
+        // not paused, nothing to do
+        DebugRequest::Continue => {}
+    }
+}
+
+fn eval(lua_ctx: Context, code: &str) -> String {
+    // evaluate as an expression first, then as a statement
+    let result = match lua_ctx
+        .load(&format!("return {}", code))
+        .eval::<MultiValue>()
+    {
+        Err(rlua::Error::SyntaxError { .. }) => lua_ctx.load(code).eval::<MultiValue>(),
+        result => result,
+    };
+
+    match result {
+        Ok(values) => values
+            .into_iter()
+            .map(|v| to_string(lua_ctx, v))
+            .collect::<Vec<String>>()
+            .join("\t"),
+
+        Err(e) => format!("Lua error: {}", e),
+    }
+}
+
+fn to_string(lua_ctx: Context, value: Value) -> String {
+    lua_ctx
+        .globals()
+        .get::<_, Function>("tostring")
+        .and_then(|f| f.call::<_, String>(value))
+        .unwrap_or_else(|e| format!("<{}>", e))
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Nil => "nil",
+        Value::Boolean(_) => "boolean",
+        Value::LightUserData(_) => "lightuserdata",
+        Value::Integer(_) => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Table(_) => "table",
+        Value::Function(_) => "function",
+        Value::Thread(_) => "thread",
+        Value::UserData(_) => "userdata",
+        Value::Error(_) => "error",
+    }
+}
+
+fn list_globals(lua_ctx: Context) -> String {
+    let mut result: Vec<String> = lua_ctx
+        .globals()
+        .pairs::<Value, Value>()
+        .filter_map(|pair| pair.ok())
+        .filter_map(|(k, v)| match k {
+            Value::String(s) => s.to_str().ok().map(|k| format!("{}: {}", k, type_name(&v))),
+            _ => None,
+        })
+        .collect();
+
+    result.sort();
+    result.join("\n")
+}
+
+/// Create the debugger socket and spawn the thread that accepts connections
+pub fn spawn_debugger_thread(socket_path: &Path) -> Result<()> {
+    // remove a stale socket of a previous instance
+    if socket_path.exists() {
+        fs::remove_file(socket_path).map_err(|e| DebuggerError::SocketError {
+            msg: format!("{}", e),
+        })?;
+    }
+
+    if let Some(dir) = socket_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| DebuggerError::SocketError {
+            msg: format!("{}", e),
+        })?;
+    }
+
+    let listener = UnixListener::bind(socket_path).map_err(|e| DebuggerError::SocketError {
+        msg: format!("{}", e),
+    })?;
+
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600)).map_err(|e| {
+        DebuggerError::SocketError {
+            msg: format!("{}", e),
+        }
+    })?;
+
+    ENABLED.store(true, Ordering::SeqCst);
+
+    thread::Builder::new()
+        .name("debugger".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if is_authorized(&stream) {
+                            if let Err(e) = thread::Builder::new()
+                                .name("debugger/session".to_owned())
+                                .spawn(move || run_session(stream))
+                            {
+                                error!("Could not spawn a debugger session: {}", e);
+                            }
+                        } else {
+                            warn!("Refused a debugger connection of an unprivileged user");
+                        }
+                    }
+
+                    Err(e) => error!("Could not accept a debugger connection: {}", e),
+                }
+            }
+        })
+        .map_err(|_e| DebuggerError::ThreadSpawnError {})?;
+
+    Ok(())
+}
+
+/// Only root and the user running the daemon may attach to VMs
+fn is_authorized(stream: &UnixStream) -> bool {
+    match getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials) {
+        Ok(credentials) => {
+            credentials.uid() == 0 || credentials.uid() == nix::unistd::geteuid().as_raw()
+        }
+
+        Err(e) => {
+            error!(
+                "Could not get the credentials of a debugger connection: {}",
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Find the most recently started VM running the script `name`
+fn find_vm(vms: &HashMap<usize, VmState>, name: &str) -> Option<usize> {
+    vms.iter()
+        .filter(|(_, vm)| vm.name == name)
+        .map(|(id, _)| *id)
+        .max()
+}
+
+/// Send `request` to the VM `id` and wait for its reply
+fn request(id: usize, make_request: impl FnOnce(Sender<String>) -> DebugRequest) -> String {
+    let (reply_tx, reply_rx) = channel();
+
+    let sent = VMS
+        .lock()
+        .get(&id)
+        .map(|vm| vm.tx.send(make_request(reply_tx)).is_ok())
+        .unwrap_or(false);
+
+    if !sent {
+        return "VM is not running".to_string();
+    }
+
+    // requests are processed by the VM when it receives its next event
+    reply_rx
+        .recv_timeout(Duration::from_millis(constants::DEBUGGER_TIMEOUT_MILLIS))
+        .unwrap_or_else(|_| "Timeout: VM did not respond".to_string())
+}
+
+fn detach(session_id: usize, id: usize) {
+    let mut vms = VMS.lock();
+
+    if let Some(vm) = vms.get_mut(&id) {
+        vm.sessions.retain(|(s, _)| *s != session_id);
+
+        // don't leave a VM paused without anyone to continue it
+        if vm.sessions.is_empty() {
+            vm.breakpoints.clear();
+            vm.tx.send(DebugRequest::Continue).unwrap_or_default();
+        }
+    }
+
+    update_breakpoint_count(&vms);
+}
+
+fn run_session(stream: UnixStream) {
+    let session_id = ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    // all output is written by a separate thread, since VMs send
+    // log messages and breakpoint notifications asynchronously
+    let (out_tx, out_rx) = channel::<String>();
+
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+
+        Err(e) => {
+            error!("Could not set up a debugger session: {}", e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for msg in out_rx.iter() {
+            if writeln!(writer, "{}", msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    info!("Debugger session {} started", session_id);
+
+    let reply = |msg: String| out_tx.send(msg).unwrap_or_default();
+    reply("Eruption debugger, type 'help' for a list of commands".to_string());
+
+    let mut attached: Option<usize> = None;
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let line = line.trim();
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, ""),
+        };
+
+        match (command, attached) {
+            ("", _) => {}
+
+            ("help", _) => reply(HELP.to_string()),
+
+            ("quit", _) => break,
+
+            ("list", _) => {
+                let vms = VMS.lock();
+                let mut names: Vec<String> = vms
+                    .iter()
+                    .map(|(id, vm)| {
+                        let marker = if Some(*id) == attached { "*" } else { " " };
+                        format!("{} {}", marker, vm.name)
+                    })
+                    .collect();
+
+                names.sort();
+                reply(names.join("\n"));
+            }
+
+            ("attach", _) => {
+                if let Some(id) = attached.take() {
+                    detach(session_id, id);
+                }
+
+                let mut vms = VMS.lock();
+                match find_vm(&vms, arg) {
+                    Some(id) => {
+                        vms.get_mut(&id)
+                            .unwrap()
+                            .sessions
+                            .push((session_id, out_tx.clone()));
+
+                        attached = Some(id);
+                        reply(format!("Attached to '{}'", arg));
+                    }
+
+                    None => reply(format!("No such VM: '{}'", arg)),
+                }
+            }
+
+            (_, None) => reply("Not attached to a VM, please use 'attach' first".to_string()),
+
+            ("detach", Some(id)) => {
+                detach(session_id, id);
+                attached = None;
+            }
+
+            ("eval", Some(id)) => {
+                let code = arg.to_string();
+                let result = request(id, |reply_tx| DebugRequest::Eval(code, reply_tx));
+
+                if !result.is_empty() {
+                    reply(result);
+                }
+            }
+
+            ("globals", Some(id)) => reply(request(id, DebugRequest::Globals)),
+
+            ("break", Some(id)) | ("delete", Some(id)) => {
+                let mut vms = VMS.lock();
+
+                if let Some(vm) = vms.get_mut(&id) {
+                    if command == "break" {
+                        vm.breakpoints.insert(arg.to_string());
+                    } else {
+                        vm.breakpoints.remove(arg);
+                    }
+                }
+
+                update_breakpoint_count(&vms);
+            }
+
+            ("breakpoints", Some(id)) => {
+                if let Some(vm) = VMS.lock().get(&id) {
+                    let mut breakpoints: Vec<String> = vm.breakpoints.iter().cloned().collect();
+
+                    breakpoints.sort();
+                    reply(breakpoints.join("\n"));
+                }
+            }
+
+            ("continue", Some(id)) => {
+                if let Some(vm) = VMS.lock().get(&id) {
+                    vm.tx.send(DebugRequest::Continue).unwrap_or_default();
+                }
+            }
+
+            (_, Some(_)) => reply(format!("Unknown command: '{}'", command)),
+        }
+    }
+
+    if let Some(id) = attached {
+        detach(session_id, id);
+    }
+
+    info!("Debugger session {} ended", session_id);
+}
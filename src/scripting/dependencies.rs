@@ -0,0 +1,372 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::profiles::Profile;
+use crate::scripting::manifest::Manifest;
+use crate::scripting::native;
+
+pub type Result<T> = std::result::Result<T, DependencyError>;
+
+#[derive(Debug, Fail)]
+pub enum DependencyError {
+    #[fail(display = "Could not load the manifest of script '{}'", script)]
+    ManifestError { script: String },
+
+    #[fail(
+        display = "Module '{}' required by '{}' could not be found in the library path",
+        module, required_by
+    )]
+    MissingModule { module: String, required_by: String },
+
+    #[fail(display = "Could not read module '{}'", module)]
+    ReadError { module: String },
+
+    #[fail(display = "Cyclic dependency between modules: {}", cycle)]
+    CyclicDependency { cycle: String },
+}
+
+/// Get the library search path. Directories listed in `global.library_path`
+/// take precedence over the `lib` directory below `script_dir`
+pub fn get_library_path(script_dir: &Path) -> Vec<PathBuf> {
+    let mut result: Vec<PathBuf> = crate::CONFIG
+        .lock()
+        .as_ref()
+        .and_then(|c| c.get_array("global.library_path").ok())
+        .map(|a| {
+            a.into_iter()
+                .filter_map(|v| v.into_str().ok())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_else(Vec::new);
+
+    result.push(script_dir.join("lib"));
+
+    result
+}
+
+/// Format the library search path for use as Lua's `package.path`
+pub fn get_lua_package_path(library_path: &[PathBuf]) -> String {
+    library_path
+        .iter()
+        .map(|dir| format!("{0}/?.lua;{0}/?", dir.display()))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// Find the file of the library module `module`, the same way `require` would
+pub fn find_module(module: &str, library_path: &[PathBuf]) -> Option<PathBuf> {
+    library_path
+        .iter()
+        .flat_map(|dir| vec![dir.join(format!("{}.lua", module)), dir.join(module)])
+        .find(|p| p.is_file())
+}
+
+/// If a long bracket, e.g. `[[` or `[==[`, starts at `pos`, returns its level
+fn get_long_bracket_level(source: &[char], pos: usize) -> Option<usize> {
+    if source.get(pos) != Some(&'[') {
+        return None;
+    }
+
+    let level = source[pos + 1..].iter().take_while(|c| **c == '=').count();
+
+    if source.get(pos + 1 + level) == Some(&'[') {
+        Some(level)
+    } else {
+        None
+    }
+}
+
+/// Skip a long string or long comment of `level`, returns the position after it
+fn skip_long_bracket(source: &[char], pos: usize, level: usize) -> usize {
+    let mut closing = vec![']'];
+    closing.extend(std::iter::repeat('=').take(level));
+    closing.push(']');
+
+    (pos + level + 2..source.len())
+        .find(|i| source[*i..].starts_with(&closing))
+        .map_or(source.len(), |i| i + closing.len())
+}
+
+/// Read the string literal that starts at `pos`, returns its contents and the
+/// position after it
+fn read_string(source: &[char], pos: usize) -> (String, usize) {
+    let quote = source[pos];
+    let mut result = String::new();
+
+    let mut i = pos + 1;
+    while i < source.len() {
+        match source[i] {
+            '\\' => {
+                if let Some(c) = source.get(i + 1) {
+                    result.push(*c);
+                }
+
+                i += 2;
+            }
+
+            '\n' => break,
+
+            c if c == quote => return (result, i + 1),
+
+            c => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    (result, i)
+}
+
+/// Extract the names of all modules that are imported with a string literal,
+/// e.g. `require "declarations"` or `require("themes/default")`. Comments
+/// and the contents of strings are skipped
+pub fn scan_requires(source: &str) -> Vec<String> {
+    let source: Vec<char> = source.chars().collect();
+    let mut result = vec![];
+
+    let mut i = 0;
+    while i < source.len() {
+        let c = source[i];
+
+        if c == '-' && source.get(i + 1) == Some(&'-') {
+            // comments, either up to the end of the line or long comments
+            i += 2;
+
+            match get_long_bracket_level(&source, i) {
+                Some(level) => i = skip_long_bracket(&source, i, level),
+
+                None => {
+                    while i < source.len() && source[i] != '\n' {
+                        i += 1;
+                    }
+                }
+            }
+        } else if c == '"' || c == '\'' {
+            i = read_string(&source, i).1;
+        } else if let Some(level) = get_long_bracket_level(&source, i) {
+            i = skip_long_bracket(&source, i, level);
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < source.len() && (source[i].is_alphanumeric() || source[i] == '_') {
+                i += 1;
+            }
+
+            if source[start..i].iter().collect::<String>() != "require" {
+                continue;
+            }
+
+            let skip_whitespace = |mut i: usize| {
+                while i < source.len() && source[i].is_whitespace() {
+                    i += 1;
+                }
+
+                i
+            };
+
+            let mut arg = skip_whitespace(i);
+            if source.get(arg) == Some(&'(') {
+                arg = skip_whitespace(arg + 1);
+            }
+
+            if let Some('"') | Some('\'') = source.get(arg) {
+                let (module, end) = read_string(&source, arg);
+
+                result.push(module);
+                i = end;
+            }
+        } else {
+            i += 1;
+        }
+    }
+
+    result
+}
+
+/// Get the imports of a script, as overridden by `profile`, or the defaults from the manifest
+pub fn get_imports(manifest: &Manifest, profile: Option<&Profile>) -> Vec<String> {
+    profile
+        .and_then(|p| p.imports.get(&manifest.name))
+        .cloned()
+        .unwrap_or_else(|| manifest.imports.clone())
+}
+
+/// Depth first traversal of the module graph
+struct Resolver<'a> {
+    library_path: &'a [PathBuf],
+    stack: Vec<String>,
+    done: HashSet<String>,
+    result: Vec<PathBuf>,
+}
+
+impl<'a> Resolver<'a> {
+    fn visit(&mut self, module: &str, required_by: &str) -> Result<()> {
+        if self.done.contains(module) {
+            return Ok(());
+        }
+
+        if let Some(pos) = self.stack.iter().position(|m| m == module) {
+            let mut cycle = self.stack[pos..].to_vec();
+            cycle.push(module.to_string());
+
+            return Err(DependencyError::CyclicDependency {
+                cycle: cycle.join(" -> "),
+            });
+        }
+
+        let file = find_module(module, self.library_path).ok_or_else(|| {
+            DependencyError::MissingModule {
+                module: module.to_string(),
+                required_by: required_by.to_string(),
+            }
+        })?;
+
+        let source = fs::read_to_string(&file).map_err(|_e| DependencyError::ReadError {
+            module: module.to_string(),
+        })?;
+
+        self.stack.push(module.to_string());
+
+        for dependency in scan_requires(&source) {
+            self.visit(&dependency, module)?;
+        }
+
+        self.stack.pop();
+
+        self.done.insert(module.to_string());
+        self.result.push(file);
+
+        Ok(())
+    }
+}
+
+/// Resolve the dependencies and imports of a script, including transitive
+/// dependencies. Returns the module files, dependencies come first
+pub fn resolve_dependencies(
+    manifest: &Manifest,
+    profile: Option<&Profile>,
+    library_path: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    let mut resolver = Resolver {
+        library_path,
+        stack: vec![],
+        done: HashSet::new(),
+        result: vec![],
+    };
+
+    for module in manifest
+        .dependencies
+        .iter()
+        .chain(get_imports(manifest, profile).iter())
+    {
+        resolver.visit(module, &manifest.name)?;
+    }
+
+    // modules that are required by the script itself, but have not been declared
+    if !native::is_native_effect(&manifest.script_file) {
+        let source =
+            fs::read_to_string(&manifest.script_file).map_err(|_e| DependencyError::ReadError {
+                module: manifest.script_file.display().to_string(),
+            })?;
+
+        for module in scan_requires(&source) {
+            resolver.visit(&module, &manifest.name)?;
+        }
+    }
+
+    Ok(resolver.result)
+}
+
+/// Verify that the dependencies of all active scripts of `profile` can be resolved
+pub fn check_profile(profile: &Profile, script_dir: &Path) -> Result<()> {
+    let library_path = get_library_path(script_dir);
+
+    for script_file in profile.active_scripts.iter() {
+        let manifest = Manifest::from(&script_dir.join(script_file)).map_err(|_e| {
+            DependencyError::ManifestError {
+                script: script_file.display().to_string(),
+            }
+        })?;
+
+        resolve_dependencies(&manifest, Some(profile), &library_path)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_scan_requires() {
+    let source = r#"
+        require "declarations"
+        require('themes/default') -- require "commented"
+        require(requires)
+        -- require "debug"
+    "#;
+
+    assert_eq!(
+        scan_requires(source),
+        vec!["declarations", "themes/default"]
+    );
+}
+
+#[test]
+fn test_scan_requires_strings_and_comments() {
+    let source = r#"
+        local separator = "--" require "declarations"
+        print("require 'quoted'")
+        --[[ require "block comment"
+        ]] require [[long]] require 'debug'
+    "#;
+
+    assert_eq!(scan_requires(source), vec!["declarations", "debug"]);
+}
+
+#[test]
+fn test_undeclared_requires() {
+    let dir = std::env::temp_dir().join(format!("eruption-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let script_file = dir.join("undeclared.lua");
+    fs::write(&script_file, "require \"missing_module\"\n").unwrap();
+
+    let mut manifest: Manifest = toml::from_str(
+        r#"
+        name = "Undeclared"
+        description = "Requires a module that has not been declared"
+        version = "0.0.1"
+        author = "The Eruption development team"
+        min_supported_version = "0.1.11"
+        "#,
+    )
+    .unwrap();
+    manifest.script_file = script_file.clone();
+
+    let result = resolve_dependencies(&manifest, None, &[dir.clone()]);
+
+    fs::remove_dir_all(&dir).unwrap();
+
+    match result {
+        Err(DependencyError::MissingModule { module, .. }) => assert_eq!(module, "missing_module"),
+        _ => panic!("Expected a missing module"),
+    }
+}
@@ -0,0 +1,263 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use rlua::{Context, Table};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hwdevices::layout::{self, KeyboardVariant};
+use crate::hwdevices::HidEvent;
+use crate::plugins::keyboard;
+use crate::scripting::script::Tick;
+use crate::util;
+use crate::SystemEvent;
+
+/// Modifier keys, as reported in the `modifiers` field of key events
+const MODIFIERS: &[(&str, &[&str])] = &[
+    ("shift", &["LEFT_SHIFT", "RIGHT_SHIFT"]),
+    ("ctrl", &["LEFT_CTRL", "RIGHT_CTRL"]),
+    ("alt", &["LEFT_ALT", "RIGHT_ALT"]),
+    ("meta", &["LEFT_META"]),
+];
+
+/// Create an event table with the common fields `type`, `device` and `timestamp`
+fn new_event<'lua>(
+    lua_ctx: Context<'lua>,
+    event_type: &str,
+    device: &str,
+) -> rlua::Result<Table<'lua>> {
+    let event = lua_ctx.create_table()?;
+
+    // milliseconds since the UNIX epoch, at the time the event has been dispatched
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+
+    event.set("type", event_type)?;
+    event.set("device", device)?;
+    event.set("timestamp", timestamp)?;
+
+    Ok(event)
+}
+
+/// Name of the key with the 1-based key index `key_index`
+fn get_key_name(key_index: u8) -> Option<&'static str> {
+    if key_index == 0 {
+        return None;
+    }
+
+    layout::get_key(KeyboardVariant::from_config(), key_index as usize - 1).map(|k| k.name)
+}
+
+fn is_key_pressed(variant: KeyboardVariant, name: &str) -> bool {
+    layout::find_key(variant, name)
+        .and_then(|k| {
+            keyboard::KEY_STATES
+                .read()
+                .ok()
+                .and_then(|states| states.get(k.index + 1).copied())
+        })
+        .unwrap_or(false)
+}
+
+fn get_modifiers(lua_ctx: Context) -> rlua::Result<Table> {
+    let variant = KeyboardVariant::from_config();
+    let modifiers = lua_ctx.create_table()?;
+
+    for (modifier, keys) in MODIFIERS.iter() {
+        modifiers.set(*modifier, keys.iter().any(|k| is_key_pressed(variant, k)))?;
+    }
+
+    Ok(modifiers)
+}
+
+fn set_key_fields(event: &Table, lua_ctx: Context, key_index: u8) -> rlua::Result<()> {
+    event.set("key_index", key_index)?;
+    event.set("key_name", get_key_name(key_index))?;
+    event.set("modifiers", get_modifiers(lua_ctx)?)?;
+
+    Ok(())
+}
+
+/// Event table of `on_key_down` and `on_key_up`
+pub fn key_event<'lua>(
+    lua_ctx: Context<'lua>,
+    event_type: &str,
+    key_index: u8,
+) -> rlua::Result<Table<'lua>> {
+    let event = new_event(lua_ctx, event_type, "keyboard")?;
+    set_key_fields(&event, lua_ctx, key_index)?;
+
+    Ok(event)
+}
+
+/// Event table of `on_hid_event`
+pub fn hid_event<'lua>(lua_ctx: Context<'lua>, hid_event: &HidEvent) -> rlua::Result<Table<'lua>> {
+    let event = match hid_event {
+        HidEvent::KeyDown { code } | HidEvent::KeyUp { code } => {
+            let event_type = if let HidEvent::KeyDown { .. } = hid_event {
+                "key_down"
+            } else {
+                "key_up"
+            };
+
+            let event = new_event(lua_ctx, event_type, "keyboard")?;
+            set_key_fields(&event, lua_ctx, util::hid_code_to_key_index(*code))?;
+
+            event
+        }
+
+        HidEvent::MuteDown | HidEvent::MuteUp => {
+            let event = new_event(lua_ctx, "mute", "keyboard")?;
+            event.set("down", *hid_event == HidEvent::MuteDown)?;
+
+            event
+        }
+
+        HidEvent::VolumeDown | HidEvent::VolumeUp => {
+            let event = new_event(lua_ctx, "volume", "keyboard")?;
+            event.set("down", *hid_event == HidEvent::VolumeDown)?;
+
+            event
+        }
+
+        HidEvent::Unknown => new_event(lua_ctx, "unknown", "keyboard")?,
+    };
+
+    Ok(event)
+}
+
+/// Event table of `on_mouse_button_down` and `on_mouse_button_up`
+pub fn mouse_button_event<'lua>(
+    lua_ctx: Context<'lua>,
+    event_type: &str,
+    button_index: u8,
+) -> rlua::Result<Table<'lua>> {
+    let event = new_event(lua_ctx, event_type, "mouse")?;
+    event.set("button_index", button_index)?;
+    event.set("modifiers", get_modifiers(lua_ctx)?)?;
+
+    Ok(event)
+}
+
+/// Event table of `on_mouse_move`
+pub fn mouse_move_event(
+    lua_ctx: Context,
+    rel_x: i32,
+    rel_y: i32,
+    rel_z: i32,
+) -> rlua::Result<Table> {
+    let event = new_event(lua_ctx, "mouse_move", "mouse")?;
+    event.set("rel_x", rel_x)?;
+    event.set("rel_y", rel_y)?;
+    event.set("rel_z", rel_z)?;
+
+    Ok(event)
+}
+
+/// Event table of `on_mouse_wheel`
+pub fn mouse_wheel_event(lua_ctx: Context, direction: u8) -> rlua::Result<Table> {
+    let event = new_event(lua_ctx, "mouse_wheel", "mouse")?;
+    event.set("direction", direction)?;
+
+    Ok(event)
+}
+
+/// Event table of `on_tick`
+pub fn tick_event<'lua>(lua_ctx: Context<'lua>, tick: &Tick) -> rlua::Result<Table<'lua>> {
+    let event = new_event(lua_ctx, "tick", "timer")?;
+    event.set("frame", tick.frame)?;
+    event.set("delta", tick.delta)?;
+    event.set("time", tick.time)?;
+
+    Ok(event)
+}
+
+/// Event table of `on_system_event`
+pub fn system_event<'lua>(
+    lua_ctx: Context<'lua>,
+    system_event: &SystemEvent,
+) -> rlua::Result<Table<'lua>> {
+    let (event_type, process, file_name) = match system_event {
+        SystemEvent::ProcessExec { event, file_name } => ("process_exec", event, file_name),
+        SystemEvent::ProcessExit { event, file_name } => ("process_exit", event, file_name),
+    };
+
+    let event = new_event(lua_ctx, event_type, "system")?;
+    event.set("pid", process.pid)?;
+    event.set("ppid", process.ppid)?;
+    event.set("tgid", process.tgid)?;
+    event.set("file_name", file_name.clone())?;
+    event.set("name_hash", file_name.as_ref().map(|f| util::hash_name(f)))?;
+
+    Ok(event)
+}
+
+#[test]
+fn test_key_event() {
+    let lua = rlua::Lua::new();
+
+    lua.context(|lua_ctx| {
+        let event = key_event(lua_ctx, "key_down", 1).unwrap();
+
+        assert_eq!(event.get::<_, String>("type").unwrap(), "key_down");
+        assert_eq!(event.get::<_, String>("device").unwrap(), "keyboard");
+        assert!(event.get::<_, i64>("timestamp").unwrap() > 0);
+        assert_eq!(event.get::<_, u8>("key_index").unwrap(), 1);
+        assert_eq!(event.get::<_, String>("key_name").unwrap(), "ESC");
+
+        // key states are indexed by the 1-based key index
+        let shift = layout::find_key(KeyboardVariant::from_config(), "LEFT_SHIFT").unwrap();
+        keyboard::KEY_STATES.write().unwrap()[shift.index + 1] = true;
+
+        let modifiers: Table = key_event(lua_ctx, "key_down", 1)
+            .unwrap()
+            .get("modifiers")
+            .unwrap();
+
+        keyboard::KEY_STATES.write().unwrap()[shift.index + 1] = false;
+
+        assert!(modifiers.get::<_, bool>("shift").unwrap());
+        assert!(!modifiers.get::<_, bool>("ctrl").unwrap());
+
+        // there is no key with the index 0
+        let event = key_event(lua_ctx, "key_up", 0).unwrap();
+        assert_eq!(event.get::<_, Option<String>>("key_name").unwrap(), None);
+    });
+}
+
+#[test]
+fn test_tick_event() {
+    let lua = rlua::Lua::new();
+
+    lua.context(|lua_ctx| {
+        let tick = Tick {
+            frame: 42,
+            delta: 0.5,
+            time: 21.0,
+            ticks: 12,
+        };
+
+        let event = tick_event(lua_ctx, &tick).unwrap();
+
+        assert_eq!(event.get::<_, String>("type").unwrap(), "tick");
+        assert_eq!(event.get::<_, String>("device").unwrap(), "timer");
+        assert_eq!(event.get::<_, u64>("frame").unwrap(), 42);
+        assert_eq!(event.get::<_, f64>("delta").unwrap(), 0.5);
+        assert_eq!(event.get::<_, f64>("time").unwrap(), 21.0);
+    });
+}
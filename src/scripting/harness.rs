@@ -0,0 +1,683 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Once};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::canvas;
+use crate::constants;
+use crate::hwdevices::{HwDevice, SimulatedDevice, NUM_KEYS, RGBA};
+use crate::plugins;
+use crate::profiles::Profile;
+use crate::scripting::bus;
+use crate::scripting::dependencies;
+use crate::scripting::native;
+use crate::scripting::random;
+use crate::scripting::script::{self, Message, RunScriptResult, Tick};
+
+/// Time to wait for a script to process an event
+const HARNESS_TIMEOUT_MILLIS: u64 = 5000;
+
+pub type Result<T> = std::result::Result<T, HarnessError>;
+
+#[derive(Debug, Fail)]
+pub enum HarnessError {
+    #[fail(display = "Script file or manifest inaccessible: {}", path)]
+    InaccessibleScript { path: String },
+
+    #[fail(display = "Script terminated with errors")]
+    ScriptError {},
+
+    #[fail(display = "Invalid profile: {}", msg)]
+    InvalidProfile { msg: String },
+
+    #[fail(display = "Timeout while waiting for the script")]
+    Timeout {},
+
+    #[fail(display = "Could not read or write golden frames: {}", msg)]
+    GoldenFramesError { msg: String },
+
+    #[fail(
+        display = "Frame {} differs from the golden frame at key {}: {} != {}",
+        frame, key, actual, expected
+    )]
+    FrameMismatch {
+        frame: usize,
+        key: usize,
+        actual: String,
+        expected: String,
+    },
+}
+
+/// A captured color map, as it would have been sent to the device
+pub type Frame = Vec<RGBA>;
+
+lazy_static! {
+    /// The harness uses the global canvas and upcall conditions, so only
+    /// one instance may be active at any given time
+    static ref HARNESS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+static REGISTER_PLUGINS: Once = Once::new();
+
+/// A script (or native effect) that is running on its own thread
+struct HarnessVm {
+    tx: Sender<Message>,
+    thread: Option<JoinHandle<()>>,
+    terminated: Arc<AtomicBool>,
+}
+
+/// Runs scripts (or native effects) headless, on a simulated device.
+/// Events are injected synchronously, and the resulting color maps are captured
+/// after each realization, just like the main loop of the daemon would do it
+pub struct ScriptHarness {
+    vms: Vec<HarnessVm>,
+    frames: Vec<Frame>,
+
+    /// Number of ticks that have been sent so far
+    frame: u64,
+
+    /// Simulated time, in units of `1 / TARGET_FPS` seconds
+    ticks: u64,
+
+    /// Simulated time, in seconds
+    time: f64,
+
+    /// Frame rate that is simulated by `step`
+    fps: u64,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl ScriptHarness {
+    /// Load the script `script_file`, using the parameters of `profile` or the
+    /// defaults from the script's manifest, if no profile has been specified
+    pub fn new(script_file: &Path, profile: Option<Profile>) -> Result<Self> {
+        Self::from_scripts(&[script_file.to_path_buf()], profile)
+    }
+
+    /// Load all active scripts of `profile`, from the directory `script_dir`
+    pub fn from_profile(profile: Profile, script_dir: &Path) -> Result<Self> {
+        profile
+            .validate(script_dir)
+            .map_err(|e| HarnessError::InvalidProfile { msg: e.to_string() })?;
+
+        dependencies::check_profile(&profile, script_dir)
+            .map_err(|e| HarnessError::InvalidProfile { msg: e.to_string() })?;
+
+        let script_files: Vec<PathBuf> = profile
+            .active_scripts
+            .iter()
+            .map(|s| script_dir.join(s))
+            .collect();
+
+        Self::from_scripts(&script_files, Some(profile))
+    }
+
+    /// Load the scripts `script_files`, layers are stacked in the specified order
+    pub fn from_scripts(script_files: &[PathBuf], profile: Option<Profile>) -> Result<Self> {
+        let guard = HARNESS_LOCK.lock();
+
+        for script_file in script_files.iter() {
+            if !crate::util::is_script_file_accessible(script_file)
+                || !crate::util::is_manifest_file_accessible(script_file)
+            {
+                return Err(HarnessError::InaccessibleScript {
+                    path: script_file.display().to_string(),
+                });
+            }
+        }
+
+        REGISTER_PLUGINS.call_once(|| {
+            plugins::register_headless_plugins()
+                .unwrap_or_else(|e| error!("Could not register plugins: {}", e))
+        });
+
+        *crate::ACTIVE_PROFILE.lock() = profile;
+        crate::ACTIVE_SCRIPTS.lock().clear();
+
+        // scripts get reproducible random numbers
+        random::set_default_seed(Some(constants::HARNESS_RANDOM_SEED));
+
+        let hwdevice: HwDevice = Arc::new(RwLock::new(SimulatedDevice::new()));
+
+        let mut vms = vec![];
+
+        for script_file in script_files.iter() {
+            let terminated = Arc::new(AtomicBool::new(false));
+
+            let (tx, rx) = channel();
+
+            let hwdevice = hwdevice.clone();
+            let script_path = script_file.clone();
+            let terminated_flag = terminated.clone();
+
+            let thread = thread::Builder::new()
+                .name(format!(
+                    "harness:{}",
+                    script_path.file_name().unwrap().to_string_lossy()
+                ))
+                .spawn(move || {
+                    let result = if native::is_native_effect(&script_path) {
+                        native::run_effect(script_path.clone(), &hwdevice, &rx)
+                    } else {
+                        script::run_script(script_path.clone(), &hwdevice, &rx)
+                    };
+
+                    match result {
+                        Ok(RunScriptResult::TerminatedGracefully) => (),
+
+                        Ok(RunScriptResult::TerminatedWithErrors) => {
+                            error!("Script '{}' terminated with errors", script_path.display())
+                        }
+
+                        Err(e) => error!("Script '{}': {}", script_path.display(), e),
+                    }
+
+                    terminated_flag.store(true, Ordering::SeqCst);
+                })
+                .map_err(|_e| HarnessError::ScriptError {})?;
+
+            vms.push(HarnessVm {
+                tx,
+                thread: Some(thread),
+                terminated,
+            });
+        }
+
+        Ok(Self {
+            vms,
+            frames: vec![],
+            frame: 0,
+            ticks: 0,
+            time: 0.0,
+            fps: constants::TARGET_FPS,
+            _guard: guard,
+        })
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.vms
+            .iter()
+            .any(|vm| vm.terminated.load(Ordering::SeqCst))
+    }
+
+    /// Send `msg` to the scripts `vms` and wait until all of them have processed it
+    fn send_and_wait(
+        &self,
+        vms: &[HarnessVm],
+        msg: Message,
+        condition: &(Mutex<usize>, Condvar),
+    ) -> Result<()> {
+        *condition.0.lock() = vms.len();
+
+        for vm in vms.iter() {
+            vm.tx
+                .send(msg.clone())
+                .map_err(|_e| HarnessError::ScriptError {})?;
+        }
+
+        let mut pending = condition.0.lock();
+        while *pending > 0 {
+            let result = condition
+                .1
+                .wait_for(&mut pending, Duration::from_millis(HARNESS_TIMEOUT_MILLIS));
+
+            if self.is_terminated() {
+                return Err(HarnessError::ScriptError {});
+            }
+
+            if result.timed_out() {
+                return Err(HarnessError::Timeout {});
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the frame rate that is simulated by `step` and `tick_frame`
+    pub fn set_frame_rate(&mut self, fps: u64) {
+        self.fps = fps.max(1);
+    }
+
+    /// Send a timer tick event, `delta` is the simulated time since the previous
+    /// tick, in units of `1 / TARGET_FPS` seconds
+    pub fn tick(&mut self, delta: u32) -> Result<()> {
+        self.advance(f64::from(delta) / constants::TARGET_FPS as f64)
+    }
+
+    /// Send a timer tick event, that advances the simulated time by one frame
+    /// at the frame rate of the harness
+    pub fn tick_frame(&mut self) -> Result<()> {
+        self.advance(1.0 / self.fps as f64)
+    }
+
+    /// Send a timer tick event, `delta` is the simulated time since the previous
+    /// tick, in seconds
+    fn advance(&mut self, delta: f64) -> Result<()> {
+        if self.is_terminated() {
+            return Err(HarnessError::ScriptError {});
+        }
+
+        // route messages that have been published since the last tick
+        for (topic, payload) in bus::take_pending() {
+            for vm in self.vms.iter() {
+                vm.tx
+                    .send(Message::BusMessage(topic.clone(), payload.clone()))
+                    .map_err(|_e| HarnessError::ScriptError {})?;
+            }
+        }
+
+        // ticks are derived from the simulated time, just like the main loop
+        // of the daemon does it. The small bias compensates for rounding errors
+        self.time += delta;
+        let total_ticks = (self.time * constants::TARGET_FPS as f64 + 1e-6) as u64;
+
+        let tick = Tick {
+            frame: self.frame,
+            delta,
+            time: self.time,
+            ticks: (total_ticks - self.ticks) as u32,
+        };
+
+        self.ticks = total_ticks;
+
+        self.frame += 1;
+
+        for vm in self.vms.iter() {
+            vm.tx
+                .send(Message::Tick(tick))
+                .map_err(|_e| HarnessError::ScriptError {})?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a key down event, `key_index` is the 1-based key index used by Lua scripts
+    pub fn key_down(&mut self, key_index: u8) -> Result<()> {
+        self.send_and_wait(
+            &self.vms,
+            Message::KeyDown(key_index),
+            &crate::UPCALL_COMPLETED_ON_KEY_DOWN,
+        )
+    }
+
+    /// Send a key up event, `key_index` is the 1-based key index used by Lua scripts
+    pub fn key_up(&mut self, key_index: u8) -> Result<()> {
+        self.send_and_wait(
+            &self.vms,
+            Message::KeyUp(key_index),
+            &crate::UPCALL_COMPLETED_ON_KEY_UP,
+        )
+    }
+
+    /// Send a mouse move event with relative coordinates
+    pub fn mouse_move(&mut self, rel_x: i32, rel_y: i32, rel_z: i32) -> Result<()> {
+        self.send_and_wait(
+            &self.vms,
+            Message::MouseMove(rel_x, rel_y, rel_z),
+            &crate::UPCALL_COMPLETED_ON_MOUSE_MOVE,
+        )
+    }
+
+    /// Realize the color maps of all scripts on a cleared canvas, and capture the result
+    pub fn capture_frame(&mut self) -> Result<Frame> {
+        let frame = self.composite_frame()?;
+        self.frames.push(frame.clone());
+
+        Ok(frame)
+    }
+
+    /// Realize the color maps of all scripts on a cleared canvas, and return
+    /// the result. The frame is not recorded in `get_frames`
+    pub fn composite_frame(&mut self) -> Result<Frame> {
+        *script::LED_MAP.write() = canvas::new_canvas();
+
+        // guarantee the right order of the alpha blend operations
+        for vm in self.vms.chunks(1) {
+            self.send_and_wait(
+                vm,
+                Message::RealizeColorMap,
+                &crate::COLOR_MAPS_READY_CONDITION,
+            )?;
+        }
+
+        Ok(canvas::to_led_map(&script::LED_MAP.read(), 1.0, None))
+    }
+
+    /// Realize only the color map of the script at `index` on a cleared canvas,
+    /// and return the result. The layer is not recorded in `get_frames`
+    pub fn capture_layer(&mut self, index: usize) -> Result<Frame> {
+        *script::LED_MAP.write() = canvas::new_canvas();
+
+        let vm = self
+            .vms
+            .get(index..=index)
+            .ok_or(HarnessError::ScriptError {})?;
+
+        self.send_and_wait(
+            vm,
+            Message::RealizeColorMap,
+            &crate::COLOR_MAPS_READY_CONDITION,
+        )?;
+
+        Ok(canvas::to_led_map(&script::LED_MAP.read(), 1.0, None))
+    }
+
+    /// Number of layers (scripts) that are stacked by the harness
+    pub fn get_num_layers(&self) -> usize {
+        self.vms.len()
+    }
+
+    /// Send a timer tick and capture the resulting frame
+    pub fn step(&mut self) -> Result<Frame> {
+        self.tick_frame()?;
+        self.capture_frame()
+    }
+
+    /// All frames that have been captured so far
+    pub fn get_frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+impl Drop for ScriptHarness {
+    fn drop(&mut self) {
+        for vm in self.vms.iter_mut() {
+            vm.tx.send(Message::Unload).unwrap_or_else(|e| {
+                error!("Could not send an event to the script: {}", e);
+            });
+
+            if let Some(thread) = vm.thread.take() {
+                thread
+                    .join()
+                    .unwrap_or_else(|_e| error!("Could not join the script thread"));
+            }
+        }
+    }
+}
+
+/// Format a frame as a line of hex color values (`rrggbb`, separated by spaces)
+pub fn frame_to_string(frame: &[RGBA]) -> String {
+    frame
+        .iter()
+        .map(|c| format!("{:02x}{:02x}{:02x}", c.r, c.g, c.b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Parse a frame from a line of hex color values
+pub fn frame_from_string(line: &str) -> Result<Frame> {
+    let result = line
+        .split_whitespace()
+        .map(|v| {
+            let c = u32::from_str_radix(v, 16).map_err(|_e| HarnessError::GoldenFramesError {
+                msg: format!("Invalid color value: {}", v),
+            })?;
+
+            Ok(RGBA {
+                r: ((c >> 16) & 0xff) as u8,
+                g: ((c >> 8) & 0xff) as u8,
+                b: (c & 0xff) as u8,
+                a: 0xff,
+            })
+        })
+        .collect::<Result<Frame>>()?;
+
+    if result.len() != NUM_KEYS {
+        return Err(HarnessError::GoldenFramesError {
+            msg: format!("Invalid number of keys: {}", result.len()),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Store `frames` as golden frames, one frame per line
+pub fn write_golden_frames<P: AsRef<Path>>(path: P, frames: &[Frame]) -> Result<()> {
+    let mut text = frames
+        .iter()
+        .map(|f| frame_to_string(&f))
+        .collect::<Vec<String>>()
+        .join("\n");
+    text.push('\n');
+
+    fs::write(path, text).map_err(|e| HarnessError::GoldenFramesError { msg: e.to_string() })
+}
+
+/// Load golden frames, empty lines and lines starting with `#` are ignored
+pub fn read_golden_frames<P: AsRef<Path>>(path: P) -> Result<Vec<Frame>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| HarnessError::GoldenFramesError { msg: e.to_string() })?;
+
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(frame_from_string)
+        .collect()
+}
+
+/// Compare `frames` to `golden`. Each color component may deviate by at most `tolerance`
+pub fn compare_frames(frames: &[Frame], golden: &[Frame], tolerance: u8) -> Result<()> {
+    if frames.len() != golden.len() {
+        return Err(HarnessError::GoldenFramesError {
+            msg: format!(
+                "Number of frames differs: {} != {}",
+                frames.len(),
+                golden.len()
+            ),
+        });
+    }
+
+    let differs = |a: u8, b: u8| (a as i16 - b as i16).abs() > tolerance as i16;
+
+    for (frame, (actual, expected)) in frames.iter().zip(golden.iter()).enumerate() {
+        for (key, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            if differs(a.r, e.r) || differs(a.g, e.g) || differs(a.b, e.b) {
+                return Err(HarnessError::FrameMismatch {
+                    frame,
+                    key,
+                    actual: format!("{:02x}{:02x}{:02x}", a.r, a.g, a.b),
+                    expected: format!("{:02x}{:02x}{:02x}", e.r, e.g, e.b),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Synthetic input events for `run_test`, applied before the tick of `frame`
+#[derive(Debug, Clone)]
+pub enum InputEvent {
+    KeyDown {
+        frame: usize,
+        key_index: u8,
+    },
+    KeyUp {
+        frame: usize,
+        key_index: u8,
+    },
+    MouseMove {
+        frame: usize,
+        rel_x: i32,
+        rel_y: i32,
+    },
+}
+
+impl InputEvent {
+    fn get_frame(&self) -> usize {
+        match *self {
+            InputEvent::KeyDown { frame, .. } => frame,
+            InputEvent::KeyUp { frame, .. } => frame,
+            InputEvent::MouseMove { frame, .. } => frame,
+        }
+    }
+}
+
+/// Run `script_file` for `num_frames` frames, injecting `events`, and return the captured frames
+pub fn run_test(
+    script_file: &Path,
+    profile: Option<Profile>,
+    num_frames: usize,
+    events: &[InputEvent],
+) -> Result<Vec<Frame>> {
+    let mut harness = ScriptHarness::new(script_file, profile)?;
+
+    for frame in 0..num_frames {
+        for event in events.iter().filter(|e| e.get_frame() == frame) {
+            match *event {
+                InputEvent::KeyDown { key_index, .. } => harness.key_down(key_index)?,
+                InputEvent::KeyUp { key_index, .. } => harness.key_up(key_index)?,
+                InputEvent::MouseMove { rel_x, rel_y, .. } => {
+                    harness.mouse_move(rel_x, rel_y, 0)?
+                }
+            }
+        }
+
+        harness.step()?;
+    }
+
+    Ok(harness.get_frames().to_vec())
+}
+
+/// Parse an event specification of the form `<frame>:<key_index>`
+fn parse_key_event(spec: &str) -> Option<(usize, u8)> {
+    let mut parts = spec.splitn(2, ':');
+
+    let frame = parts.next()?.parse().ok()?;
+    let key_index = parts.next()?.parse().ok()?;
+
+    Some((frame, key_index))
+}
+
+/// Entry point of the `test-script` subcommand, returns the process exit code
+pub fn run_cli(matches: &clap::ArgMatches) -> i32 {
+    let script_file = PathBuf::from(matches.value_of("SCRIPT").unwrap());
+
+    let profile = match matches.value_of("profile") {
+        Some(profile_file) => match Profile::from(&PathBuf::from(profile_file)) {
+            Ok(profile) => Some(profile),
+
+            Err(e) => {
+                error!("Could not load profile '{}': {}", profile_file, e);
+                return 2;
+            }
+        },
+
+        None => None,
+    };
+
+    let num_frames = matches
+        .value_of("frames")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+
+    let tolerance = matches
+        .value_of("tolerance")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+
+    let mut events = vec![];
+
+    for spec in matches.values_of("key-down").into_iter().flatten() {
+        match parse_key_event(spec) {
+            Some((frame, key_index)) => events.push(InputEvent::KeyDown { frame, key_index }),
+            None => {
+                error!("Invalid event specification: {}", spec);
+                return 2;
+            }
+        }
+    }
+
+    for spec in matches.values_of("key-up").into_iter().flatten() {
+        match parse_key_event(spec) {
+            Some((frame, key_index)) => events.push(InputEvent::KeyUp { frame, key_index }),
+            None => {
+                error!("Invalid event specification: {}", spec);
+                return 2;
+            }
+        }
+    }
+
+    let frames = match run_test(&script_file, profile, num_frames, &events) {
+        Ok(frames) => frames,
+
+        Err(e) => {
+            error!("Could not run script '{}': {}", script_file.display(), e);
+            return 1;
+        }
+    };
+
+    match matches.value_of("golden") {
+        Some(golden_file) if matches.is_present("update") => {
+            if let Err(e) = write_golden_frames(golden_file, &frames) {
+                error!("{}", e);
+                return 1;
+            }
+
+            info!("Updated golden frames: {}", golden_file);
+
+            0
+        }
+
+        Some(golden_file) => {
+            let result = read_golden_frames(golden_file)
+                .and_then(|golden| compare_frames(&frames, &golden, tolerance));
+
+            match result {
+                Ok(()) => {
+                    info!("{}: {} frames OK", script_file.display(), frames.len());
+                    0
+                }
+
+                Err(e) => {
+                    error!("{}: {}", script_file.display(), e);
+                    1
+                }
+            }
+        }
+
+        None => {
+            for frame in frames.iter() {
+                println!("{}", frame_to_string(&frame));
+            }
+
+            0
+        }
+    }
+}
+
+#[test]
+fn test_solid_color() {
+    let frames = run_test(Path::new("src/scripts/solid.lua"), None, 2, &[]).unwrap();
+
+    assert_eq!(frames.len(), 2);
+
+    // default parameters of `solid.lua`: color_background = 0xffff1010, opacity = 1.0
+    for frame in frames {
+        assert_eq!(frame_to_string(&frame), vec!["ff1010"; NUM_KEYS].join(" "));
+    }
+}
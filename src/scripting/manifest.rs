@@ -22,7 +22,10 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::constants;
+use crate::hwdevices;
 use crate::profiles;
+use crate::scripting::native;
 use crate::util;
 
 pub type Result<T> = std::result::Result<T, ManifestError>;
@@ -40,6 +43,28 @@ pub enum ManifestError {
 
     #[fail(display = "Could not parse param value")]
     ParseParamError {},
+
+    #[fail(display = "Invalid value for parameter '{}': {}", param, msg)]
+    InvalidValue { param: String, msg: String },
+
+    #[fail(
+        display = "Script '{}' requires Eruption {} or later, but this is version {}",
+        script, required, current
+    )]
+    IncompatibleScript {
+        script: String,
+        required: String,
+        current: String,
+    },
+
+    #[fail(display = "Script '{}' has invalid version information", script)]
+    InvalidVersion { script: String },
+
+    #[fail(
+        display = "Script '{}' uses the unsupported event API version {}",
+        script, version
+    )]
+    UnsupportedEventApi { script: String, version: u32 },
     // #[fail(display = "Unknown error: {}", description)]
     // UnknownError { description: String },
 }
@@ -52,6 +77,10 @@ fn default_script_file() -> PathBuf {
     "".into()
 }
 
+fn default_event_api() -> u32 {
+    1
+}
+
 fn default_html_class() -> String {
     "badge-default".into()
 }
@@ -63,11 +92,23 @@ pub enum ConfigParam {
         name: String,
         description: String,
         default: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        step: Option<i64>,
     },
     Float {
         name: String,
         description: String,
         default: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        step: Option<f64>,
     },
     Bool {
         name: String,
@@ -78,84 +119,317 @@ pub enum ConfigParam {
         name: String,
         description: String,
         default: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        choices: Option<Vec<String>>,
     },
     Color {
         name: String,
         description: String,
         default: u32,
     },
+    /// A list of colors, e.g. a palette or the stops of a gradient
+    #[serde(rename = "color_list")]
+    ColorList {
+        name: String,
+        description: String,
+        default: Vec<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min_count: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_count: Option<usize>,
+    },
+    /// A set of keys, specified by their 1-based key indices
+    #[serde(rename = "key_set")]
+    KeySet {
+        name: String,
+        description: String,
+        default: Vec<u8>,
+    },
 }
 
-pub trait ParseConfig {
-    fn parse_config_param(&self, param: &str, val: &str) -> Result<profiles::ConfigParam>;
-}
+impl ConfigParam {
+    /// Verify that `value` has the right type, and satisfies the
+    /// constraints declared for this parameter
+    pub fn validate(&self, value: &profiles::ConfigParam) -> Result<()> {
+        let invalid = |msg: String| {
+            Err(ManifestError::InvalidValue {
+                param: self.get_name().to_string(),
+                msg,
+            })
+        };
 
-impl ParseConfig for Vec<ConfigParam> {
-    fn parse_config_param(&self, param: &str, val: &str) -> Result<profiles::ConfigParam> {
-        for p in self.iter() {
-            match &p {
-                ConfigParam::Int { name, .. } => {
-                    if name == param {
-                        let value =
-                            i64::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?;
-
-                        return Ok(profiles::ConfigParam::Int {
-                            name: name.to_string(),
-                            value,
-                        });
+        match (self, value) {
+            (ConfigParam::Int { min, max, step, .. }, profiles::ConfigParam::Int { value, .. }) => {
+                if let Some(min) = min {
+                    if value < min {
+                        return invalid(format!("{} is less than the minimum of {}", value, min));
+                    }
+                }
+
+                if let Some(max) = max {
+                    if value > max {
+                        return invalid(format!(
+                            "{} is greater than the maximum of {}",
+                            value, max
+                        ));
                     }
                 }
 
-                ConfigParam::Float { name, .. } => {
-                    if name == param {
-                        let value =
-                            f64::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?;
+                if let Some(step) = step {
+                    // the difference of two i64 values may not fit into an i64
+                    let offset = i128::from(*value) - i128::from(min.unwrap_or(0));
 
-                        return Ok(profiles::ConfigParam::Float {
-                            name: name.to_string(),
-                            value,
-                        });
+                    if *step > 0 && offset % i128::from(*step) != 0 {
+                        return invalid(format!(
+                            "{} is not a multiple of the step {}",
+                            value, step
+                        ));
                     }
                 }
 
-                ConfigParam::Bool { name, .. } => {
-                    if name == param {
-                        let value =
-                            bool::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?;
+                Ok(())
+            }
 
-                        return Ok(profiles::ConfigParam::Bool {
-                            name: name.to_string(),
-                            value,
-                        });
+            (
+                ConfigParam::Float { min, max, step, .. },
+                profiles::ConfigParam::Float { value, .. },
+            ) => {
+                if !value.is_finite() {
+                    return invalid(format!("{} is not a finite number", value));
+                }
+
+                if let Some(min) = min {
+                    if value < min {
+                        return invalid(format!("{} is less than the minimum of {}", value, min));
                     }
                 }
 
-                ConfigParam::String { name, .. } => {
-                    if name == param {
-                        let value = val.to_owned();
+                if let Some(max) = max {
+                    if value > max {
+                        return invalid(format!(
+                            "{} is greater than the maximum of {}",
+                            value, max
+                        ));
+                    }
+                }
 
-                        return Ok(profiles::ConfigParam::String {
-                            name: name.to_string(),
-                            value,
-                        });
+                if let Some(step) = step {
+                    let steps = (value - min.unwrap_or(0.0)) / step;
+
+                    if *step > 0.0 && (steps - steps.round()).abs() > 1.0e-6 {
+                        return invalid(format!(
+                            "{} is not a multiple of the step {}",
+                            value, step
+                        ));
                     }
                 }
 
-                ConfigParam::Color { name, .. } => {
-                    if name == param {
-                        let value = u32::from_str_radix(&val[1..], 16)
-                            .map_err(|_e| ManifestError::ParseParamError {})?;
+                Ok(())
+            }
+
+            (ConfigParam::Bool { .. }, profiles::ConfigParam::Bool { .. }) => Ok(()),
 
-                        return Ok(profiles::ConfigParam::Color {
-                            name: name.to_string(),
+            (ConfigParam::String { choices, .. }, profiles::ConfigParam::String { value, .. }) => {
+                if let Some(choices) = choices {
+                    if !choices.contains(value) {
+                        return invalid(format!(
+                            "'{}' is not one of the choices: {}",
                             value,
-                        });
+                            choices.join(", ")
+                        ));
                     }
                 }
+
+                Ok(())
             }
+
+            (ConfigParam::Color { .. }, profiles::ConfigParam::Color { .. }) => Ok(()),
+
+            (
+                ConfigParam::ColorList {
+                    min_count,
+                    max_count,
+                    ..
+                },
+                profiles::ConfigParam::ColorList { value, .. },
+            ) => {
+                if let Some(min_count) = min_count {
+                    if value.len() < *min_count {
+                        return invalid(format!(
+                            "{} colors are less than the minimum of {}",
+                            value.len(),
+                            min_count
+                        ));
+                    }
+                }
+
+                if let Some(max_count) = max_count {
+                    if value.len() > *max_count {
+                        return invalid(format!(
+                            "{} colors are more than the maximum of {}",
+                            value.len(),
+                            max_count
+                        ));
+                    }
+                }
+
+                Ok(())
+            }
+
+            (ConfigParam::KeySet { .. }, profiles::ConfigParam::KeySet { value, .. }) => {
+                match value
+                    .iter()
+                    .find(|k| **k == 0 || **k as usize > hwdevices::NUM_KEYS)
+                {
+                    Some(key) => invalid(format!("{} is not a valid key index", key)),
+                    None => Ok(()),
+                }
+            }
+
+            _ => invalid(format!(
+                "expected a value of type '{}'",
+                self.get_type_name()
+            )),
         }
+    }
+
+    /// Verify that the default value satisfies the declared constraints
+    pub fn validate_default(&self) -> Result<()> {
+        self.validate(&self.get_default_value())
+    }
+
+    /// The default value, as a profile value
+    pub fn get_default_value(&self) -> profiles::ConfigParam {
+        let name = self.get_name().to_string();
 
-        Err(ManifestError::ParseParamError {})
+        match self {
+            ConfigParam::Int { default, .. } => profiles::ConfigParam::Int {
+                name,
+                value: *default,
+            },
+
+            ConfigParam::Float { default, .. } => profiles::ConfigParam::Float {
+                name,
+                value: *default,
+            },
+
+            ConfigParam::Bool { default, .. } => profiles::ConfigParam::Bool {
+                name,
+                value: *default,
+            },
+
+            ConfigParam::String { default, .. } => profiles::ConfigParam::String {
+                name,
+                value: default.clone(),
+            },
+
+            ConfigParam::Color { default, .. } => profiles::ConfigParam::Color {
+                name,
+                value: *default,
+            },
+
+            ConfigParam::ColorList { default, .. } => profiles::ConfigParam::ColorList {
+                name,
+                value: default.clone(),
+            },
+
+            ConfigParam::KeySet { default, .. } => profiles::ConfigParam::KeySet {
+                name,
+                value: default.clone(),
+            },
+        }
+    }
+
+    /// The name of the type, as used in manifest files
+    pub fn get_type_name(&self) -> &'static str {
+        match self {
+            ConfigParam::Int { .. } => "int",
+            ConfigParam::Float { .. } => "float",
+            ConfigParam::Bool { .. } => "bool",
+            ConfigParam::String { .. } => "string",
+            ConfigParam::Color { .. } => "color",
+            ConfigParam::ColorList { .. } => "color_list",
+            ConfigParam::KeySet { .. } => "key_set",
+        }
+    }
+}
+
+/// Parse a color of the form `#rrggbb`
+fn parse_color(val: &str) -> Result<u32> {
+    let val = val.trim();
+
+    if !val.starts_with('#') {
+        return Err(ManifestError::ParseParamError {});
+    }
+
+    u32::from_str_radix(&val[1..], 16).map_err(|_e| ManifestError::ParseParamError {})
+}
+
+pub trait ParseConfig {
+    fn parse_config_param(&self, param: &str, val: &str) -> Result<profiles::ConfigParam>;
+}
+
+impl ParseConfig for Vec<ConfigParam> {
+    /// Parse the value `val` of the parameter `param`, and validate it
+    /// against the constraints declared in the manifest
+    fn parse_config_param(&self, param: &str, val: &str) -> Result<profiles::ConfigParam> {
+        let p = self
+            .iter()
+            .find(|p| p.get_name() == param)
+            .ok_or(ManifestError::ParseParamError {})?;
+
+        let name = param.to_string();
+
+        let result = match p {
+            ConfigParam::Int { .. } => profiles::ConfigParam::Int {
+                name,
+                value: i64::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?,
+            },
+
+            ConfigParam::Float { .. } => profiles::ConfigParam::Float {
+                name,
+                value: f64::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?,
+            },
+
+            ConfigParam::Bool { .. } => profiles::ConfigParam::Bool {
+                name,
+                value: bool::from_str(&val).map_err(|_e| ManifestError::ParseParamError {})?,
+            },
+
+            ConfigParam::String { .. } => profiles::ConfigParam::String {
+                name,
+                value: val.to_owned(),
+            },
+
+            ConfigParam::Color { .. } => profiles::ConfigParam::Color {
+                name,
+                value: parse_color(&val)?,
+            },
+
+            // comma separated list of colors, e.g. `#ff0000, #0000ff`
+            ConfigParam::ColorList { .. } => profiles::ConfigParam::ColorList {
+                name,
+                value: val
+                    .split(',')
+                    .filter(|v| !v.trim().is_empty())
+                    .map(parse_color)
+                    .collect::<Result<Vec<u32>>>()?,
+            },
+
+            // comma separated list of key indices, e.g. `1, 2, 3`
+            ConfigParam::KeySet { .. } => profiles::ConfigParam::KeySet {
+                name,
+                value: val
+                    .split(',')
+                    .filter(|v| !v.trim().is_empty())
+                    .map(|v| u8::from_str(v.trim()).map_err(|_e| ManifestError::ParseParamError {}))
+                    .collect::<Result<Vec<u8>>>()?,
+            },
+        };
+
+        p.validate(&result)?;
+
+        Ok(result)
     }
 }
 
@@ -176,20 +450,70 @@ impl GetAttr for ConfigParam {
             ConfigParam::String { ref name, .. } => name,
 
             ConfigParam::Color { ref name, .. } => name,
+
+            ConfigParam::ColorList { ref name, .. } => name,
+
+            ConfigParam::KeySet { ref name, .. } => name,
         }
     }
 
     fn get_default(&self) -> String {
+        profiles::GetAttr::get_value(&self.get_default_value())
+    }
+}
+
+/// Compatibility of a script with the running version of Eruption
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Compatibility {
+    /// The script supports the running version
+    Compatible,
+
+    /// The script requires a newer version of Eruption
+    Incompatible,
+
+    /// `version` or `min_supported_version` is not a valid semantic version
+    InvalidVersion,
+}
+
+impl Default for Compatibility {
+    fn default() -> Self {
+        Compatibility::InvalidVersion
+    }
+}
+
+impl Compatibility {
+    pub fn as_str(self) -> &'static str {
         match self {
-            ConfigParam::Int { ref default, .. } => format!("{}", default),
+            Compatibility::Compatible => "compatible",
+            Compatibility::Incompatible => "incompatible",
+            Compatibility::InvalidVersion => "invalid-version",
+        }
+    }
+}
 
-            ConfigParam::Float { ref default, .. } => format!("{}", default),
+/// How to treat scripts that are not compatible with the running version,
+/// set via `global.script_compatibility`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CompatibilityMode {
+    /// Refuse to load incompatible scripts
+    Strict,
 
-            ConfigParam::Bool { ref default, .. } => format!("{}", default),
+    /// Load incompatible scripts, but log a warning
+    Warn,
+}
 
-            ConfigParam::String { ref default, .. } => default.to_owned(),
+impl CompatibilityMode {
+    pub fn from_config() -> Self {
+        let mode = crate::CONFIG
+            .lock()
+            .as_ref()
+            .and_then(|c| c.get_str("global.script_compatibility").ok())
+            .unwrap_or_else(|| "strict".to_string());
 
-            ConfigParam::Color { ref default, .. } => format!("#{:06x}", default),
+        if mode.eq_ignore_ascii_case("warn") {
+            CompatibilityMode::Warn
+        } else {
+            CompatibilityMode::Strict
         }
     }
 }
@@ -207,10 +531,26 @@ pub struct Manifest {
     pub author: String,
     pub min_supported_version: String,
     pub tags: Option<Vec<ScriptTag>>,
+
+    /// Library modules that are required by the script
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+
+    /// Library modules that are imported by the script, and may be overridden by a profile
+    #[serde(default)]
+    pub imports: Vec<String>,
+
     pub config: Option<Vec<ConfigParam>>,
 
+    /// Version of the event API, e.g. the arguments the event handlers are called with
+    #[serde(default = "default_event_api")]
+    pub event_api: u32,
+
     #[serde(default = "default_html_class")]
     pub html_class: String,
+
+    #[serde(skip)]
+    pub compatibility: Compatibility,
 }
 
 impl std::cmp::PartialOrd for Manifest {
@@ -230,6 +570,29 @@ impl Manifest {
                         // fill in required fields, after parsing
                         result.id = id;
                         result.script_file = script.to_path_buf();
+                        result.compatibility = result.get_compatibility();
+
+                        if result.event_api < 1
+                            || result.event_api > constants::CURRENT_EVENT_API_VERSION
+                        {
+                            let e = ManifestError::UnsupportedEventApi {
+                                script: script.display().to_string(),
+                                version: result.event_api,
+                            };
+
+                            error!("{}", e);
+                            return Err(e);
+                        }
+
+                        // the default values have to satisfy the declared constraints
+                        if let Some(config) = &result.config {
+                            for param in config.iter() {
+                                if let Err(e) = param.validate_default() {
+                                    error!("{}: {}", script.display(), e);
+                                    return Err(ManifestError::ParseError {});
+                                }
+                            }
+                        }
 
                         if let Some(tags) = &result.tags {
                             result.html_class.clear();
@@ -258,20 +621,76 @@ impl Manifest {
     pub fn from(script: &Path) -> Result<Self> {
         Self::new(default_id(), script)
     }
+
+    /// Compare `min_supported_version` with the running version of Eruption
+    fn get_compatibility(&self) -> Compatibility {
+        let current = semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+
+        match (
+            semver::Version::parse(&self.version),
+            semver::Version::parse(&self.min_supported_version),
+        ) {
+            (Ok(_), Ok(required)) if required <= current => Compatibility::Compatible,
+            (Ok(_), Ok(_)) => Compatibility::Incompatible,
+
+            _ => Compatibility::InvalidVersion,
+        }
+    }
+
+    /// Returns an error if the script is not compatible with the running version
+    /// of Eruption. In `CompatibilityMode::Warn` only a warning is logged
+    pub fn check_compatibility(&self) -> Result<()> {
+        self.check_compatibility_with(CompatibilityMode::from_config())
+    }
+
+    /// Like `check_compatibility`, but in the compatibility mode `mode`
+    pub fn check_compatibility_with(&self, mode: CompatibilityMode) -> Result<()> {
+        let error = match self.compatibility {
+            Compatibility::Compatible => return Ok(()),
+
+            Compatibility::Incompatible => ManifestError::IncompatibleScript {
+                script: self.name.clone(),
+                required: self.min_supported_version.clone(),
+                current: env!("CARGO_PKG_VERSION").to_string(),
+            },
+
+            Compatibility::InvalidVersion => ManifestError::InvalidVersion {
+                script: self.name.clone(),
+            },
+        };
+
+        match mode {
+            CompatibilityMode::Strict => Err(error),
+
+            CompatibilityMode::Warn => {
+                warn!("{}", error);
+                Ok(())
+            }
+        }
+    }
 }
 
 /// Get a `Vec` of `PathBufs` of available script files in the directory `script_path`.
-#[allow(dead_code)]
 fn get_script_files(script_path: &Path) -> Result<Vec<PathBuf>> {
     match fs::read_dir(script_path) {
         Ok(paths) => Ok(paths
             .map(|p| p.unwrap().path())
-            .filter(|p| {
-                if p.extension().is_some() {
-                    return p.extension().unwrap() == "lua";
+            .filter_map(|p| {
+                if p.extension().is_some() && p.extension().unwrap() == "lua" {
+                    return Some(p);
                 }
 
-                false
+                // native effects have no script file, but a manifest named `<effect>.native.manifest`
+                let file_name = p.file_name()?.to_string_lossy().to_string();
+                if file_name.ends_with(".native.manifest") {
+                    let effect = p.with_file_name(file_name.trim_end_matches(".manifest"));
+
+                    if native::is_native_effect_available(&effect) {
+                        return Some(effect);
+                    }
+                }
+
+                None
             })
             .collect()),
 
@@ -279,9 +698,8 @@ fn get_script_files(script_path: &Path) -> Result<Vec<PathBuf>> {
     }
 }
 
-#[allow(dead_code)]
 pub fn get_scripts(script_path: &Path) -> Result<Vec<Manifest>> {
-    let script_files = get_script_files(script_path).unwrap();
+    let script_files = get_script_files(script_path)?;
 
     let mut errors_present = false;
     let mut result: Vec<Manifest> = vec![];
@@ -289,6 +707,14 @@ pub fn get_scripts(script_path: &Path) -> Result<Vec<Manifest>> {
     for (id, script_file) in script_files.iter().enumerate() {
         match Manifest::new(id, &script_file) {
             Ok(manifest) => {
+                if manifest.compatibility != Compatibility::Compatible {
+                    warn!(
+                        "Script '{}' is not compatible with this version of Eruption: {:?}",
+                        script_file.display(),
+                        manifest.compatibility
+                    );
+                }
+
                 result.push(manifest);
             }
 
@@ -426,3 +852,72 @@ impl ScriptTag {
         }
     }
 }
+
+#[test]
+fn test_validate_step() {
+    let param = ConfigParam::Int {
+        name: "value".to_string(),
+        description: String::new(),
+        default: 1,
+        min: Some(-1),
+        max: None,
+        step: Some(2),
+    };
+
+    let value = |value| profiles::ConfigParam::Int {
+        name: "value".to_string(),
+        value,
+    };
+
+    assert!(param.validate(&value(std::i64::MAX)).is_ok());
+    assert!(param.validate(&value(std::i64::MAX - 1)).is_err());
+    assert!(param.validate(&value(-2)).is_err());
+}
+
+#[test]
+fn test_compatibility() {
+    let manifest = |min_supported_version: &str| {
+        let mut manifest: Manifest = toml::from_str(&format!(
+            r#"
+            name = "Test"
+            description = "Requires version {}"
+            version = "0.0.1"
+            author = "The Eruption development team"
+            min_supported_version = "{}"
+            "#,
+            min_supported_version, min_supported_version
+        ))
+        .unwrap();
+
+        manifest.compatibility = manifest.get_compatibility();
+        manifest
+    };
+
+    let current = semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+    let newer = format!("{}.{}.0", current.major, current.minor + 1);
+
+    let same = manifest(env!("CARGO_PKG_VERSION"));
+    assert_eq!(same.compatibility, Compatibility::Compatible);
+    assert!(same
+        .check_compatibility_with(CompatibilityMode::Strict)
+        .is_ok());
+
+    let newer = manifest(&newer);
+    assert_eq!(newer.compatibility, Compatibility::Incompatible);
+    assert!(newer
+        .check_compatibility_with(CompatibilityMode::Strict)
+        .is_err());
+    assert!(newer
+        .check_compatibility_with(CompatibilityMode::Warn)
+        .is_ok());
+
+    let invalid = manifest("0.1");
+    assert_eq!(invalid.compatibility, Compatibility::InvalidVersion);
+    assert_eq!(invalid.compatibility.as_str(), "invalid-version");
+    assert!(invalid
+        .check_compatibility_with(CompatibilityMode::Strict)
+        .is_err());
+    assert!(invalid
+        .check_compatibility_with(CompatibilityMode::Warn)
+        .is_ok());
+}
@@ -15,5 +15,16 @@
     along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
 */
 
+pub mod animation;
+pub mod bus;
+pub mod debugger;
+pub mod dependencies;
+pub mod event_api;
+pub mod harness;
 pub mod manifest;
+pub mod native;
+pub mod particles;
+pub mod random;
 pub mod script;
+pub mod serialize;
+pub mod stats;
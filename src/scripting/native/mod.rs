@@ -0,0 +1,267 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use log::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+
+use crate::canvas::Color;
+use crate::hwdevices::HwDevice;
+use crate::profiles::Profile;
+use crate::scripting::manifest::{ConfigParam, Manifest};
+use crate::scripting::script::{self, Message, Result, RunScriptResult, ScriptingError, Tick};
+use crate::scripting::stats;
+use crate::{ACTIVE_PROFILE, ACTIVE_SCRIPTS};
+
+mod noise_effect;
+
+/// File extension of native effects, as used in the `active_scripts` section of profiles
+pub const NATIVE_EFFECT_EXTENSION: &str = "native";
+
+/// An effect that is implemented natively in Rust. Native effects are listed
+/// in profiles just like Lua scripts, e.g. `perlin.native`, they have a manifest
+/// and occupy a layer of the canvas
+pub trait Effect {
+    /// Called once, after the effect has been loaded
+    fn on_startup(&mut self, _config: &EffectConfig) {}
+
+    /// Called on each timer tick. Render into `canvas` and return `true`
+    /// to submit it for realization on the next frame
+    fn on_tick(&mut self, tick: &Tick, canvas: &mut [Color]) -> bool;
+
+    /// Called when a key has been pressed
+    fn on_key_down(&mut self, _key_index: u8) {}
+
+    /// Called when a key has been released
+    fn on_key_up(&mut self, _key_index: u8) {}
+
+    /// Called before the effect is unloaded
+    fn on_quit(&mut self) {}
+}
+
+/// Instantiate the native effect with the name `name`
+pub fn create_effect(name: &str) -> Option<Box<dyn Effect>> {
+    match name {
+        "perlin" => Some(Box::new(noise_effect::NoiseEffect::new(
+            noise_effect::NoiseKind::Perlin,
+        ))),
+
+        "turbulence" => Some(Box::new(noise_effect::NoiseEffect::new(
+            noise_effect::NoiseKind::Turbulence,
+        ))),
+
+        "voronoi" => Some(Box::new(noise_effect::NoiseEffect::new(
+            noise_effect::NoiseKind::Voronoi,
+        ))),
+
+        _ => None,
+    }
+}
+
+/// Returns `true` if `script_file` refers to a native effect
+pub fn is_native_effect(script_file: &Path) -> bool {
+    script_file
+        .extension()
+        .map(|e| e == NATIVE_EFFECT_EXTENSION)
+        .unwrap_or(false)
+}
+
+/// Returns `true` if `script_file` refers to a known native effect
+pub fn is_native_effect_available(script_file: &Path) -> bool {
+    get_effect_name(script_file)
+        .map(|name| create_effect(&name).is_some())
+        .unwrap_or(false)
+}
+
+fn get_effect_name(script_file: &Path) -> Option<String> {
+    script_file
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+}
+
+/// Parameters of a native effect, as declared in its manifest and
+/// optionally overridden by the active profile
+pub struct EffectConfig {
+    manifest: Manifest,
+    profile: Option<Profile>,
+}
+
+macro_rules! get_effect_param {
+    ($t:ident, $tval:ty, $pval:path) => {
+        paste::item! {
+            #[allow(dead_code)]
+            pub fn [<get_ $t>](&self, name: &str) -> Option<$tval> {
+                let value = self
+                    .profile
+                    .as_ref()
+                    .and_then(|p| p.[<get_ $t _value>](&self.manifest.name, name))
+                    .map(|v| v.to_owned());
+
+                value.or_else(|| {
+                    self.manifest.config.as_ref().and_then(|config| {
+                        config.iter().find_map(|p| match p {
+                            $pval { name: n, default, .. } if n == name => Some(default.to_owned()),
+                            _ => None,
+                        })
+                    })
+                })
+            }
+        }
+    };
+}
+
+impl EffectConfig {
+    pub fn new(manifest: Manifest, profile: Option<Profile>) -> Self {
+        Self { manifest, profile }
+    }
+
+    get_effect_param!(int, i64, ConfigParam::Int);
+    get_effect_param!(float, f64, ConfigParam::Float);
+    get_effect_param!(bool, bool, ConfigParam::Bool);
+    get_effect_param!(str, String, ConfigParam::String);
+    get_effect_param!(color, u32, ConfigParam::Color);
+    get_effect_param!(color_list, Vec<u32>, ConfigParam::ColorList);
+    get_effect_param!(key_set, Vec<u8>, ConfigParam::KeySet);
+}
+
+/// Loads and runs a native effect.
+/// This is the counterpart to `script::run_script`, it consumes the same
+/// messages and takes part in the same upcall and layer realization protocol
+pub fn run_effect(
+    file: PathBuf,
+    _hwdevice: &HwDevice,
+    rx: &Receiver<Message>,
+) -> Result<RunScriptResult> {
+    let name = get_effect_name(&file).ok_or(ScriptingError::UnknownEffect {})?;
+    let mut effect = create_effect(&name).ok_or(ScriptingError::UnknownEffect {})?;
+
+    let manifest = Manifest::from(&file).map_err(|e| {
+        error!(
+            "Could not parse manifest file for effect '{}': {}",
+            file.display(),
+            e
+        );
+
+        ScriptingError::InaccessibleManifest {}
+    })?;
+
+    manifest.check_compatibility().map_err(|e| {
+        error!("{}", e);
+        ScriptingError::IncompatibleScript {}
+    })?;
+
+    ACTIVE_SCRIPTS.lock().push(manifest.clone());
+
+    let config = EffectConfig::new(manifest, ACTIVE_PROFILE.lock().clone());
+    effect.on_startup(&config);
+
+    let script_name = file.file_name().unwrap().to_string_lossy().to_string();
+    let mut canvas = crate::canvas::new_canvas();
+
+    loop {
+        if let Ok(msg) = rx.recv() {
+            match msg {
+                Message::Quit(_param) => {
+                    effect.on_quit();
+
+                    *crate::UPCALL_COMPLETED_ON_QUIT.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_QUIT.1.notify_all();
+                }
+
+                Message::Tick(tick) => {
+                    let _measurement = stats::measure(&script_name, "on_tick");
+
+                    if effect.on_tick(&tick, &mut canvas) {
+                        script::LOCAL_LED_MAP
+                            .with(|local_map| local_map.borrow_mut().copy_from_slice(&canvas));
+
+                        script::FRAME_GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+
+                Message::RealizeColorMap => {
+                    let _measurement = stats::measure(&script_name, "realize");
+                    script::realize_color_map(&script_name);
+                }
+
+                Message::KeyDown(key_index) => {
+                    {
+                        let _measurement = stats::measure(&script_name, "on_key_down");
+                        effect.on_key_down(key_index);
+                    }
+
+                    *crate::UPCALL_COMPLETED_ON_KEY_DOWN.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_KEY_DOWN.1.notify_all();
+                }
+
+                Message::KeyUp(key_index) => {
+                    {
+                        let _measurement = stats::measure(&script_name, "on_key_up");
+                        effect.on_key_up(key_index);
+                    }
+
+                    *crate::UPCALL_COMPLETED_ON_KEY_UP.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_KEY_UP.1.notify_all();
+                }
+
+                Message::HidEvent(_param) => {
+                    *crate::UPCALL_COMPLETED_ON_HID_EVENT.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_HID_EVENT.1.notify_all();
+                }
+
+                Message::MouseButtonDown(_param) => {
+                    *crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_DOWN.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_DOWN.1.notify_all();
+                }
+
+                Message::MouseButtonUp(_param) => {
+                    *crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_UP.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_MOUSE_BUTTON_UP.1.notify_all();
+                }
+
+                Message::MouseMove(_rel_x, _rel_y, _rel_z) => {
+                    *crate::UPCALL_COMPLETED_ON_MOUSE_MOVE.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_MOUSE_MOVE.1.notify_all();
+                }
+
+                Message::MouseWheelEvent(_param) => {
+                    *crate::UPCALL_COMPLETED_ON_MOUSE_EVENT.0.lock() -= 1;
+                    crate::UPCALL_COMPLETED_ON_MOUSE_EVENT.1.notify_all();
+                }
+
+                Message::SystemEvent(_param) => {}
+
+                Message::BusMessage(_topic, _payload) => {}
+
+                Message::Unload => {
+                    effect.on_quit();
+
+                    debug!(
+                        "Native effect '{}' terminated gracefully",
+                        file.file_name().unwrap().to_string_lossy()
+                    );
+
+                    return Ok(RunScriptResult::TerminatedGracefully);
+                }
+            }
+        } else {
+            // the main thread dropped our channel
+            return Ok(RunScriptResult::TerminatedGracefully);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use noise::{NoiseFn, Seedable};
+use palette::ConvertFrom;
+use palette::{Hsl, Srgb};
+
+use super::{Effect, EffectConfig};
+use crate::canvas::{srgb_to_linear, Color};
+use crate::scripting::script::Tick;
+
+/// Number of rows of the keyboard, like `num_rows` in `declarations.lua`
+const NUM_ROWS: f64 = 6.0;
+
+/// Number of columns of the keyboard, like `num_cols` in `declarations.lua`
+const NUM_COLS: f64 = 21.0;
+
+/// The noise function used by a `NoiseEffect`
+pub enum NoiseKind {
+    Perlin,
+    Turbulence,
+    Voronoi,
+}
+
+/// Native implementation of the `perlin.lua`, `turbulence.lua` and `voronoi.lua`
+/// effects. The noise generators are constructed only once, and the hue is
+/// converted to linear light without a round trip through 8 bits sRGB
+pub struct NoiseEffect {
+    kind: NoiseKind,
+
+    perlin: noise::Perlin,
+    worley: noise::Worley,
+
+    ticks: u64,
+
+    animation_delay: f64,
+    time_scale: f64,
+    coord_scale: f64,
+    color_saturation: f64,
+    color_lightness: f64,
+    color_divisor: f64,
+    color_offset: f64,
+    opacity: f64,
+}
+
+impl NoiseEffect {
+    pub fn new(kind: NoiseKind) -> Self {
+        Self {
+            kind,
+
+            perlin: noise::Perlin::new(),
+            worley: noise::Worley::new(),
+
+            ticks: 0,
+
+            animation_delay: 1.0,
+            time_scale: 1.0,
+            coord_scale: 1.0,
+            color_saturation: 1.0,
+            color_lightness: 0.5,
+            color_divisor: 1.0,
+            color_offset: 0.0,
+            opacity: 1.0,
+        }
+    }
+
+    fn noise(&self, x: f64, y: f64, z: f64) -> f64 {
+        match self.kind {
+            NoiseKind::Perlin => self.perlin.get([x, y, z]) / 2.0 + 0.5,
+
+            NoiseKind::Turbulence => f64::from(
+                simdnoise::NoiseBuilder::turbulence_3d_offset(
+                    x as f32, 2, y as f32, 2, z as f32, 2,
+                )
+                .generate_scaled(0.0, 1.0)[0],
+            ),
+
+            NoiseKind::Voronoi => self.worley.get([x, y, z]) / 2.0 + 0.5,
+        }
+    }
+}
+
+impl Effect for NoiseEffect {
+    fn on_startup(&mut self, config: &EffectConfig) {
+        let get = |name, default| config.get_float(name).unwrap_or(default);
+
+        self.animation_delay = get("animation_delay", self.animation_delay).max(1.0);
+        self.time_scale = get("time_scale", self.time_scale);
+        self.coord_scale = get("coord_scale", self.coord_scale);
+        self.color_saturation = get("color_saturation", self.color_saturation);
+        self.color_lightness = get("color_lightness", self.color_lightness);
+        self.color_divisor = get("color_divisor", self.color_divisor);
+        self.color_offset = get("color_offset", self.color_offset);
+        self.opacity = get("opacity", self.opacity);
+
+        // the turbulence noise (SIMD) is not seedable
+        if let Some(seed) = config.get_int("seed") {
+            self.perlin = noise::Perlin::new().set_seed(seed as u32);
+            self.worley = noise::Worley::new().set_seed(seed as u32);
+        }
+    }
+
+    fn on_tick(&mut self, tick: &Tick, canvas: &mut [Color]) -> bool {
+        self.ticks += u64::from(tick.ticks);
+
+        if self.ticks as f64 % self.animation_delay != 0.0 {
+            return false;
+        }
+
+        let z = self.ticks as f64 / self.time_scale;
+
+        for (idx, color) in canvas.iter_mut().enumerate() {
+            // Lua scripts use 1-based indices
+            let i = (idx + 1) as f64;
+
+            let x = i / NUM_ROWS;
+            let y = i / NUM_COLS;
+
+            let val = self.noise(x / self.coord_scale, y / self.coord_scale, z) * 360.0;
+
+            let hue = (val / self.color_divisor) + self.color_offset;
+            let (r, g, b) =
+                Srgb::convert_from(Hsl::new(hue, self.color_saturation, self.color_lightness))
+                    .into_components();
+
+            *color = Color {
+                r: srgb_to_linear(r as f32),
+                g: srgb_to_linear(g as f32),
+                b: srgb_to_linear(b as f32),
+                a: self.opacity as f32,
+            };
+        }
+
+        true
+    }
+}
@@ -0,0 +1,550 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use rand::Rng;
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::time::Instant;
+
+use crate::canvas::{self, Canvas, Color};
+use crate::constants;
+use crate::hwdevices::layout::{self, KeyGeometry, KeyboardVariant};
+use crate::hwdevices::NUM_KEYS;
+
+/// Parameters of the particles spawned by an emitter
+#[derive(Debug, Copy, Clone)]
+pub struct ParticleOptions {
+    pub color: Color,
+
+    /// Initial speed, in key units per second
+    pub speed: f64,
+
+    /// Random variation of the speed, in the range [0.0..1.0]
+    pub spread: f64,
+
+    /// Direction of movement and its random variation, in degrees
+    pub angle: f64,
+    pub angle_spread: f64,
+
+    /// Lifetime, in seconds
+    pub life: f64,
+
+    /// Exponent of the fade out curve, 1.0 fades out linearly
+    pub decay: f64,
+
+    /// Acceleration, in key units per second squared, pointing down
+    pub gravity: f64,
+
+    /// Loss of speed, as a fraction per second
+    pub drag: f64,
+
+    /// Radius of a particle, in key units
+    pub size: f64,
+}
+
+impl Default for ParticleOptions {
+    fn default() -> Self {
+        Self {
+            color: Color::from_u32(0xffff_ffff),
+            speed: 8.0,
+            spread: 0.5,
+            angle: 0.0,
+            angle_spread: 360.0,
+            life: 1.0,
+            decay: 1.0,
+            gravity: 0.0,
+            drag: 0.0,
+            size: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Particle {
+    x: f64,
+    y: f64,
+    vx: f64,
+    vy: f64,
+    age: f64,
+    options: ParticleOptions,
+}
+
+/// Spawns particles continuously, at a fixed rate
+#[derive(Debug, Clone)]
+struct Emitter {
+    x: f64,
+    y: f64,
+
+    /// Particles per second
+    rate: f64,
+
+    /// Remaining lifetime of the emitter, in seconds
+    remaining: f64,
+
+    /// Fractional particles, carried over to the next step
+    pending: f64,
+
+    options: ParticleOptions,
+}
+
+/// Parameters of a ripple, a ring that expands from its origin
+#[derive(Debug, Copy, Clone)]
+pub struct RippleOptions {
+    pub color: Color,
+
+    /// Speed of expansion, in key units per second
+    pub speed: f64,
+
+    /// Width of the ring, in key units
+    pub width: f64,
+
+    /// Lifetime, in seconds
+    pub life: f64,
+
+    /// Exponent of the fade out curve, 1.0 fades out linearly
+    pub decay: f64,
+}
+
+impl Default for RippleOptions {
+    fn default() -> Self {
+        Self {
+            color: Color::from_u32(0xffff_ffff),
+            speed: 10.0,
+            width: 1.0,
+            life: 1.5,
+            decay: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Ripple {
+    x: f64,
+    y: f64,
+    age: f64,
+    options: RippleOptions,
+}
+
+fn get_fade(age: f64, life: f64, decay: f64) -> f64 {
+    if life <= 0.0 {
+        0.0
+    } else {
+        (1.0 - age / life).max(0.0).powf(decay.max(0.0))
+    }
+}
+
+/// The particles, emitters and ripples of a Lua VM
+#[derive(Debug, Clone)]
+pub struct Engine {
+    particles: Vec<Particle>,
+    emitters: Vec<Emitter>,
+    ripples: Vec<Ripple>,
+
+    /// The rendered layer, blended on top of the color map of the script
+    layer: Canvas,
+
+    last_step: Option<Instant>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            particles: vec![],
+            emitters: vec![],
+            ripples: vec![],
+            layer: canvas::new_canvas(),
+            last_step: None,
+        }
+    }
+}
+
+impl Engine {
+    /// Returns true if there is anything left to simulate
+    pub fn is_active(&self) -> bool {
+        !self.particles.is_empty() || !self.emitters.is_empty() || !self.ripples.is_empty()
+    }
+
+    pub fn get_num_particles(&self) -> usize {
+        self.particles.len()
+    }
+
+    fn spawn_particle(&mut self, x: f64, y: f64, options: &ParticleOptions) {
+        if self.particles.len() >= constants::MAX_PARTICLES {
+            return;
+        }
+
+        let mut rng = rand::thread_rng();
+
+        let angle = (options.angle + options.angle_spread * (rng.gen::<f64>() - 0.5)) * PI / 180.0;
+        let speed = options.speed * (1.0 - options.spread.max(0.0).min(1.0) * rng.gen::<f64>());
+
+        self.particles.push(Particle {
+            x,
+            y,
+            vx: angle.cos() * speed,
+            vy: -angle.sin() * speed,
+            age: 0.0,
+            options: *options,
+        });
+    }
+
+    /// Spawn `count` particles at once at (`x`, `y`), in key units
+    pub fn spawn_burst(&mut self, x: f64, y: f64, count: usize, options: &ParticleOptions) {
+        let count = count.min(constants::MAX_PARTICLES.saturating_sub(self.particles.len()));
+
+        for _ in 0..count {
+            self.spawn_particle(x, y, options);
+        }
+    }
+
+    /// Spawn `rate` particles per second, for `duration` seconds. The rate
+    /// is clamped to `MAX_PARTICLE_RATE`
+    pub fn add_emitter(
+        &mut self,
+        x: f64,
+        y: f64,
+        rate: f64,
+        duration: f64,
+        options: &ParticleOptions,
+    ) {
+        if self.emitters.len() >= constants::MAX_EMITTERS {
+            return;
+        }
+
+        self.emitters.push(Emitter {
+            x,
+            y,
+            rate: rate.max(0.0).min(constants::MAX_PARTICLE_RATE),
+            remaining: duration,
+            pending: 0.0,
+            options: *options,
+        });
+    }
+
+    pub fn spawn_ripple(&mut self, x: f64, y: f64, options: &RippleOptions) {
+        if self.ripples.len() >= constants::MAX_RIPPLES {
+            return;
+        }
+
+        self.ripples.push(Ripple {
+            x,
+            y,
+            age: 0.0,
+            options: *options,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.emitters.clear();
+        self.ripples.clear();
+    }
+
+    /// Advance the simulation by `dt` seconds
+    pub fn step(&mut self, dt: f64) {
+        let mut emitters = std::mem::replace(&mut self.emitters, vec![]);
+
+        for emitter in emitters.iter_mut() {
+            let dt = dt.min(emitter.remaining);
+
+            emitter.remaining -= dt;
+            emitter.pending =
+                (emitter.pending + emitter.rate * dt).min(constants::MAX_PARTICLES as f64);
+
+            while emitter.pending >= 1.0 && self.particles.len() < constants::MAX_PARTICLES {
+                emitter.pending -= 1.0;
+                self.spawn_particle(emitter.x, emitter.y, &emitter.options);
+            }
+        }
+
+        emitters.retain(|e| e.remaining > 0.0);
+        self.emitters = emitters;
+
+        for p in self.particles.iter_mut() {
+            let drag = (1.0 - p.options.drag * dt).max(0.0);
+
+            p.vx *= drag;
+            p.vy = p.vy * drag + p.options.gravity * dt;
+
+            p.x += p.vx * dt;
+            p.y += p.vy * dt;
+            p.age += dt;
+        }
+
+        self.particles.retain(|p| p.age < p.options.life);
+
+        for r in self.ripples.iter_mut() {
+            r.age += dt;
+        }
+
+        self.ripples.retain(|r| r.age < r.options.life);
+    }
+
+    /// Render all particles and ripples onto the keys. Overlapping particles
+    /// add up, the result is written to the layer of the engine
+    pub fn render(&mut self, keys: &[KeyGeometry]) {
+        for c in self.layer.iter_mut() {
+            *c = Color::default();
+        }
+
+        for key in keys.iter().filter(|k| k.index < NUM_KEYS) {
+            let (cx, cy) = key.center();
+            let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+
+            let mut add = |color: &Color, weight: f64| {
+                let weight = weight as f32 * color.a;
+
+                r += color.r * weight;
+                g += color.g * weight;
+                b += color.b * weight;
+                a += weight;
+            };
+
+            for p in self.particles.iter() {
+                let d = ((p.x - cx).powi(2) + (p.y - cy).powi(2)).sqrt();
+
+                if d < p.options.size {
+                    let fade = get_fade(p.age, p.options.life, p.options.decay);
+                    add(&p.options.color, (1.0 - d / p.options.size) * fade);
+                }
+            }
+
+            for ripple in self.ripples.iter() {
+                let d = ((ripple.x - cx).powi(2) + (ripple.y - cy).powi(2)).sqrt();
+                let distance_to_ring = (d - ripple.age * ripple.options.speed).abs();
+
+                if distance_to_ring < ripple.options.width {
+                    let fade = get_fade(ripple.age, ripple.options.life, ripple.options.decay);
+                    add(
+                        &ripple.options.color,
+                        (1.0 - distance_to_ring / ripple.options.width) * fade,
+                    );
+                }
+            }
+
+            if a > 0.0 {
+                self.layer[key.index] = Color {
+                    r: r / a,
+                    g: g / a,
+                    b: b / a,
+                    a: a.min(1.0),
+                };
+            }
+        }
+    }
+
+    /// Step the simulation by the wall time since the last step, and render it
+    pub fn update(&mut self) {
+        let now = Instant::now();
+
+        // limit the time step, e.g. after the VM has been paused by the debugger
+        let dt = self
+            .last_step
+            .map(|t| {
+                now.duration_since(t)
+                    .as_secs_f64()
+                    .min(constants::MAX_PARTICLE_TIME_STEP)
+            })
+            .unwrap_or(0.0);
+
+        self.last_step = Some(now);
+
+        self.step(dt);
+        self.render(layout::get_layout(KeyboardVariant::from_config()));
+    }
+}
+
+/// Read a color from the field `color` of a Lua table
+fn get_color(options: &rlua::Table, default: Color) -> rlua::Result<Color> {
+    Ok(options
+        .get::<_, Option<u32>>("color")?
+        .map(Color::from_u32)
+        .unwrap_or(default))
+}
+
+impl ParticleOptions {
+    /// Read the options from a Lua table, missing fields get their default values
+    pub fn from_lua(options: Option<rlua::Table>) -> rlua::Result<Self> {
+        let mut result = Self::default();
+
+        if let Some(options) = options {
+            let get = |name: &str, default: f64| -> rlua::Result<f64> {
+                Ok(options.get::<_, Option<f64>>(name)?.unwrap_or(default))
+            };
+
+            result.color = get_color(&options, result.color)?;
+            result.speed = get("speed", result.speed)?;
+            result.spread = get("spread", result.spread)?;
+            result.angle = get("angle", result.angle)?;
+            result.angle_spread = get("angle_spread", result.angle_spread)?;
+            result.life = get("life", result.life)?;
+            result.decay = get("decay", result.decay)?;
+            result.gravity = get("gravity", result.gravity)?;
+            result.drag = get("drag", result.drag)?;
+            result.size = get("size", result.size)?;
+        }
+
+        Ok(result)
+    }
+}
+
+impl RippleOptions {
+    /// Read the options from a Lua table, missing fields get their default values
+    pub fn from_lua(options: Option<rlua::Table>) -> rlua::Result<Self> {
+        let mut result = Self::default();
+
+        if let Some(options) = options {
+            let get = |name: &str, default: f64| -> rlua::Result<f64> {
+                Ok(options.get::<_, Option<f64>>(name)?.unwrap_or(default))
+            };
+
+            result.color = get_color(&options, result.color)?;
+            result.speed = get("speed", result.speed)?;
+            result.width = get("width", result.width)?;
+            result.life = get("life", result.life)?;
+            result.decay = get("decay", result.decay)?;
+        }
+
+        Ok(result)
+    }
+}
+
+thread_local! {
+    /// The engine of the Lua VM running on this thread
+    pub static ENGINE: RefCell<Engine> = RefCell::new(Engine::default());
+
+    /// Set while the layer of the engine is not empty
+    static LAYER_VISIBLE: RefCell<bool> = RefCell::new(false);
+}
+
+/// Get the center of the key with the 1-based key index `key_index`
+pub fn get_key_position(key_index: usize) -> Option<(f64, f64)> {
+    if key_index == 0 {
+        return None;
+    }
+
+    layout::get_key(KeyboardVariant::from_config(), key_index - 1).map(|k| k.center())
+}
+
+/// Step and render the engine of this thread. Returns true if its layer
+/// has changed, and needs to be realized
+pub fn update() -> bool {
+    ENGINE.with(|engine| {
+        let mut engine = engine.borrow_mut();
+
+        let is_active = engine.is_active();
+        let was_visible = LAYER_VISIBLE.with(|v| v.replace(is_active));
+
+        if is_active || was_visible {
+            engine.update();
+
+            true
+        } else {
+            // restart the clock when the next particle gets spawned
+            engine.last_step = None;
+
+            false
+        }
+    })
+}
+
+/// Blend the layer of the engine of this thread onto `canvas`
+pub fn blend_layer(canvas: &mut [Color]) {
+    ENGINE.with(|engine| {
+        for (background, fg) in canvas.iter_mut().zip(engine.borrow().layer.iter()) {
+            *background = fg.over(background);
+        }
+    });
+}
+
+#[test]
+fn test_particles() {
+    let mut engine = Engine::default();
+
+    let options = ParticleOptions {
+        speed: 10.0,
+        spread: 0.0,
+        angle: 0.0,
+        angle_spread: 0.0,
+        life: 1.0,
+        ..Default::default()
+    };
+
+    engine.spawn_burst(0.0, 0.0, 4, &options);
+    engine.step(0.5);
+
+    // all particles moved to the right
+    assert_eq!(engine.get_num_particles(), 4);
+    assert!(engine
+        .particles
+        .iter()
+        .all(|p| (p.x - 5.0).abs() < 1e-9 && p.y.abs() < 1e-9));
+
+    engine.step(0.5);
+
+    assert_eq!(engine.get_num_particles(), 0);
+    assert!(!engine.is_active());
+}
+
+#[test]
+fn test_emitter() {
+    let mut engine = Engine::default();
+
+    let options = ParticleOptions {
+        life: 10.0,
+        ..Default::default()
+    };
+
+    engine.add_emitter(0.0, 0.0, 10.0, 1.0, &options);
+
+    for _ in 0..4 {
+        engine.step(0.25);
+    }
+
+    assert_eq!(engine.get_num_particles(), 10);
+    assert!(engine.emitters.is_empty());
+}
+
+#[test]
+fn test_limits() {
+    let mut engine = Engine::default();
+    let options = ParticleOptions::default();
+
+    engine.spawn_burst(0.0, 0.0, std::usize::MAX, &options);
+    assert_eq!(engine.get_num_particles(), constants::MAX_PARTICLES);
+
+    engine.clear();
+
+    for _ in 0..constants::MAX_EMITTERS + 1 {
+        engine.add_emitter(0.0, 0.0, std::f64::INFINITY, 1.0, &options);
+    }
+
+    assert_eq!(engine.emitters.len(), constants::MAX_EMITTERS);
+    assert!(engine
+        .emitters
+        .iter()
+        .all(|e| e.rate == constants::MAX_PARTICLE_RATE));
+
+    engine.step(0.1);
+    assert_eq!(engine.get_num_particles(), constants::MAX_PARTICLES);
+
+    for _ in 0..constants::MAX_RIPPLES + 1 {
+        engine.spawn_ripple(0.0, 0.0, &RippleOptions::default());
+    }
+
+    assert_eq!(engine.ripples.len(), constants::MAX_RIPPLES);
+}
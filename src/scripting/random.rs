@@ -0,0 +1,405 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use lazy_static::lazy_static;
+use noise::{MultiFractal, NoiseFn, Seedable};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+
+use crate::constants;
+use crate::util;
+
+pub type Result<T> = std::result::Result<T, RandomError>;
+
+#[derive(Debug, Fail)]
+pub enum RandomError {
+    #[fail(display = "Unknown noise type: {}", name)]
+    UnknownNoiseType { name: String },
+
+    #[fail(display = "Noise type '{}' does not support {} dimensions", name, dims)]
+    UnsupportedDimensions { name: String, dims: usize },
+
+    #[fail(display = "Empty range: {} .. {}", low, high)]
+    EmptyRange { low: f64, high: f64 },
+
+    #[fail(display = "Invalid range: {} .. {}", low, high)]
+    InvalidRange { low: f64, high: f64 },
+}
+
+lazy_static! {
+    /// Seed for the random number generators of all Lua VMs, `None` seeds
+    /// them from the entropy of the system
+    static ref DEFAULT_SEED: Mutex<Option<u64>> = Mutex::new(None);
+}
+
+thread_local! {
+    /// Random number generator of the Lua VM of this thread
+    static SCRIPT_RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Set the seed for the random number generators of Lua VMs that are started
+/// afterwards, `None` seeds them from the entropy of the system
+pub fn set_default_seed(seed: Option<u64>) {
+    *DEFAULT_SEED.lock() = seed;
+}
+
+/// Initialize the random number generator of the Lua VM of this thread, the
+/// seed is derived from the default seed and a stable hash of `script_name`
+pub fn init(script_name: &str) {
+    if let Some(seed) = *DEFAULT_SEED.lock() {
+        set_seed(seed ^ u64::from(util::hash_name(script_name)));
+    }
+}
+
+/// Re-seed the random number generator of the Lua VM of this thread
+pub fn set_seed(seed: u64) {
+    SCRIPT_RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Get a random integer in the range `low..high`, from the random number
+/// generator of the Lua VM of this thread
+pub fn gen_range(low: u64, high: u64) -> Result<u64> {
+    if low >= high {
+        return Err(RandomError::EmptyRange {
+            low: low as f64,
+            high: high as f64,
+        });
+    }
+
+    Ok(SCRIPT_RNG.with(|rng| rng.borrow_mut().gen_range(low, high)))
+}
+
+/// Get a seed for a new random number generator, from the random number
+/// generator of the Lua VM of this thread
+fn next_seed() -> u64 {
+    SCRIPT_RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+/// A seedable random number generator
+pub struct Random {
+    rng: StdRng,
+}
+
+impl Random {
+    /// Create a new generator, without `seed` it is seeded from the random
+    /// number generator of the Lua VM of this thread
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed.unwrap_or_else(next_seed)),
+        }
+    }
+
+    pub fn int(&mut self, low: i64, high: i64) -> Result<i64> {
+        if low >= high {
+            return Err(RandomError::EmptyRange {
+                low: low as f64,
+                high: high as f64,
+            });
+        }
+
+        Ok(self.rng.gen_range(low, high))
+    }
+
+    pub fn float(&mut self, low: f64, high: f64) -> Result<f64> {
+        // the width of the range must be finite, too
+        if !low.is_finite() || !high.is_finite() || !(high - low).is_finite() {
+            return Err(RandomError::InvalidRange { low, high });
+        }
+
+        if low >= high {
+            return Err(RandomError::EmptyRange { low, high });
+        }
+
+        Ok(self.rng.gen_range(low, high))
+    }
+
+    /// Returns true with the probability `p`
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.rng.gen_bool(p.max(0.0).min(1.0))
+    }
+}
+
+impl rlua::UserData for Random {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("seed", |_, random, seed: u64| {
+            random.rng = StdRng::seed_from_u64(seed);
+            Ok(())
+        });
+
+        methods.add_method_mut("int", |_, random, (low, high): (i64, i64)| {
+            random
+                .int(low, high)
+                .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+        });
+
+        methods.add_method_mut("float", |_, random, range: Option<(f64, f64)>| {
+            let (low, high) = range.unwrap_or((0.0, 1.0));
+
+            random
+                .float(low, high)
+                .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+        });
+
+        methods.add_method_mut("chance", |_, random, p: f64| Ok(random.chance(p)));
+
+        methods.add_method_mut("shuffle", |_, random, table: rlua::Table| {
+            let mut values = table
+                .clone()
+                .sequence_values::<rlua::Value>()
+                .collect::<rlua::Result<Vec<_>>>()?;
+
+            values.shuffle(&mut random.rng);
+
+            for (i, v) in values.into_iter().enumerate() {
+                table.set(i + 1, v)?;
+            }
+
+            Ok(table)
+        });
+    }
+}
+
+/// Parameters of a noise generator
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NoiseOptions {
+    pub seed: u32,
+
+    /// Coordinates are multiplied by the frequency
+    pub frequency: f64,
+
+    /// Number of octaves of fractal noise types
+    pub octaves: usize,
+
+    /// Frequency multiplier between successive octaves of fractal noise types
+    pub lacunarity: f64,
+
+    /// Amplitude multiplier between successive octaves of fractal noise types
+    pub persistence: f64,
+}
+
+impl Default for NoiseOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            frequency: 1.0,
+            octaves: 6,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+}
+
+enum Generator {
+    Perlin(noise::Perlin),
+    OpenSimplex(noise::OpenSimplex),
+    SuperSimplex(noise::SuperSimplex),
+    Value(noise::Value),
+    Voronoi(noise::Worley),
+    Fbm(noise::Fbm),
+    Billow(noise::Billow),
+    Ridged(noise::RidgedMulti),
+}
+
+/// A seedable noise generator, values are in the range [0.0..1.0]
+pub struct Noise {
+    name: String,
+    generator: Generator,
+    frequency: f64,
+}
+
+impl Noise {
+    /// Create a noise generator of the type `name`, e.g. "perlin" or "fbm"
+    pub fn new(name: &str, options: &NoiseOptions) -> Result<Self> {
+        let octaves = options.octaves.max(1).min(constants::MAX_NOISE_OCTAVES);
+
+        let generator = match name {
+            "perlin" => Generator::Perlin(noise::Perlin::new().set_seed(options.seed)),
+
+            "open_simplex" => {
+                Generator::OpenSimplex(noise::OpenSimplex::new().set_seed(options.seed))
+            }
+
+            "super_simplex" => {
+                Generator::SuperSimplex(noise::SuperSimplex::new().set_seed(options.seed))
+            }
+
+            "value" => Generator::Value(noise::Value::new().set_seed(options.seed)),
+            "voronoi" => Generator::Voronoi(noise::Worley::new().set_seed(options.seed)),
+
+            "fbm" => Generator::Fbm(
+                noise::Fbm::new()
+                    .set_seed(options.seed)
+                    .set_octaves(octaves)
+                    .set_lacunarity(options.lacunarity)
+                    .set_persistence(options.persistence),
+            ),
+
+            "billow" => Generator::Billow(
+                noise::Billow::new()
+                    .set_seed(options.seed)
+                    .set_octaves(octaves)
+                    .set_lacunarity(options.lacunarity)
+                    .set_persistence(options.persistence),
+            ),
+
+            "ridged" => Generator::Ridged(
+                noise::RidgedMulti::new()
+                    .set_seed(options.seed)
+                    .set_octaves(octaves)
+                    .set_lacunarity(options.lacunarity)
+                    .set_persistence(options.persistence),
+            ),
+
+            _ => {
+                return Err(RandomError::UnknownNoiseType {
+                    name: name.to_string(),
+                })
+            }
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            generator,
+            frequency: options.frequency,
+        })
+    }
+
+    pub fn get_2d(&self, x: f64, y: f64) -> f64 {
+        let p = [x * self.frequency, y * self.frequency];
+
+        let v = match &self.generator {
+            Generator::Perlin(g) => g.get(p),
+            Generator::OpenSimplex(g) => g.get(p),
+            Generator::SuperSimplex(g) => g.get(p),
+            Generator::Value(g) => g.get(p),
+            Generator::Voronoi(g) => g.get(p),
+            Generator::Fbm(g) => g.get(p),
+            Generator::Billow(g) => g.get(p),
+            Generator::Ridged(g) => g.get(p),
+        };
+
+        v / 2.0 + 0.5
+    }
+
+    pub fn get_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        let p = [x * self.frequency, y * self.frequency, z * self.frequency];
+
+        let v = match &self.generator {
+            Generator::Perlin(g) => g.get(p),
+            Generator::OpenSimplex(g) => g.get(p),
+            Generator::SuperSimplex(g) => g.get(p),
+            Generator::Value(g) => g.get(p),
+            Generator::Voronoi(g) => g.get(p),
+            Generator::Fbm(g) => g.get(p),
+            Generator::Billow(g) => g.get(p),
+            Generator::Ridged(g) => g.get(p),
+        };
+
+        v / 2.0 + 0.5
+    }
+
+    pub fn get_4d(&self, x: f64, y: f64, z: f64, w: f64) -> Result<f64> {
+        let f = self.frequency;
+        let p = [x * f, y * f, z * f, w * f];
+
+        let v = match &self.generator {
+            Generator::Perlin(g) => g.get(p),
+            Generator::OpenSimplex(g) => g.get(p),
+            Generator::Value(g) => g.get(p),
+            Generator::Voronoi(g) => g.get(p),
+            Generator::Fbm(g) => g.get(p),
+            Generator::Billow(g) => g.get(p),
+            Generator::Ridged(g) => g.get(p),
+
+            Generator::SuperSimplex(_) => {
+                return Err(RandomError::UnsupportedDimensions {
+                    name: self.name.clone(),
+                    dims: 4,
+                })
+            }
+        };
+
+        Ok(v / 2.0 + 0.5)
+    }
+}
+
+impl rlua::UserData for Noise {
+    fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "get",
+            |_, noise, (x, y, z, w): (f64, f64, Option<f64>, Option<f64>)| match (z, w) {
+                (None, _) => Ok(noise.get_2d(x, y)),
+                (Some(z), None) => Ok(noise.get_3d(x, y, z)),
+
+                (Some(z), Some(w)) => noise
+                    .get_4d(x, y, z, w)
+                    .map_err(|e| rlua::Error::RuntimeError(format!("{}", e))),
+            },
+        );
+    }
+}
+
+#[test]
+fn test_random() {
+    let mut a = Random::new(Some(42));
+    let mut b = Random::new(Some(42));
+    let mut c = Random::new(Some(43));
+
+    let seq_a: Vec<i64> = (0..16).map(|_| a.int(0, 1000).unwrap()).collect();
+    let seq_b: Vec<i64> = (0..16).map(|_| b.int(0, 1000).unwrap()).collect();
+    let seq_c: Vec<i64> = (0..16).map(|_| c.int(0, 1000).unwrap()).collect();
+
+    assert_eq!(seq_a, seq_b);
+    assert_ne!(seq_a, seq_c);
+    assert!(a.int(1, 1).is_err());
+    assert!(a.float(0.0, std::f64::INFINITY).is_err());
+    assert!(a.float(std::f64::NAN, 1.0).is_err());
+    assert!(a.float(-std::f64::MAX, std::f64::MAX).is_err());
+
+    set_seed(7);
+    let first = gen_range(0, 1_000_000).unwrap();
+    set_seed(7);
+    assert_eq!(gen_range(0, 1_000_000).unwrap(), first);
+}
+
+#[test]
+fn test_noise() {
+    let options = NoiseOptions {
+        seed: 1,
+        frequency: 0.5,
+        ..NoiseOptions::default()
+    };
+
+    let a = Noise::new("fbm", &options).unwrap();
+    let b = Noise::new("fbm", &options).unwrap();
+    let c = Noise::new("fbm", &NoiseOptions { seed: 2, ..options }).unwrap();
+
+    let p = (1.3, 2.7, 0.4);
+
+    assert_eq!(a.get_3d(p.0, p.1, p.2), b.get_3d(p.0, p.1, p.2));
+    assert_ne!(a.get_3d(p.0, p.1, p.2), c.get_3d(p.0, p.1, p.2));
+
+    assert!(Noise::new("unknown", &options).is_err());
+    assert!(Noise::new("super_simplex", &options)
+        .unwrap()
+        .get_4d(0.0, 0.0, 0.0, 0.0)
+        .is_err());
+}
@@ -19,28 +19,62 @@ use failure::Fail;
 use lazy_static::lazy_static;
 use log::*;
 use parking_lot::RwLock;
-use rand::Rng;
 use rlua::{Context, Function, Lua};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 use std::vec::Vec;
 
-use crate::hwdevices::{HidEvent, HwDevice, NUM_KEYS, RGBA};
+use crate::canvas::{self, Canvas};
+use crate::canvas2d::{Canvas2D, Fill, Paint};
+use crate::color::{self, ColorSpace, Gradient};
+use crate::hwdevices::layout::{self, KeyboardVariant, KEY_UNIT_MM};
+use crate::hwdevices::{HidEvent, HwDevice};
+use crate::image::Image;
 use crate::plugin_manager;
+use crate::recording::{self, Player};
+use crate::scripting::animation::{self, Timeline};
+use crate::scripting::bus::{self, BusValue};
+use crate::scripting::debugger;
+use crate::scripting::dependencies;
+use crate::scripting::event_api;
 use crate::scripting::manifest::{ConfigParam, Manifest};
+use crate::scripting::particles::{self, ParticleOptions, RippleOptions};
+use crate::scripting::random::{self, Noise, NoiseOptions, Random};
+use crate::scripting::serialize;
+use crate::scripting::stats;
+use crate::text;
+use crate::util;
+use crate::visualize;
 
 use crate::{SystemEvent, ACTIVE_PROFILE, ACTIVE_SCRIPTS};
 
+/// Timing information of a timer tick
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Tick {
+    /// Frame number, increases by one on each tick
+    pub frame: u64,
+
+    /// Real time since the previous tick, in seconds
+    pub delta: f64,
+
+    /// Real time since the daemon has been started, in seconds
+    pub time: f64,
+
+    /// Time since the previous tick in units of `1 / TARGET_FPS` seconds. This
+    /// is the `delta` that is passed to `on_tick` of the event API versions 1 and 2
+    pub ticks: u32,
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     // Startup, // Not passed via message but invoked directly
     Quit(u32),
-    Tick(u32),
+    Tick(Tick),
 
     // Keyboard events
     KeyDown(u8),
@@ -58,6 +92,9 @@ pub enum Message {
     // System events
     SystemEvent(SystemEvent),
 
+    // Messages published on the inter-script message bus
+    BusMessage(String, BusValue),
+
     //LoadScript(PathBuf),
     // Abort,
     Unload,
@@ -67,13 +104,8 @@ pub enum Message {
 }
 
 lazy_static! {
-    /// Global LED state of the managed device
-    pub static ref LED_MAP: Arc<RwLock<Vec<RGBA>>> = Arc::new(RwLock::new(vec![RGBA {
-        r: 0x00,
-        g: 0x00,
-        b: 0x00,
-        a: 0x00,
-    }; NUM_KEYS]));
+    /// Global LED state of the managed device, in linear light
+    pub static ref LED_MAP: Arc<RwLock<Canvas>> = Arc::new(RwLock::new(canvas::new_canvas()));
 
     /// Frame generation counter, used to detect if we need to submit the LED_MAP to the keyboard
     pub static ref FRAME_GENERATION_COUNTER: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
@@ -81,12 +113,10 @@ lazy_static! {
 
 thread_local! {
     /// LED color map to be realized on the next render frame
-    pub static LOCAL_LED_MAP: RefCell<Vec<RGBA>> = RefCell::new(vec![RGBA {
-        r: 0x00,
-        g: 0x00,
-        b: 0x00,
-        a: 0x00,
-    }; NUM_KEYS]);
+    pub static LOCAL_LED_MAP: RefCell<Canvas> = RefCell::new(canvas::new_canvas());
+
+    /// The timer tick that is currently being processed
+    static CURRENT_TICK: Cell<Tick> = Cell::new(Tick::default());
 }
 
 pub type Result<T> = std::result::Result<T, ScriptingError>;
@@ -101,6 +131,12 @@ pub enum ScriptingError {
 
     #[fail(display = "Invalid or inaccessible manifest file")]
     InaccessibleManifest {},
+
+    #[fail(display = "Unknown native effect")]
+    UnknownEffect {},
+
+    #[fail(display = "Script is not compatible with this version of Eruption")]
+    IncompatibleScript {},
     // #[fail(display = "Unknown error: {}", description)]
     // UnknownError { description: String },
 }
@@ -119,7 +155,8 @@ mod callbacks {
 
     use super::{LED_MAP, LOCAL_LED_MAP};
 
-    use crate::hwdevices::{HwDevice, LedKind, NUM_KEYS, RGBA};
+    use crate::canvas::{self, Color};
+    use crate::hwdevices::{HwDevice, LedKind, NUM_KEYS};
     use crate::plugins::macros;
 
     /// Log a message with severity level `trace`.
@@ -467,16 +504,11 @@ mod callbacks {
     /// Set the color of the key `idx` to `c`.
     pub(crate) fn set_key_color(hwdevice: &HwDevice, idx: usize, c: u32) {
         let mut led_map = LED_MAP.write();
-        led_map[idx] = RGBA {
-            a: u8::try_from((c >> 24) & 0xff).unwrap(),
-            r: u8::try_from((c >> 16) & 0xff).unwrap(),
-            g: u8::try_from((c >> 8) & 0xff).unwrap(),
-            b: u8::try_from(c & 0xff).unwrap(),
-        };
+        led_map[idx] = Color::from_u32(c);
 
         hwdevice
             .write()
-            .send_led_map(&*led_map)
+            .send_led_map(&canvas::to_led_map(&*led_map, 1.0, None))
             .unwrap_or_else(|e| error!("Could not send the LED map to the keyboard: {}", e));
 
         thread::sleep(Duration::from_millis(
@@ -486,15 +518,11 @@ mod callbacks {
 
     /// Get state of all LEDs
     pub(crate) fn get_color_map() -> Vec<u32> {
-        let global_led_map = LED_MAP.write();
+        let global_led_map = LED_MAP.read();
 
         let result = global_led_map
             .iter()
-            .map(|v| {
-                ((v.r as u32).overflowing_shl(16).0
-                    + (v.g as u32).overflowing_shl(8).0
-                    + v.b as u32) as u32
-            })
+            .map(|v| v.to_u32() & 0x00ffffff)
             .collect::<Vec<u32>>();
 
         assert!(result.len() == NUM_KEYS);
@@ -506,38 +534,18 @@ mod callbacks {
     pub(crate) fn set_color_map(hwdevice: &HwDevice, map: &[u32]) {
         assert!(map.len() == NUM_KEYS);
 
-        let mut led_map = [RGBA {
-            r: 0,
-            g: 0,
-            b: 0,
-            a: 0,
-        }; NUM_KEYS];
-
-        let mut i = 0;
-        loop {
-            led_map[i] = RGBA {
-                a: u8::try_from((map[i] >> 24) & 0xff).unwrap(),
-                r: u8::try_from((map[i] >> 16) & 0xff).unwrap(),
-                g: u8::try_from((map[i] >> 8) & 0xff).unwrap(),
-                b: u8::try_from(map[i] & 0xff).unwrap(),
-            };
+        let led_map = canvas::from_color_map(map);
 
-            i += 1;
-            if i >= NUM_KEYS - 1 {
-                break;
-            }
-        }
+        hwdevice
+            .write()
+            .send_led_map(&canvas::to_led_map(&led_map, 1.0, None))
+            .unwrap_or_else(|e| error!("Could not send the LED map to the keyboard: {}", e));
 
         {
             let mut global_led_map = LED_MAP.write();
-            *global_led_map = led_map.to_vec();
+            *global_led_map = led_map;
         }
 
-        hwdevice
-            .write()
-            .send_led_map(&led_map)
-            .unwrap_or_else(|e| error!("Could not send the LED map to the keyboard: {}", e));
-
         thread::sleep(Duration::from_millis(
             crate::constants::DEVICE_SETTLE_MILLIS,
         ));
@@ -557,33 +565,67 @@ mod callbacks {
             )
         );
 
-        let mut led_map = [RGBA {
-            r: 0,
-            g: 0,
-            b: 0,
-            a: 0,
-        }; NUM_KEYS];
-
-        let mut i = 0;
-        loop {
-            led_map[i] = RGBA {
-                a: u8::try_from((map[i] >> 24) & 0xff).unwrap(),
-                r: u8::try_from((map[i] >> 16) & 0xff).unwrap(),
-                g: u8::try_from((map[i] >> 8) & 0xff).unwrap(),
-                b: u8::try_from(map[i] & 0xff).unwrap(),
-            };
-
-            i += 1;
-            if i >= NUM_KEYS - 1 {
-                break;
+        LOCAL_LED_MAP.with(|local_map| {
+            for (dst, src) in local_map.borrow_mut().iter_mut().zip(map.iter()) {
+                *dst = Color::from_u32(*src);
             }
-        }
+        });
 
-        LOCAL_LED_MAP.with(|local_map| local_map.borrow_mut().copy_from_slice(&led_map));
         super::FRAME_GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst);
     }
 }
 
+/// Blend the thread local color map with the global canvas ("realize" the
+/// color map) and notify the main thread that we are done
+pub(crate) fn realize_color_map(script_name: &str) {
+    // blend in linear light, brightness and the conversion
+    // to sRGB are applied only once, when the canvas gets
+    // sent to the device
+    LOCAL_LED_MAP.with(|foreground| {
+        let foreground = foreground.borrow();
+
+        let recording_layer = recording::is_recording_layer(script_name);
+        let exporting_layer = visualize::is_exporting_layers();
+
+        if recording_layer || exporting_layer {
+            // keep the colors of the layer unblended, so that it can be played
+            // back or shown with its original opacity
+            let mut layer = foreground.clone();
+
+            particles::blend_layer(&mut layer);
+
+            if recording_layer {
+                recording::record_layer(script_name, &layer);
+            }
+
+            if exporting_layer {
+                visualize::export_layer(script_name, &layer);
+            }
+        }
+
+        let mut led_map = LED_MAP.write();
+
+        for (background, fg) in led_map.iter_mut().zip(foreground.iter()) {
+            *background = fg.over(background);
+        }
+
+        // particles are drawn on top of the color map of the script
+        particles::blend_layer(&mut led_map);
+    });
+
+    // signal readiness / notify the main thread that we are done
+    let val = { *crate::COLOR_MAPS_READY_CONDITION.0.lock() };
+
+    let val = val.checked_sub(1).unwrap_or_else(|| {
+        warn!("Incorrect state in locking code detected");
+        0
+    });
+
+    *crate::COLOR_MAPS_READY_CONDITION.0.lock() = val;
+
+    crate::COLOR_MAPS_READY_CONDITION.1.notify_one();
+}
+
 /// Action requests for `run_script`
 pub enum RunScriptResult {
     /// Script terminated gracefully
@@ -615,16 +657,28 @@ pub fn run_script(
                 );
 
                 return Err(ScriptingError::InaccessibleManifest {});
+            } else if let Err(error) = manifest.as_ref().unwrap().check_compatibility() {
+                error!("{}", error);
+
+                return Err(ScriptingError::IncompatibleScript {});
             } else {
                 ACTIVE_SCRIPTS
                     .lock()
                     .push(manifest.as_ref().unwrap().clone());
             }
 
+            let script_name = file.file_name().unwrap().to_string_lossy().to_string();
+            let event_api = manifest.as_ref().unwrap().event_api;
+
+            random::init(&script_name);
+
+            // make this VM available to the debugger, until the script terminates
+            let _debugger_guard = debugger::register_vm(&script_name);
+
             let result: rlua::Result<RunScriptResult> = lua.context::<_, _>(|lua_ctx| {
                 let mut errors_present = false;
 
-                if register_support_globals(lua_ctx, &hwdevice).is_err() {
+                if register_support_globals(lua_ctx, &hwdevice, &file).is_err() {
                     return Ok(RunScriptResult::TerminatedWithErrors);
                 }
 
@@ -660,12 +714,18 @@ pub fn run_script(
 
                 loop {
                     if let Ok(msg) = rx.recv() {
+                        // serve requests of attached debugger sessions
+                        debugger::process_requests(lua_ctx);
+
                         match msg {
                             Message::Quit(param) => {
                                 let mut errors_present = false;
 
                                 if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_quit")
                                 {
+                                    debugger::check_breakpoint(lua_ctx, "on_quit");
+                                    let _measurement = stats::measure(&script_name, "on_quit");
+
                                     handler.call::<_, ()>(param).unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
@@ -680,15 +740,37 @@ pub fn run_script(
                                 }
                             }
 
-                            Message::Tick(param) => {
+                            Message::Tick(tick) => {
                                 let mut errors_present = false;
 
+                                CURRENT_TICK.with(|t| t.set(tick));
+
+                                // update the properties of bound animations first
+                                animation::update(lua_ctx).unwrap_or_else(|e| {
+                                    error!("Lua error in animation: {}", e);
+                                    errors_present = true;
+                                });
+
                                 if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_tick")
                                 {
-                                    handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    debugger::check_breakpoint(lua_ctx, "on_tick");
+                                    let _measurement = stats::measure(&script_name, "on_tick");
+
+                                    let result = if event_api >= 3 {
+                                        event_api::tick_event(lua_ctx, &tick)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>(tick.ticks)
+                                    };
+
+                                    result.unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
-                                    })
+                                    });
+                                }
+
+                                if particles::update() {
+                                    FRAME_GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst);
                                 }
 
                                 if errors_present {
@@ -697,42 +779,8 @@ pub fn run_script(
                             }
 
                             Message::RealizeColorMap => {
-                                LOCAL_LED_MAP.with(|foreground| {
-                                    for (idx, background) in LED_MAP.write().iter_mut().enumerate() {
-                                        let bg = &background;
-                                        let fg = foreground.borrow()[idx];
-
-                                        let brightness = crate::BRIGHTNESS.load(Ordering::SeqCst);
-
-                                        #[rustfmt::skip]
-                                        let color = RGBA {
-                                            r: ((((fg.a as f64) * fg.r as f64 + (255 - fg.a) as f64 * bg.r as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                            g: ((((fg.a as f64) * fg.g as f64 + (255 - fg.a) as f64 * bg.g as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                            b: ((((fg.a as f64) * fg.b as f64 + (255 - fg.a) as f64 * bg.b as f64).abs() * brightness as f64 / 100.0) as u32 >> 8) as u8,
-                                            a: fg.a as u8,
-                                        };
-
-                                        *background = color;
-                                    }
-                                });
-
-                                // signal readiness / notify the main thread that we are done
-                                let val = { *crate::COLOR_MAPS_READY_CONDITION
-                                    .0
-                                    .lock() };
-
-                                let val = val.checked_sub(1)
-                                    .unwrap_or_else(|| {
-                                        warn!("Incorrect state in locking code detected");
-                                        0
-                                    });
-
-                                *crate::COLOR_MAPS_READY_CONDITION
-                                    .0
-                                    .lock() = val;
-
-
-                                crate::COLOR_MAPS_READY_CONDITION.1.notify_one();
+                                let _measurement = stats::measure(&script_name, "realize");
+                                realize_color_map(&script_name);
                             }
 
                             Message::KeyDown(param) => {
@@ -741,7 +789,17 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_key_down")
                                 {
-                                    handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    debugger::check_breakpoint(lua_ctx, "on_key_down");
+                                    let _measurement = stats::measure(&script_name, "on_key_down");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::key_event(lua_ctx, "key_down", param)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>(param)
+                                    };
+
+                                    result.unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
                                     });
@@ -761,7 +819,17 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_key_up")
                                 {
-                                    handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    debugger::check_breakpoint(lua_ctx, "on_key_up");
+                                    let _measurement = stats::measure(&script_name, "on_key_up");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::key_event(lua_ctx, "key_up", param)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>(param)
+                                    };
+
+                                    result.unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
                                     });
@@ -781,6 +849,9 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_hid_event")
                                 {
+                                    debugger::check_breakpoint(lua_ctx, "on_hid_event");
+                                    let _measurement = stats::measure(&script_name, "on_hid_event");
+
                                     let arg1: u8;
                                     let event_type: u32 = match param {
                                         HidEvent::KeyUp { code } => {
@@ -816,12 +887,17 @@ pub fn run_script(
                                         }
                                     };
 
-                                    handler
-                                        .call::<_, ()>((event_type, arg1))
-                                        .unwrap_or_else(|e| {
-                                            error!("Lua error: {}", e);
-                                            errors_present = true;
-                                        });
+                                    let result = if event_api >= 2 {
+                                        event_api::hid_event(lua_ctx, &param)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>((event_type, arg1))
+                                    };
+
+                                    result.unwrap_or_else(|e| {
+                                        error!("Lua error: {}", e);
+                                        errors_present = true;
+                                    });
                                 }
 
                                 *crate::UPCALL_COMPLETED_ON_HID_EVENT.0.lock() -= 1;
@@ -838,7 +914,22 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_mouse_button_down")
                                 {
-                                    handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    debugger::check_breakpoint(lua_ctx, "on_mouse_button_down");
+                                    let _measurement =
+                                        stats::measure(&script_name, "on_mouse_button_down");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::mouse_button_event(
+                                            lua_ctx,
+                                            "mouse_button_down",
+                                            param,
+                                        )
+                                        .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>(param)
+                                    };
+
+                                    result.unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
                                     });
@@ -858,7 +949,22 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_mouse_button_up")
                                 {
-                                    handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    debugger::check_breakpoint(lua_ctx, "on_mouse_button_up");
+                                    let _measurement =
+                                        stats::measure(&script_name, "on_mouse_button_up");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::mouse_button_event(
+                                            lua_ctx,
+                                            "mouse_button_up",
+                                            param,
+                                        )
+                                        .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>(param)
+                                    };
+
+                                    result.unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
                                     });
@@ -878,12 +984,21 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_mouse_move")
                                 {
-                                    handler.call::<_, ()>((rel_x, rel_y, rel_z)).unwrap_or_else(
-                                        |e| {
-                                            error!("Lua error: {}", e);
-                                            errors_present = true;
-                                        },
-                                    );
+                                    debugger::check_breakpoint(lua_ctx, "on_mouse_move");
+                                    let _measurement =
+                                        stats::measure(&script_name, "on_mouse_move");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::mouse_move_event(lua_ctx, rel_x, rel_y, rel_z)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>((rel_x, rel_y, rel_z))
+                                    };
+
+                                    result.unwrap_or_else(|e| {
+                                        error!("Lua error: {}", e);
+                                        errors_present = true;
+                                    });
                                 }
 
                                 *crate::UPCALL_COMPLETED_ON_MOUSE_MOVE.0.lock() -= 1;
@@ -900,7 +1015,18 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_mouse_wheel")
                                 {
-                                    handler.call::<_, ()>(param).unwrap_or_else(|e| {
+                                    debugger::check_breakpoint(lua_ctx, "on_mouse_wheel");
+                                    let _measurement =
+                                        stats::measure(&script_name, "on_mouse_wheel");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::mouse_wheel_event(lua_ctx, param)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        handler.call::<_, ()>(param)
+                                    };
+
+                                    result.unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
                                     });
@@ -920,35 +1046,43 @@ pub fn run_script(
                                 if let Ok(handler) =
                                     lua_ctx.globals().get::<_, Function>("on_system_event")
                                 {
-                                    let event_type;
-                                    let arg1;
-                                    let arg2;
-                                    let arg3;
-
-                                    match param {
-                                        SystemEvent::ProcessExec { event, file_name } => {
-                                            event_type = 0;
-
-                                            arg1 = event.pid;
-                                            arg2 = file_name.unwrap_or_default();
-                                            arg3 = 0; // TODO: implement hashing
+                                    debugger::check_breakpoint(lua_ctx, "on_system_event");
+                                    let _measurement =
+                                        stats::measure(&script_name, "on_system_event");
+
+                                    let result = if event_api >= 2 {
+                                        event_api::system_event(lua_ctx, &param)
+                                            .and_then(|event| handler.call::<_, ()>(event))
+                                    } else {
+                                        let event_type;
+                                        let arg1;
+                                        let arg2;
+
+                                        match param {
+                                            SystemEvent::ProcessExec { event, file_name } => {
+                                                event_type = 0;
+
+                                                arg1 = event.pid;
+                                                arg2 = file_name.unwrap_or_default();
+                                            }
+
+                                            SystemEvent::ProcessExit { event, file_name } => {
+                                                event_type = 1;
+
+                                                arg1 = event.pid;
+                                                arg2 = file_name.unwrap_or_default();
+                                            }
                                         }
 
-                                        SystemEvent::ProcessExit { event, file_name } => {
-                                            event_type = 1;
+                                        let arg3 = util::hash_name(&arg2);
 
-                                            arg1 = event.pid;
-                                            arg2 = file_name.unwrap_or_default();
-                                            arg3 = 0; // TODO: implement hashing
-                                        }
-                                    }
-
-                                    handler
-                                        .call::<_, ()>((event_type, arg1, arg2, arg3))
-                                        .unwrap_or_else(|e| {
-                                            error!("Lua error: {}", e);
-                                            errors_present = true;
-                                        });
+                                        handler.call::<_, ()>((event_type, arg1, arg2, arg3))
+                                    };
+
+                                    result.unwrap_or_else(|e| {
+                                        error!("Lua error: {}", e);
+                                        errors_present = true;
+                                    });
                                 }
 
                                 // *crate::UPCALL_COMPLETED_ON_SYSTEM_EVENT.0.lock() -= 1;
@@ -959,6 +1093,13 @@ pub fn run_script(
                                 }
                             }
 
+                            Message::BusMessage(topic, payload) => {
+                                if let Err(e) = bus::dispatch(lua_ctx, &topic, &payload) {
+                                    error!("Lua error: {}", e);
+                                    return Ok(RunScriptResult::TerminatedWithErrors);
+                                }
+                            }
+
                             //Message::LoadScript(script_path) => {
                             //return Ok(RunScriptResult::ReExecuteOtherScript(script_path))
                             //}
@@ -967,12 +1108,14 @@ pub fn run_script(
                             //     error!("Lua script '{}' terminated with errors", file.file_name().unwrap().to_string_lossy());
                             //     return Ok(RunScriptResult::TerminatedWithErrors);
                             // }
-
                             Message::Unload => {
                                 let mut errors_present = false;
 
                                 if let Ok(handler) = lua_ctx.globals().get::<_, Function>("on_quit")
                                 {
+                                    debugger::check_breakpoint(lua_ctx, "on_quit");
+                                    let _measurement = stats::measure(&script_name, "on_quit");
+
                                     handler.call::<_, ()>(()).unwrap_or_else(|e| {
                                         error!("Lua error: {}", e);
                                         errors_present = true;
@@ -1009,20 +1152,27 @@ pub fn run_script(
     }
 }
 
-fn register_support_globals(lua_ctx: Context, _hwdevice: &HwDevice) -> rlua::Result<()> {
+fn register_support_globals(
+    lua_ctx: Context,
+    _hwdevice: &HwDevice,
+    script_file: &Path,
+) -> rlua::Result<()> {
     let globals = lua_ctx.globals();
 
-    #[cfg(debug_assertions)]
-    lua_ctx
-        .load("package.path = package.path .. ';src/scripts/lib/?;src/scripts/lib/?.lua'")
-        .exec()
-        .unwrap();
-
-    #[cfg(not(debug_assertions))]
-    lua_ctx
-        .load("package.path = package.path .. ';/usr/share/eruption/scripts/lib/?;/usr/share/eruption/scripts/lib/?.lua'")
-        .exec()
-        .unwrap();
+    // search library modules in the library path first
+    let script_dir = script_file.parent().unwrap_or_else(|| Path::new("."));
+    let library_path = dependencies::get_library_path(script_dir);
+
+    let package: rlua::Table = globals.get("package")?;
+    let package_path: String = package.get("path")?;
+    package.set(
+        "path",
+        format!(
+            "{};{}",
+            dependencies::get_lua_package_path(&library_path),
+            package_path
+        ),
+    )?;
 
     let mut config: HashMap<&str, &str> = HashMap::new();
     config.insert("daemon_name", "eruption");
@@ -1031,6 +1181,35 @@ fn register_support_globals(lua_ctx: Context, _hwdevice: &HwDevice) -> rlua::Res
 
     globals.set("config", config)?;
 
+    // palettes are searched in the library path as well
+    let load_palette = lua_ctx.create_function(move |lua_ctx, name: String| {
+        let palette = color::load_palette(&name, &library_path)
+            .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))?;
+
+        let result = lua_ctx.create_table()?;
+        result.set("name", palette.name)?;
+        result.set("colors", palette.colors)?;
+        result.set("named", palette.named)?;
+
+        Ok(result)
+    })?;
+    globals.set("load_palette", load_palette)?;
+
+    // image file names are relative to the directory of the script
+    let image_dir = script_dir.to_path_buf();
+    let load_image = lua_ctx.create_function(move |_, file_name: String| {
+        Image::load(&image_dir.join(file_name))
+            .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+    })?;
+    globals.set("load_image", load_image)?;
+
+    // file names of recordings are relative to the recording directory
+    let load_recording = lua_ctx.create_function(move |_, file_name: String| {
+        Player::load(&recording::get_recording_dir().join(file_name))
+            .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+    })?;
+    globals.set("load_recording", load_recording)?;
+
     Ok(())
 }
 
@@ -1042,30 +1221,35 @@ fn register_support_funcs(lua_ctx: Context, hwdevice: &HwDevice) -> rlua::Result
     // logging
     let trace = lua_ctx.create_function(|_, msg: String| {
         callbacks::log_trace(&msg);
+        debugger::forward_log("TRACE", &msg);
         Ok(())
     })?;
     globals.set("trace", trace)?;
 
     let debug = lua_ctx.create_function(|_, msg: String| {
         callbacks::log_debug(&msg);
+        debugger::forward_log("DEBUG", &msg);
         Ok(())
     })?;
     globals.set("debug", debug)?;
 
     let info = lua_ctx.create_function(|_, msg: String| {
         callbacks::log_info(&msg);
+        debugger::forward_log("INFO", &msg);
         Ok(())
     })?;
     globals.set("info", info)?;
 
     let warn = lua_ctx.create_function(|_, msg: String| {
         callbacks::log_warn(&msg);
+        debugger::forward_log("WARN", &msg);
         Ok(())
     })?;
     globals.set("warn", warn)?;
 
     let error = lua_ctx.create_function(|_, msg: String| {
         callbacks::log_error(&msg);
+        debugger::forward_log("ERROR", &msg);
         Ok(())
     })?;
     globals.set("error", error)?;
@@ -1076,6 +1260,33 @@ fn register_support_funcs(lua_ctx: Context, hwdevice: &HwDevice) -> rlua::Result
     })?;
     globals.set("delay", delay)?;
 
+    // timekeeping
+    let get_frame = lua_ctx.create_function(|_, ()| Ok(CURRENT_TICK.with(|t| t.get().frame)))?;
+    globals.set("get_frame", get_frame)?;
+
+    let get_time = lua_ctx.create_function(|_, ()| Ok(CURRENT_TICK.with(|t| t.get().time)))?;
+    globals.set("get_time", get_time)?;
+
+    let get_delta_time =
+        lua_ctx.create_function(|_, ()| Ok(CURRENT_TICK.with(|t| t.get().delta)))?;
+    globals.set("get_delta_time", get_delta_time)?;
+
+    let get_local_time = lua_ctx.create_function(|_, ()| {
+        let now =
+            util::get_local_time().map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))?;
+
+        Ok((now.hour, now.minute, now.second, now.millisecond))
+    })?;
+    globals.set("get_local_time", get_local_time)?;
+
+    let get_local_date = lua_ctx.create_function(|_, ()| {
+        let now =
+            util::get_local_time().map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))?;
+
+        Ok((now.year, now.month, now.day, now.weekday))
+    })?;
+    globals.set("get_local_date", get_local_date)?;
+
     // math library
     let max = lua_ctx.create_function(|_, (f1, f2): (f64, f64)| Ok(f1.max(f2)))?;
     globals.set("max", max)?;
@@ -1102,10 +1313,20 @@ fn register_support_funcs(lua_ctx: Context, hwdevice: &HwDevice) -> rlua::Result
     let sqrt = lua_ctx.create_function(|_, f: f64| Ok(f.sqrt()))?;
     globals.set("sqrt", sqrt)?;
 
-    let rand =
-        lua_ctx.create_function(|_, (l, h): (u64, u64)| Ok(rand::thread_rng().gen_range(l, h)))?;
+    let rand = lua_ctx.create_function(|_, (l, h): (u64, u64)| {
+        random::gen_range(l, h).map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+    })?;
     globals.set("rand", rand)?;
 
+    let set_random_seed = lua_ctx.create_function(|_, seed: u64| {
+        random::set_seed(seed);
+        Ok(())
+    })?;
+    globals.set("set_random_seed", set_random_seed)?;
+
+    let create_random = lua_ctx.create_function(|_, seed: Option<u64>| Ok(Random::new(seed)))?;
+    globals.set("create_random", create_random)?;
+
     let trunc = lua_ctx.create_function(|_, f: f64| Ok(f.trunc() as i64))?;
     globals.set("trunc", trunc)?;
 
@@ -1190,6 +1411,335 @@ fn register_support_funcs(lua_ctx: Context, hwdevice: &HwDevice) -> rlua::Result
     })?;
     globals.set("linear_gradient", linear_gradient)?;
 
+    // perceptual color spaces and gradients
+    fn get_color_space(name: Option<String>) -> rlua::Result<ColorSpace> {
+        ColorSpace::from_name(name.as_deref().unwrap_or("oklab"))
+            .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+    }
+
+    let color_to_hsv = lua_ctx.create_function(|_, c: u32| Ok(color::color_to_hsv(c)))?;
+    globals.set("color_to_hsv", color_to_hsv)?;
+
+    let hsv_to_color =
+        lua_ctx.create_function(|_, (h, s, v, a): (f64, f64, f64, Option<f64>)| {
+            Ok(color::hsv_to_color(h, s, v, a.unwrap_or(1.0)))
+        })?;
+    globals.set("hsv_to_color", hsv_to_color)?;
+
+    let color_to_oklab = lua_ctx.create_function(|_, c: u32| Ok(color::color_to_oklab(c)))?;
+    globals.set("color_to_oklab", color_to_oklab)?;
+
+    let oklab_to_color =
+        lua_ctx.create_function(|_, (l, a, b, alpha): (f64, f64, f64, Option<f64>)| {
+            Ok(color::oklab_to_color(l, a, b, alpha.unwrap_or(1.0)))
+        })?;
+    globals.set("oklab_to_color", oklab_to_color)?;
+
+    let color_to_oklch = lua_ctx.create_function(|_, c: u32| Ok(color::color_to_oklch(c)))?;
+    globals.set("color_to_oklch", color_to_oklch)?;
+
+    let oklch_to_color =
+        lua_ctx.create_function(|_, (l, c, h, alpha): (f64, f64, f64, Option<f64>)| {
+            Ok(color::oklch_to_color(l, c, h, alpha.unwrap_or(1.0)))
+        })?;
+    globals.set("oklch_to_color", oklch_to_color)?;
+
+    let mix_colors =
+        lua_ctx.create_function(|_, (c1, c2, p, space): (u32, u32, f64, Option<String>)| {
+            Ok(color::mix(c1, c2, p, get_color_space(space)?))
+        })?;
+    globals.set("mix_colors", mix_colors)?;
+
+    let create_gradient =
+        lua_ctx.create_function(|_, (stops, space): (Vec<rlua::Value>, Option<String>)| {
+            let space = get_color_space(space)?;
+
+            // either a list of colors, or a list of { position, color } pairs
+            if stops.iter().all(|s| match s {
+                rlua::Value::Table(_) => false,
+                _ => true,
+            }) {
+                let colors = stops
+                    .into_iter()
+                    .map(|s| match s {
+                        rlua::Value::Integer(i) => Ok(i as u32),
+                        rlua::Value::Number(f) => Ok(f as u32),
+                        _ => Err(rlua::Error::RuntimeError("Invalid color".to_string())),
+                    })
+                    .collect::<rlua::Result<Vec<u32>>>()?;
+
+                Ok(Gradient::from_colors(&colors, space))
+            } else {
+                let stops = stops
+                    .into_iter()
+                    .map(|s| match s {
+                        rlua::Value::Table(t) => match (t.get::<_, f64>(1)?, t.get::<_, u32>(2)?) {
+                            (p, _) if !p.is_finite() => Err(rlua::Error::RuntimeError(
+                                "Invalid position of color stop".to_string(),
+                            )),
+
+                            stop => Ok(stop),
+                        },
+                        _ => Err(rlua::Error::RuntimeError("Invalid color stop".to_string())),
+                    })
+                    .collect::<rlua::Result<Vec<(f64, u32)>>>()?;
+
+                Ok(Gradient::new(stops, space))
+            }
+        })?;
+    globals.set("create_gradient", create_gradient)?;
+
+    // color map utilities, operating on whole color maps at once
+    let map_mix = lua_ctx.create_function(
+        |_, (map1, map2, p, space): (Vec<u32>, Vec<u32>, f64, Option<String>)| {
+            Ok(color::map_mix(&map1, &map2, p, get_color_space(space)?))
+        },
+    )?;
+    globals.set("map_mix", map_mix)?;
+
+    let map_scale = lua_ctx
+        .create_function(|_, (map, factor): (Vec<u32>, f64)| Ok(color::map_scale(&map, factor)))?;
+    globals.set("map_scale", map_scale)?;
+
+    let map_adjust = lua_ctx.create_function(
+        |_, (map, degrees, saturation): (Vec<u32>, f64, Option<f64>)| {
+            Ok(color::map_adjust(&map, degrees, saturation.unwrap_or(1.0)))
+        },
+    )?;
+    globals.set("map_adjust", map_adjust)?;
+
+    // animations
+    let ease = lua_ctx.create_function(|_, (name, t): (String, f64)| {
+        Ok(animation::get_easing(Some(name))?.apply(t))
+    })?;
+    globals.set("ease", ease)?;
+
+    // (from, to, duration [, easing [, repeat]])
+    type TweenArgs<T> = (T, T, f64, Option<String>, Option<String>);
+
+    let tween =
+        lua_ctx.create_function(|_, (from, to, duration, easing, repeat): TweenArgs<f64>| {
+            Ok(Timeline::tween(
+                from,
+                to,
+                duration,
+                animation::get_easing(easing)?,
+                animation::get_repeat_mode(repeat)?,
+                false,
+            ))
+        })?;
+    globals.set("tween", tween)?;
+
+    let color_tween =
+        lua_ctx.create_function(|_, (from, to, duration, easing, repeat): TweenArgs<u32>| {
+            Ok(Timeline::tween(
+                f64::from(from),
+                f64::from(to),
+                duration,
+                animation::get_easing(easing)?,
+                animation::get_repeat_mode(repeat)?,
+                true,
+            ))
+        })?;
+    globals.set("color_tween", color_tween)?;
+
+    let timeline = lua_ctx.create_function(
+        |_, (keyframes, repeat): (Vec<rlua::Table>, Option<String>)| {
+            Ok(Timeline::new(
+                animation::get_keyframes(keyframes)?,
+                animation::get_repeat_mode(repeat)?,
+                false,
+            ))
+        },
+    )?;
+    globals.set("timeline", timeline)?;
+
+    let color_timeline = lua_ctx.create_function(
+        |_, (keyframes, repeat): (Vec<rlua::Table>, Option<String>)| {
+            Ok(Timeline::new(
+                animation::get_keyframes(keyframes)?,
+                animation::get_repeat_mode(repeat)?,
+                true,
+            ))
+        },
+    )?;
+    globals.set("color_timeline", color_timeline)?;
+
+    type AnimateArgs<'lua> = (
+        rlua::Table<'lua>,
+        rlua::Value<'lua>,
+        rlua::AnyUserData<'lua>,
+        Option<Function<'lua>>,
+    );
+
+    let animate = lua_ctx.create_function(
+        |lua_ctx, (target, key, timeline, on_complete): AnimateArgs| {
+            animation::animate(lua_ctx, target, key, timeline, on_complete)
+        },
+    )?;
+    globals.set("animate", animate)?;
+
+    let cancel_animation =
+        lua_ctx.create_function(|lua_ctx, handle: i64| animation::cancel(lua_ctx, handle))?;
+    globals.set("cancel_animation", cancel_animation)?;
+
+    // particles and ripples
+    fn get_position(key_index: usize, options: &Option<rlua::Table>) -> rlua::Result<(f64, f64)> {
+        let (x, y) = particles::get_key_position(key_index).unwrap_or((0.0, 0.0));
+
+        match options {
+            Some(options) => Ok((
+                options.get::<_, Option<f64>>("x")?.unwrap_or(x),
+                options.get::<_, Option<f64>>("y")?.unwrap_or(y),
+            )),
+
+            None => Ok((x, y)),
+        }
+    }
+
+    let emit_particles =
+        lua_ctx.create_function(|_, (key_index, options): (usize, Option<rlua::Table>)| {
+            let (x, y) = get_position(key_index, &options)?;
+
+            let (count, rate, duration) = match &options {
+                Some(o) => (
+                    o.get::<_, Option<usize>>("count")?,
+                    o.get::<_, Option<f64>>("rate")?,
+                    o.get::<_, Option<f64>>("duration")?,
+                ),
+
+                None => (None, None, None),
+            };
+
+            if let Some(rate) = rate {
+                if !rate.is_finite() {
+                    return Err(rlua::Error::RuntimeError(format!(
+                        "Invalid rate of particles: {}",
+                        rate
+                    )));
+                }
+            }
+
+            let options = ParticleOptions::from_lua(options)?;
+
+            particles::ENGINE.with(|engine| {
+                let mut engine = engine.borrow_mut();
+
+                // spawn particles continuously if a rate has been specified, else all at once
+                match rate {
+                    Some(rate) => engine.add_emitter(x, y, rate, duration.unwrap_or(1.0), &options),
+                    None => engine.spawn_burst(x, y, count.unwrap_or(16), &options),
+                }
+            });
+
+            Ok(())
+        })?;
+    globals.set("emit_particles", emit_particles)?;
+
+    let spawn_ripple =
+        lua_ctx.create_function(|_, (key_index, options): (usize, Option<rlua::Table>)| {
+            let (x, y) = get_position(key_index, &options)?;
+            let options = RippleOptions::from_lua(options)?;
+
+            particles::ENGINE.with(|engine| engine.borrow_mut().spawn_ripple(x, y, &options));
+
+            Ok(())
+        })?;
+    globals.set("spawn_ripple", spawn_ripple)?;
+
+    let clear_particles = lua_ctx.create_function(|_, ()| {
+        particles::ENGINE.with(|engine| engine.borrow_mut().clear());
+        Ok(())
+    })?;
+    globals.set("clear_particles", clear_particles)?;
+
+    let get_num_particles = lua_ctx.create_function(|_, ()| {
+        Ok(particles::ENGINE.with(|engine| engine.borrow().get_num_particles()))
+    })?;
+    globals.set("get_num_particles", get_num_particles)?;
+
+    // vector canvas
+    let create_canvas = lua_ctx.create_function(|_, ()| Ok(Canvas2D::new()))?;
+    globals.set("create_canvas", create_canvas)?;
+
+    let create_linear_fill = lua_ctx.create_function(
+        |_, (x1, y1, x2, y2, gradient): (f64, f64, f64, f64, rlua::AnyUserData)| {
+            let gradient = gradient.borrow::<Gradient>()?.clone();
+
+            Ok(Fill(Paint::Linear {
+                x1,
+                y1,
+                x2,
+                y2,
+                gradient,
+            }))
+        },
+    )?;
+    globals.set("create_linear_fill", create_linear_fill)?;
+
+    let create_radial_fill = lua_ctx.create_function(
+        |_, (x, y, radius, gradient): (f64, f64, f64, rlua::AnyUserData)| {
+            let gradient = gradient.borrow::<Gradient>()?.clone();
+
+            Ok(Fill(Paint::Radial {
+                x,
+                y,
+                radius,
+                gradient,
+            }))
+        },
+    )?;
+    globals.set("create_radial_fill", create_radial_fill)?;
+
+    let get_key_geometry = lua_ctx.create_function(|_, key_index: usize| {
+        let key = if key_index > 0 {
+            layout::get_key(KeyboardVariant::from_config(), key_index - 1)
+        } else {
+            None
+        };
+
+        let key = key.ok_or_else(|| {
+            rlua::Error::RuntimeError(format!("Invalid key index: {}", key_index))
+        })?;
+
+        Ok((
+            key.x * KEY_UNIT_MM,
+            key.y * KEY_UNIT_MM,
+            key.width * KEY_UNIT_MM,
+            key.height * KEY_UNIT_MM,
+        ))
+    })?;
+    globals.set("get_key_geometry", get_key_geometry)?;
+
+    // text rendering
+    let draw_text = lua_ctx.create_function(
+        |_, (s, x, color, background): (String, f64, u32, Option<u32>)| {
+            Ok(text::draw(
+                &text::render(&s),
+                x,
+                color,
+                background.unwrap_or(0),
+            ))
+        },
+    )?;
+    globals.set("draw_text", draw_text)?;
+
+    let scroll_text = lua_ctx.create_function(
+        |_, (s, step, color, background): (String, f64, u32, Option<u32>)| {
+            let bitmap = text::render(&s);
+            let x = text::get_scroll_position(&bitmap, step);
+
+            Ok(text::draw(&bitmap, x, color, background.unwrap_or(0)))
+        },
+    )?;
+    globals.set("scroll_text", scroll_text)?;
+
+    let text_width = lua_ctx.create_function(|_, s: String| Ok(text::render(&s).width))?;
+    globals.set("text_width", text_width)?;
+
+    let hash_name = lua_ctx.create_function(|_, name: String| Ok(util::hash_name(&name)))?;
+    globals.set("hash_name", hash_name)?;
+
     // noise utilities
 
     // fast implementations (SIMD)
@@ -1259,6 +1809,54 @@ fn register_support_funcs(lua_ctx: Context, hwdevice: &HwDevice) -> rlua::Result
     })?;
     globals.set("super_simplex_noise", super_simplex_noise)?;
 
+    // seedable noise generators
+    let create_noise =
+        lua_ctx.create_function(|_, (name, options): (String, Option<rlua::Table>)| {
+            let mut result = NoiseOptions::default();
+
+            if let Some(options) = options {
+                result.seed = options
+                    .get::<_, Option<u32>>("seed")?
+                    .unwrap_or(result.seed);
+                result.frequency = options
+                    .get::<_, Option<f64>>("frequency")?
+                    .unwrap_or(result.frequency);
+                result.octaves = options
+                    .get::<_, Option<usize>>("octaves")?
+                    .unwrap_or(result.octaves);
+                result.lacunarity = options
+                    .get::<_, Option<f64>>("lacunarity")?
+                    .unwrap_or(result.lacunarity);
+                result.persistence = options
+                    .get::<_, Option<f64>>("persistence")?
+                    .unwrap_or(result.persistence);
+            }
+
+            Noise::new(&name, &result).map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+        })?;
+    globals.set("create_noise", create_noise)?;
+
+    // serialization
+    let json_encode =
+        lua_ctx.create_function(|_, (value, pretty): (rlua::Value, Option<bool>)| {
+            serialize::json_encode(value, pretty.unwrap_or(false))
+                .map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+        })?;
+    globals.set("json_encode", json_encode)?;
+
+    let json_decode =
+        lua_ctx.create_function(|lua_ctx, json: String| serialize::json_decode(lua_ctx, &json))?;
+    globals.set("json_decode", json_decode)?;
+
+    let toml_encode = lua_ctx.create_function(|_, value: rlua::Value| {
+        serialize::toml_encode(value).map_err(|e| rlua::Error::RuntimeError(format!("{}", e)))
+    })?;
+    globals.set("toml_encode", toml_encode)?;
+
+    let toml_decode = lua_ctx
+        .create_function(|lua_ctx, document: String| serialize::toml_decode(lua_ctx, &document))?;
+    globals.set("toml_decode", toml_decode)?;
+
     // transformation utilities
     let rotate = lua_ctx.create_function(|_, (map, theta): (Vec<u32>, f64)| {
         Ok(callbacks::rotate(&map, theta, (22, 6)))
@@ -1297,6 +1895,18 @@ fn register_support_funcs(lua_ctx: Context, hwdevice: &HwDevice) -> rlua::Result
     })?;
     globals.set("submit_color_map", submit_color_map)?;
 
+    // inter-script message bus
+    let publish = lua_ctx.create_function(|_, (topic, payload): (String, rlua::Value)| {
+        bus::publish(&topic, BusValue::from_lua(payload, 0)?);
+        Ok(())
+    })?;
+    globals.set("publish", publish)?;
+
+    let subscribe = lua_ctx.create_function(|lua_ctx, (topic, handler): (String, Function)| {
+        bus::subscribe(lua_ctx, topic, handler)
+    })?;
+    globals.set("subscribe", subscribe)?;
+
     // finally, register Lua functions supplied by eruption plugins
     let plugin_manager = plugin_manager::PLUGIN_MANAGER.read();
     let plugins = plugin_manager.get_plugins();
@@ -1313,6 +1923,13 @@ fn register_script_config(lua_ctx: Context, manifest: &Manifest) -> rlua::Result
     let script_name = &manifest.name;
 
     let globals = lua_ctx.globals();
+
+    // library modules that the script shall import
+    globals.set(
+        "imports",
+        dependencies::get_imports(manifest, profile.as_ref()),
+    )?;
+
     if let Some(config) = &manifest.config {
         for param in config.iter() {
             debug!("Applying parameter {:?}", param);
@@ -1377,6 +1994,30 @@ fn register_script_config(lua_ctx: Context, manifest: &Manifest) -> rlua::Result
                         globals.raw_set::<&str, u32>(name, *default)?;
                     }
                 }
+
+                ConfigParam::ColorList { name, default, .. } => {
+                    if let Some(profile) = profile {
+                        if let Some(val) = profile.get_color_list_value(script_name, name) {
+                            globals.raw_set::<&str, Vec<u32>>(name, val.to_vec())?;
+                        } else {
+                            globals.raw_set::<&str, Vec<u32>>(name, default.clone())?;
+                        }
+                    } else {
+                        globals.raw_set::<&str, Vec<u32>>(name, default.clone())?;
+                    }
+                }
+
+                ConfigParam::KeySet { name, default, .. } => {
+                    if let Some(profile) = profile {
+                        if let Some(val) = profile.get_key_set_value(script_name, name) {
+                            globals.raw_set::<&str, Vec<u8>>(name, val.to_vec())?;
+                        } else {
+                            globals.raw_set::<&str, Vec<u8>>(name, default.clone())?;
+                        }
+                    } else {
+                        globals.raw_set::<&str, Vec<u8>>(name, default.clone())?;
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,332 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use failure::Fail;
+use rlua::{Context, Value};
+use serde_json::{Map, Number};
+
+use crate::constants;
+
+pub type Result<T> = std::result::Result<T, SerializeError>;
+
+#[derive(Debug, Fail)]
+pub enum SerializeError {
+    #[fail(display = "Can not encode a value of type {}", type_name)]
+    UnsupportedType { type_name: String },
+
+    #[fail(display = "Can not encode a table key of type {}", type_name)]
+    UnsupportedKey { type_name: String },
+
+    #[fail(display = "Can not encode a number that is not finite")]
+    InvalidNumber {},
+
+    #[fail(display = "Tables are nested too deeply, or contain a cycle")]
+    TooDeep {},
+
+    #[fail(display = "A TOML document must be a table")]
+    NotATable {},
+
+    #[fail(display = "Invalid string: {}", msg)]
+    InvalidString { msg: String },
+
+    #[fail(display = "Could not encode: {}", msg)]
+    EncodeError { msg: String },
+
+    #[fail(display = "Could not decode: {}", msg)]
+    DecodeError { msg: String },
+}
+
+fn to_string(s: &rlua::String) -> Result<String> {
+    s.to_str()
+        .map(|s| s.to_string())
+        .map_err(|e| SerializeError::InvalidString { msg: e.to_string() })
+}
+
+/// Returns the length of `table` if it is a sequence, i.e. its keys are
+/// exactly the integers 1..n, with n > 0
+fn get_sequence_len(table: &rlua::Table) -> Result<Option<i64>> {
+    let len = table.raw_len();
+    let mut count = 0;
+
+    for pair in table.clone().pairs::<Value, Value>() {
+        let (key, _) = pair.map_err(|e| SerializeError::EncodeError { msg: e.to_string() })?;
+
+        match key {
+            Value::Integer(i) if i >= 1 && i <= len => count += 1,
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(if len > 0 && count == len {
+        Some(len)
+    } else {
+        None
+    })
+}
+
+/// Convert a Lua value to a JSON value. Tables with the consecutive integer keys
+/// 1..n are encoded as arrays, all other tables are encoded as objects, with
+/// their keys converted to strings
+pub fn to_json(value: Value, depth: usize) -> Result<serde_json::Value> {
+    let result = match value {
+        Value::Nil => serde_json::Value::Null,
+        Value::Boolean(b) => serde_json::Value::Bool(b),
+        Value::Integer(i) => serde_json::Value::Number(i.into()),
+
+        Value::Number(f) => {
+            serde_json::Value::Number(Number::from_f64(f).ok_or(SerializeError::InvalidNumber {})?)
+        }
+
+        Value::String(s) => serde_json::Value::String(to_string(&s)?),
+
+        Value::Table(table) => {
+            if depth >= constants::MAX_SERIALIZE_DEPTH {
+                return Err(SerializeError::TooDeep {});
+            }
+
+            let get_error = |e: rlua::Error| SerializeError::EncodeError { msg: e.to_string() };
+
+            if let Some(len) = get_sequence_len(&table)? {
+                let mut result = Vec::with_capacity(len as usize);
+
+                for i in 1..=len {
+                    result.push(to_json(table.raw_get(i).map_err(get_error)?, depth + 1)?);
+                }
+
+                serde_json::Value::Array(result)
+            } else {
+                let mut result = Map::new();
+
+                for pair in table.pairs::<Value, Value>() {
+                    let (key, value) = pair.map_err(get_error)?;
+
+                    let key = match key {
+                        Value::String(s) => to_string(&s)?,
+                        Value::Integer(i) => i.to_string(),
+                        Value::Number(f) => f.to_string(),
+
+                        _ => {
+                            return Err(SerializeError::UnsupportedKey {
+                                type_name: key.type_name().to_string(),
+                            })
+                        }
+                    };
+
+                    result.insert(key, to_json(value, depth + 1)?);
+                }
+
+                serde_json::Value::Object(result)
+            }
+        }
+
+        _ => {
+            return Err(SerializeError::UnsupportedType {
+                type_name: value.type_name().to_string(),
+            })
+        }
+    };
+
+    Ok(result)
+}
+
+/// Convert a JSON value to a Lua value, `null` is converted to `nil` and arrays
+/// are converted to tables with 1-based indices
+pub fn from_json<'lua>(
+    lua_ctx: Context<'lua>,
+    value: &serde_json::Value,
+) -> rlua::Result<Value<'lua>> {
+    let result = match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or(0.0)),
+        },
+
+        serde_json::Value::String(s) => Value::String(lua_ctx.create_string(s)?),
+
+        serde_json::Value::Array(array) => {
+            let table = lua_ctx.create_table()?;
+
+            for (i, v) in array.iter().enumerate() {
+                table.set(i + 1, from_json(lua_ctx, v)?)?;
+            }
+
+            Value::Table(table)
+        }
+
+        serde_json::Value::Object(map) => {
+            let table = lua_ctx.create_table()?;
+
+            for (k, v) in map.iter() {
+                table.set(k.as_str(), from_json(lua_ctx, v)?)?;
+            }
+
+            Value::Table(table)
+        }
+    };
+
+    Ok(result)
+}
+
+/// Convert a TOML value to a Lua value, dates are converted to strings
+pub fn from_toml<'lua>(lua_ctx: Context<'lua>, value: &toml::Value) -> rlua::Result<Value<'lua>> {
+    let result = match value {
+        toml::Value::Boolean(b) => Value::Boolean(*b),
+        toml::Value::Integer(i) => Value::Integer(*i),
+        toml::Value::Float(f) => Value::Number(*f),
+        toml::Value::String(s) => Value::String(lua_ctx.create_string(s)?),
+        toml::Value::Datetime(d) => Value::String(lua_ctx.create_string(&d.to_string())?),
+
+        toml::Value::Array(array) => {
+            let table = lua_ctx.create_table()?;
+
+            for (i, v) in array.iter().enumerate() {
+                table.set(i + 1, from_toml(lua_ctx, v)?)?;
+            }
+
+            Value::Table(table)
+        }
+
+        toml::Value::Table(map) => {
+            let table = lua_ctx.create_table()?;
+
+            for (k, v) in map.iter() {
+                table.set(k.as_str(), from_toml(lua_ctx, v)?)?;
+            }
+
+            Value::Table(table)
+        }
+    };
+
+    Ok(result)
+}
+
+/// Encode a Lua value as JSON
+pub fn json_encode(value: Value, pretty: bool) -> Result<String> {
+    let value = to_json(value, 0)?;
+
+    if pretty {
+        serde_json::to_string_pretty(&value)
+    } else {
+        serde_json::to_string(&value)
+    }
+    .map_err(|e| SerializeError::EncodeError { msg: e.to_string() })
+}
+
+/// Decode a JSON document to a Lua value
+pub fn json_decode<'lua>(lua_ctx: Context<'lua>, json: &str) -> rlua::Result<Value<'lua>> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| {
+        rlua::Error::RuntimeError(format!(
+            "{}",
+            SerializeError::DecodeError { msg: e.to_string() }
+        ))
+    })?;
+
+    from_json(lua_ctx, &value)
+}
+
+/// Encode a Lua table as a TOML document
+pub fn toml_encode(value: Value) -> Result<String> {
+    let value = match to_json(value, 0)? {
+        // an empty table is encoded as an object, see `get_sequence_len`
+        value @ serde_json::Value::Object(_) => value,
+        _ => return Err(SerializeError::NotATable {}),
+    };
+
+    // the TOML value orders plain values before tables, as required by TOML
+    let value = toml::Value::try_from(value)
+        .map_err(|e| SerializeError::EncodeError { msg: e.to_string() })?;
+
+    toml::to_string(&value).map_err(|e| SerializeError::EncodeError { msg: e.to_string() })
+}
+
+/// Decode a TOML document to a Lua table
+pub fn toml_decode<'lua>(lua_ctx: Context<'lua>, document: &str) -> rlua::Result<Value<'lua>> {
+    let value: toml::Value = document.parse().map_err(|e: toml::de::Error| {
+        rlua::Error::RuntimeError(format!(
+            "{}",
+            SerializeError::DecodeError { msg: e.to_string() }
+        ))
+    })?;
+
+    from_toml(lua_ctx, &value)
+}
+
+#[test]
+fn test_json() {
+    let lua = rlua::Lua::new();
+
+    lua.context(|lua_ctx| {
+        let value: Value = lua_ctx
+            .load(r#"return { name = "fx1", keys = { 1, 2, 3 }, opacity = 0.5, enabled = true }"#)
+            .eval()
+            .unwrap();
+
+        let json = json_encode(value, false).unwrap();
+        let decoded: rlua::Table = match json_decode(lua_ctx, &json).unwrap() {
+            Value::Table(table) => table,
+            _ => panic!("Expected a table"),
+        };
+
+        assert_eq!(decoded.get::<_, String>("name").unwrap(), "fx1");
+        assert_eq!(decoded.get::<_, f64>("opacity").unwrap(), 0.5);
+        assert_eq!(decoded.get::<_, bool>("enabled").unwrap(), true);
+        assert_eq!(decoded.get::<_, Vec<i64>>("keys").unwrap(), vec![1, 2, 3]);
+
+        // sparse tables are encoded as objects
+        let value: Value = lua_ctx.load("return { [1] = 1, [3] = 3 }").eval().unwrap();
+        assert!(json_encode(value, false).unwrap().starts_with('{'));
+
+        // cycles are detected
+        let value: Value = lua_ctx
+            .load("local t = {} t.t = t return t")
+            .eval()
+            .unwrap();
+        assert!(json_encode(value, false).is_err());
+
+        let value: Value = lua_ctx.load("return { f = print }").eval().unwrap();
+        assert!(json_encode(value, false).is_err());
+    });
+}
+
+#[test]
+fn test_toml() {
+    let lua = rlua::Lua::new();
+
+    lua.context(|lua_ctx| {
+        let value: Value = lua_ctx
+            .load(r#"return { section = { value = 1 }, name = "fx1", list = { 1.5, 2.5 } }"#)
+            .eval()
+            .unwrap();
+
+        let document = toml_encode(value).unwrap();
+        assert!(document.starts_with("list = [1.5, 2.5]\nname = \"fx1\"\n"));
+
+        let decoded: rlua::Table = match toml_decode(lua_ctx, &document).unwrap() {
+            Value::Table(table) => table,
+            _ => panic!("Expected a table"),
+        };
+
+        let section: rlua::Table = decoded.get("section").unwrap();
+        assert_eq!(section.get::<_, i64>("value").unwrap(), 1);
+
+        let value: Value = lua_ctx.load("return { 1, 2, 3 }").eval().unwrap();
+        assert!(toml_encode(value).is_err());
+    });
+}
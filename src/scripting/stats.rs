@@ -0,0 +1,219 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::Mutex;
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::constants;
+
+lazy_static! {
+    /// Statistics of each handler, by script file name and handler name
+    static ref STATS: Mutex<BTreeMap<String, BTreeMap<&'static str, HandlerStats>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Incremented on every reset of the statistics
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Generation of the statistics at the time the VM of this thread has been started
+    static VM_GENERATION: Cell<usize> = Cell::new(0);
+}
+
+/// Call counts and timings of an event handler
+#[derive(Debug, Clone, Default)]
+pub struct HandlerStats {
+    /// Number of calls since the script has been started
+    pub calls: u64,
+
+    /// Accumulated wall time of all calls
+    pub total: Duration,
+
+    /// The most recent samples, used for the rolling statistics
+    window: VecDeque<Duration>,
+}
+
+impl HandlerStats {
+    fn record(&mut self, elapsed: Duration) {
+        self.calls += 1;
+        self.total += elapsed;
+
+        if self.window.len() >= constants::STATS_WINDOW_SIZE {
+            self.window.pop_front();
+        }
+
+        self.window.push_back(elapsed);
+    }
+
+    /// Average wall time of the most recent calls
+    pub fn get_average(&self) -> Duration {
+        if self.window.is_empty() {
+            Duration::from_secs(0)
+        } else {
+            self.window.iter().sum::<Duration>() / self.window.len() as u32
+        }
+    }
+
+    /// Maximum wall time of the most recent calls
+    pub fn get_max(&self) -> Duration {
+        self.window
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or_else(|| Duration::from_secs(0))
+    }
+}
+
+/// Measures the wall time of a handler call, it is recorded when the measurement is dropped
+pub struct Measurement<'a> {
+    script: &'a str,
+    handler: &'static str,
+    start: Instant,
+}
+
+impl<'a> Drop for Measurement<'a> {
+    fn drop(&mut self) {
+        record(self.script, self.handler, self.start.elapsed());
+    }
+}
+
+/// Start measuring a call of `handler` of `script`
+pub fn measure<'a>(script: &'a str, handler: &'static str) -> Measurement<'a> {
+    Measurement {
+        script,
+        handler,
+        start: Instant::now(),
+    }
+}
+
+/// Called when a VM is started on the current thread. Calls that are recorded
+/// by a VM that has been started before the most recent reset are discarded, so
+/// that VMs that are still shutting down don't skew the statistics
+pub fn register_vm() {
+    VM_GENERATION.with(|g| g.set(GENERATION.load(Ordering::SeqCst)));
+}
+
+/// Record a call of `handler` of `script` that took `elapsed`
+pub fn record(script: &str, handler: &'static str, elapsed: Duration) {
+    let mut stats = STATS.lock();
+
+    if VM_GENERATION.with(|g| g.get()) != GENERATION.load(Ordering::SeqCst) {
+        return;
+    }
+
+    if !stats.contains_key(script) {
+        stats.insert(script.to_string(), BTreeMap::new());
+    }
+
+    stats
+        .get_mut(script)
+        .unwrap()
+        .entry(handler)
+        .or_default()
+        .record(elapsed);
+}
+
+/// Get a snapshot of the statistics as a list of (script, handler, statistics)
+pub fn get_stats() -> Vec<(String, String, HandlerStats)> {
+    STATS
+        .lock()
+        .iter()
+        .flat_map(|(script, handlers)| {
+            handlers
+                .iter()
+                .map(move |(handler, stats)| (script.clone(), handler.to_string(), stats.clone()))
+        })
+        .collect()
+}
+
+/// Discard all statistics, e.g. when the active profile changes
+pub fn reset() {
+    let mut stats = STATS.lock();
+
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    stats.clear();
+}
+
+/// Log the statistics of all handlers, the most expensive ones first
+pub fn log_summary(level: Level) {
+    let mut stats = get_stats();
+
+    if stats.is_empty() {
+        return;
+    }
+
+    stats.sort_by(|a, b| b.2.total.cmp(&a.2.total));
+
+    log!(level, "Script performance statistics:");
+
+    for (script, handler, stats) in stats.iter() {
+        log!(
+            level,
+            "{:<28} {:<22} calls: {:>8}  avg: {:>8.3} ms  max: {:>8.3} ms  total: {:>10.1} ms",
+            script,
+            handler,
+            stats.calls,
+            stats.get_average().as_secs_f64() * 1000.0,
+            stats.get_max().as_secs_f64() * 1000.0,
+            stats.total.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+#[test]
+fn test_rolling_stats() {
+    let mut stats = HandlerStats::default();
+
+    stats.record(Duration::from_millis(100));
+
+    for _ in 0..constants::STATS_WINDOW_SIZE {
+        stats.record(Duration::from_millis(2));
+    }
+
+    assert_eq!(stats.calls, constants::STATS_WINDOW_SIZE as u64 + 1);
+    assert_eq!(stats.get_average(), Duration::from_millis(2));
+    assert_eq!(stats.get_max(), Duration::from_millis(2));
+    assert_eq!(
+        stats.total,
+        Duration::from_millis(100 + 2 * constants::STATS_WINDOW_SIZE as u64)
+    );
+}
+
+#[test]
+fn test_reset() {
+    std::thread::spawn(|| {
+        register_vm();
+        record("test_reset.lua", "on_tick", Duration::from_millis(1));
+        assert!(get_stats().iter().any(|s| s.0 == "test_reset.lua"));
+
+        // calls of VMs that have been started before the reset are discarded
+        reset();
+        record("test_reset.lua", "on_quit", Duration::from_millis(1));
+        assert!(!get_stats().iter().any(|s| s.0 == "test_reset.lua"));
+
+        register_vm();
+        record("test_reset.lua", "on_tick", Duration::from_millis(1));
+        assert!(get_stats().iter().any(|s| s.0 == "test_reset.lua"));
+    })
+    .join()
+    .unwrap();
+}
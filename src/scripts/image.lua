@@ -0,0 +1,47 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+require "declarations"
+require "debug"
+
+-- global state variables --
+color_map = {}
+image = nil
+x_offset = 0.0
+y_offset = 0.0
+
+function on_startup(config)
+  image = load_image(image_file)
+
+  info("Image: Loaded '" .. image_file .. "' (" .. image:width() .. "x" ..
+       image:height() .. ", " .. image:frames() .. " frame(s))")
+end
+
+function on_tick(delta)
+  x_offset = x_offset + scroll_x * delta
+  y_offset = y_offset + scroll_y * delta
+
+  color_map = image:sample({
+    fit = fit,
+    scale = scale,
+    angle = angle * math.pi / 180.0,
+    x_offset = x_offset,
+    y_offset = y_offset,
+    wrap = wrap,
+    opacity = opacity,
+  })
+
+  submit_color_map(color_map)
+end
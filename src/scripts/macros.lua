@@ -55,7 +55,9 @@ EASY_SHIFT_MOUSE_WHEEL_MACRO_TABLE = {	-- macro tables for mouse wheel events (E
 require "themes/default"
 
 -- import custom macro definitions sub-modules
-require(requires)
+for _, module in ipairs(imports) do
+	require(module)
+end
 
 -- global state variables --
 ticks = 0
@@ -0,0 +1,36 @@
+-- This file is part of Eruption.
+
+-- Eruption is free software: you can redistribute it and/or modify
+-- it under the terms of the GNU General Public License as published by
+-- the Free Software Foundation, either version 3 of the License, or
+-- (at your option) any later version.
+
+-- Eruption is distributed in the hope that it will be useful,
+-- but WITHOUT ANY WARRANTY without even the implied warranty of
+-- MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+-- GNU General Public License for more details.
+
+-- You should have received a copy of the GNU General Public License
+-- along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+
+require "declarations"
+require "debug"
+
+-- global state variables --
+player = nil
+
+function on_startup(config)
+  player = load_recording(recording_file)
+  player:set_speed(speed)
+  player:set_loop(loop)
+  player:rewind()
+
+  info("Playback: Loaded '" .. recording_file .. "' (" .. player:frames() ..
+       " frame(s), " .. player:duration() .. " seconds)")
+end
+
+function on_tick(delta)
+  player:update(get_delta_time())
+
+  submit_color_map(player:color_map())
+end
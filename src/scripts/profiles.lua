@@ -20,60 +20,16 @@ require "debug"
 
 -- load the state table from the ephemeral store
 local function load_state_table()
-	local len = load_int_transient("profiles.saved_state_table.len", 0)
-	if len == 0 then return {} end
-
-	local result = {}
-	for i = 1, len do
-		local key = "profiles.saved_state_table[" .. i .. "]"
-
-		local pid = load_int_transient(key .. ".pid", -1)
-		local type = load_string_transient(key .. ".type", "number")
-
-		local mapping
-		if type == "string" then
-			mapping = load_string_transient(key .. ".profile", "default.profile")
-		elseif type == "number" then
-			mapping = load_int_transient(key .. ".slot", 1)
-		else
-			error("Unhandled type in 'load_state_table'")
-		end
-
-		local elem = { pid, mapping }
-		table.insert(result, elem)
-	end
-
-	return result
+	return json_decode(load_string_transient("profiles.saved_state_table", "[]"))
 end
 
 -- store the state table to the ephemeral store
 local function store_state_table()
-	if SAVED_STATE_TABLE == nil or #SAVED_STATE_TABLE == nil or
-	  #SAVED_STATE_TABLE == 0 then
+	if SAVED_STATE_TABLE == nil or #SAVED_STATE_TABLE == 0 then
 		return
 	end
 
-	local len = #SAVED_STATE_TABLE
-	store_int_transient("profiles.saved_state_table.len", len)
-
-	for i = 1, len do
-		local key = "profiles.saved_state_table[" .. i .. "]"
-
-		local pid = SAVED_STATE_TABLE[i][1]
-		local mapping = SAVED_STATE_TABLE[i][2]
-
-		store_int_transient(key .. ".pid", pid)
-
-		if type(mapping) == "string" then
-			store_string_transient(key .. ".type", "string")
-			store_string_transient(key .. ".profile", mapping)
-		elseif type(mapping) == "number" then
-			store_string_transient(key .. ".type", "number")
-			store_int_transient(key .. ".slot", mapping)
-		else
-			error("Unhandled type in 'store_state_table'")
-		end
-	end
+	store_string_transient("profiles.saved_state_table", json_encode(SAVED_STATE_TABLE))
 end
 
 PROCESS_MAPPING_TABLE = {}  -- stores `file name` to `slot/profile` mappings
@@ -189,4 +145,6 @@ function on_system_event(code, arg1, arg2, arg3)
 end
 
 -- import custom mapping definitions sub-modules
-require(requires)
+for _, module in ipairs(imports) do
+	require(module)
+end
@@ -19,6 +19,11 @@ require "debug"
 -- global state variables --
 ticks = 0
 color_map = {}
+noise = nil
+
+function on_startup(config)
+    noise = create_noise("voronoi", { seed = seed })
+end
 
 function on_tick(delta)
     ticks = ticks + delta
@@ -31,9 +36,9 @@ function on_tick(delta)
             local x = i / num_rows
             local y = i / num_cols
 
-            local val = voronoi_noise(x / coord_scale,
-                                        y / coord_scale,
-                                        ticks / time_scale)
+            local val = noise:get(x / coord_scale,
+                                  y / coord_scale,
+                                  ticks / time_scale)
             val = lerp(0, 360, val)
 
             color_map[i] = hsla_to_color((val / color_divisor) + color_offset,
@@ -0,0 +1,252 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use crate::canvas::{Canvas, Color};
+use crate::constants;
+use crate::hwdevices::layout::{self, KeyboardVariant, KEYBOARD_WIDTH};
+use crate::hwdevices::NUM_KEYS;
+use crate::scripting::script;
+
+/// Height of a glyph, in pixels. Each row of pixels is mapped onto a row of
+/// keys, from the number row down to the row of the space bar
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// Vertical position of the top row of pixels, in key units (the number row)
+const TEXT_TOP: f64 = 1.5;
+
+/// Glyph used for characters that are missing from the font
+const REPLACEMENT_CHAR: char = '?';
+
+#[rustfmt::skip]
+const FONT: &[(char, [&str; GLYPH_HEIGHT])] = &[
+    (' ', ["..", "..", "..", "..", ".."]),
+    ('A', [".#.", "#.#", "###", "#.#", "#.#"]),
+    ('B', ["##.", "#.#", "##.", "#.#", "##."]),
+    ('C', [".##", "#..", "#..", "#..", ".##"]),
+    ('D', ["##.", "#.#", "#.#", "#.#", "##."]),
+    ('E', ["###", "#..", "##.", "#..", "###"]),
+    ('F', ["###", "#..", "##.", "#..", "#.."]),
+    ('G', [".##", "#..", "#.#", "#.#", ".##"]),
+    ('H', ["#.#", "#.#", "###", "#.#", "#.#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..#", "..#", "..#", "#.#", ".#."]),
+    ('K', ["#.#", "#.#", "##.", "#.#", "#.#"]),
+    ('L', ["#..", "#..", "#..", "#..", "###"]),
+    ('M', ["#...#", "##.##", "#.#.#", "#...#", "#...#"]),
+    ('N', ["#..#", "##.#", "#.##", "#..#", "#..#"]),
+    ('O', [".#.", "#.#", "#.#", "#.#", ".#."]),
+    ('P', ["##.", "#.#", "##.", "#..", "#.."]),
+    ('Q', [".#.", "#.#", "#.#", "##.", ".##"]),
+    ('R', ["##.", "#.#", "##.", "#.#", "#.#"]),
+    ('S', [".##", "#..", ".#.", "..#", "##."]),
+    ('T', ["###", ".#.", ".#.", ".#.", ".#."]),
+    ('U', ["#.#", "#.#", "#.#", "#.#", "###"]),
+    ('V', ["#.#", "#.#", "#.#", "#.#", ".#."]),
+    ('W', ["#...#", "#...#", "#.#.#", "##.##", "#...#"]),
+    ('X', ["#.#", "#.#", ".#.", "#.#", "#.#"]),
+    ('Y', ["#.#", "#.#", ".#.", ".#.", ".#."]),
+    ('Z', ["###", "..#", ".#.", "#..", "###"]),
+    ('0', ["###", "#.#", "#.#", "#.#", "###"]),
+    ('1', [".#.", "##.", ".#.", ".#.", "###"]),
+    ('2', ["##.", "..#", ".#.", "#..", "###"]),
+    ('3', ["##.", "..#", ".#.", "..#", "##."]),
+    ('4', ["#.#", "#.#", "###", "..#", "..#"]),
+    ('5', ["###", "#..", "##.", "..#", "##."]),
+    ('6', [".##", "#..", "###", "#.#", "###"]),
+    ('7', ["###", "..#", ".#.", ".#.", ".#."]),
+    ('8', ["###", "#.#", "###", "#.#", "###"]),
+    ('9', ["###", "#.#", "###", "..#", "##."]),
+    ('!', ["#", "#", "#", ".", "#"]),
+    ('.', [".", ".", ".", ".", "#"]),
+    (',', ["..", "..", "..", ".#", "#."]),
+    (':', [".", "#", ".", "#", "."]),
+    (';', ["..", ".#", "..", ".#", "#."]),
+    ('\'', ["#", "#", ".", ".", "."]),
+    ('"', ["#.#", "#.#", "...", "...", "..."]),
+    ('-', ["...", "...", "###", "...", "..."]),
+    ('+', ["...", ".#.", "###", ".#.", "..."]),
+    ('=', ["...", "###", "...", "###", "..."]),
+    ('*', ["...", "#.#", ".#.", "#.#", "..."]),
+    ('/', ["..#", "..#", ".#.", "#..", "#.."]),
+    ('%', ["#.#", "..#", ".#.", "#..", "#.#"]),
+    ('#', ["#.#", "###", "#.#", "###", "#.#"]),
+    ('(', [".#", "#.", "#.", "#.", ".#"]),
+    (')', ["#.", ".#", ".#", ".#", "#."]),
+    ('<', ["..#", ".#.", "#..", ".#.", "..#"]),
+    ('>', ["#..", ".#.", "..#", ".#.", "#.."]),
+    ('?', ["##.", "..#", ".#.", "...", ".#."]),
+    ('_', ["...", "...", "...", "...", "###"]),
+];
+
+fn get_glyph(c: char) -> &'static [&'static str; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+
+    FONT.iter()
+        .find(|(g, _)| *g == c)
+        .or_else(|| FONT.iter().find(|(g, _)| *g == REPLACEMENT_CHAR))
+        .map(|(_, rows)| rows)
+        .unwrap()
+}
+
+/// A line of text, rendered with the bitmap font
+#[derive(Debug, Clone)]
+pub struct TextBitmap {
+    /// Width in pixels, which equals key units
+    pub width: usize,
+
+    /// Rows of pixels, top to bottom
+    rows: [Vec<bool>; GLYPH_HEIGHT],
+}
+
+impl TextBitmap {
+    fn is_set(&self, x: isize, y: isize) -> bool {
+        if x < 0 || y < 0 || y as usize >= GLYPH_HEIGHT {
+            false
+        } else {
+            self.rows[y as usize]
+                .get(x as usize)
+                .copied()
+                .unwrap_or(false)
+        }
+    }
+}
+
+/// Render a line of text. Glyphs are separated by a blank column
+pub fn render(text: &str) -> TextBitmap {
+    let mut rows: [Vec<bool>; GLYPH_HEIGHT] = Default::default();
+
+    for (i, c) in text.chars().enumerate() {
+        let glyph = get_glyph(c);
+
+        for (row, pixels) in rows.iter_mut().zip(glyph.iter()) {
+            if i > 0 {
+                row.push(false);
+            }
+
+            row.extend(pixels.chars().map(|p| p == '#'));
+        }
+    }
+
+    TextBitmap {
+        width: rows[0].len(),
+        rows,
+    }
+}
+
+/// Draw `bitmap` onto the keys, with its left edge at `x` (in key units).
+/// Returns a color map, keys that are not covered by the text get `background`
+pub fn draw(bitmap: &TextBitmap, x: f64, color: u32, background: u32) -> Vec<u32> {
+    let mut result = vec![background; NUM_KEYS];
+
+    for key in layout::get_layout(KeyboardVariant::from_config()) {
+        if key.index >= NUM_KEYS {
+            continue;
+        }
+
+        let (cx, cy) = key.center();
+
+        let px = (cx - x).floor() as isize;
+        let py = (cy - TEXT_TOP).floor() as isize;
+
+        if bitmap.is_set(px, py) {
+            result[key.index] = color;
+        }
+    }
+
+    result
+}
+
+/// Position of a marquee after it has been scrolled by `step` key units. The
+/// text enters on the right edge of the keyboard, and starts over after it
+/// has left on the left edge
+pub fn get_scroll_position(bitmap: &TextBitmap, step: f64) -> f64 {
+    KEYBOARD_WIDTH - step.rem_euclid(KEYBOARD_WIDTH + bitmap.width as f64)
+}
+
+/// A one-off message, shown on top of the active profile
+struct Message {
+    bitmap: TextBitmap,
+    color: u32,
+    start: Instant,
+}
+
+lazy_static! {
+    /// The message that is currently being shown, if any
+    static ref MESSAGE: Mutex<Option<Message>> = Mutex::new(None);
+}
+
+/// Scroll `text` across the keyboard once, on top of the active profile
+pub fn show_message(text: &str, color: u32) {
+    MESSAGE.lock().replace(Message {
+        bitmap: render(text),
+        color,
+        start: Instant::now(),
+    });
+}
+
+/// Returns true while a message is being shown
+pub fn is_message_active() -> bool {
+    MESSAGE.lock().is_some()
+}
+
+/// Blend the current message onto `canvas`. The message is removed after it
+/// has scrolled across the keyboard
+pub fn blend_message(canvas: &mut Canvas) {
+    let mut message = MESSAGE.lock();
+
+    if let Some(m) = message.as_ref() {
+        let step = m.start.elapsed().as_secs_f64() * constants::MESSAGE_SCROLL_SPEED;
+
+        if step >= KEYBOARD_WIDTH + m.bitmap.width as f64 {
+            message.take();
+
+            // re-render the canvas without the message
+            script::FRAME_GENERATION_COUNTER.fetch_add(1, Ordering::SeqCst);
+        } else {
+            let map = draw(
+                &m.bitmap,
+                KEYBOARD_WIDTH - step,
+                m.color,
+                constants::MESSAGE_BACKGROUND,
+            );
+
+            for (background, fg) in canvas.iter_mut().zip(map.iter()) {
+                *background = Color::from_u32(*fg).over(background);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_render() {
+    let bitmap = render("Hi!");
+
+    // H, a blank column, I, a blank column and !
+    assert_eq!(bitmap.width, 3 + 1 + 3 + 1 + 1);
+
+    assert!(bitmap.is_set(0, 0));
+    assert!(!bitmap.is_set(1, 0));
+    assert!(bitmap.is_set(1, 2));
+    assert!(!bitmap.is_set(3, 2));
+    assert!(bitmap.is_set(8, 4));
+    assert!(!bitmap.is_set(9, 4));
+}
@@ -26,6 +26,7 @@ use udev::Enumerator;
 // use log::*;
 
 use crate::hwdevices::{self, HidEventCode};
+use crate::scripting::native;
 
 pub type Result<T> = std::result::Result<T, UtilError>;
 
@@ -194,7 +195,12 @@ pub fn get_mouse_dev_from_udev() -> Result<String> {
 /// Returns the associated manifest path in `PathBuf` for the script `script_path`.
 pub fn get_manifest_for(script_file: &Path) -> PathBuf {
     let mut manifest_path = script_file.to_path_buf();
-    manifest_path.set_extension("lua.manifest");
+
+    if native::is_native_effect(script_file) {
+        manifest_path.set_extension("native.manifest");
+    } else {
+        manifest_path.set_extension("lua.manifest");
+    }
 
     manifest_path
 }
@@ -206,7 +212,11 @@ pub fn is_file_accessible<P: AsRef<Path>>(p: P) -> std::io::Result<String> {
 /// Checks whether a script file is readable
 #[allow(dead_code)]
 pub fn is_script_file_accessible(script_file: &Path) -> bool {
-    is_file_accessible(script_file).is_ok()
+    if native::is_native_effect(script_file) {
+        native::is_native_effect_available(script_file)
+    } else {
+        is_file_accessible(script_file).is_ok()
+    }
 }
 
 /// Checks whether a script's manifest file is readable
@@ -415,6 +425,30 @@ static _EV_TO_INDEX_ANSI: [u8; 0x2ff + 1] = [
     0xff, // 0x2f0
 ];
 
+/// Find the pair of adjacent `items`, that are sorted by `position`, so that
+/// `position(items[i - 1]) <= p < position(items[i])`. Returns `i` and the relative
+/// position of `p` in between both items, or `None` if `p` is outside of the range
+/// of the items, or NaN
+pub fn find_segment<T>(items: &[T], p: f64, position: impl Fn(&T) -> f64) -> Option<(usize, f64)> {
+    let i = items.iter().position(|item| position(item) > p)?;
+
+    if i == 0 {
+        return None;
+    }
+
+    let (p0, p1) = (position(&items[i - 1]), position(&items[i]));
+
+    Some((i, (p - p0) / (p1 - p0)))
+}
+
+/// 32 bit FNV-1a hash of `name`, e.g. the file name of a process. The same
+/// hash is available to Lua scripts via `hash_name(name)`
+pub fn hash_name(name: &str) -> u32 {
+    name.bytes().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
 pub fn ev_key_to_key_index(key: EV_KEY) -> u8 {
     EV_TO_INDEX_ISO[((key as u8) as usize)] + 1
 }
@@ -496,3 +530,68 @@ pub fn get_process_file_name(pid: i32) -> Result<String> {
         .into_string()
         .map_err(|_| UtilError::OpFailed {})?)
 }
+
+/// Local (wall-clock) date and time
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LocalTime {
+    pub year: i32,
+
+    /// Month of the year, in the range 1..12
+    pub month: u32,
+
+    /// Day of the month, in the range 1..31
+    pub day: u32,
+
+    /// Day of the week, in the range 1..7 (Monday is 1)
+    pub weekday: u32,
+
+    /// Day of the year, in the range 1..366
+    pub year_day: u32,
+
+    /// Offset of the time zone from UTC, in seconds east of UTC
+    pub utc_offset: i64,
+
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millisecond: u32,
+}
+
+/// Get the current local date and time, in the time zone of the system
+pub fn get_local_time() -> Result<LocalTime> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| UtilError::OpFailed {})?;
+
+    let secs = now.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return Err(UtilError::OpFailed {});
+    }
+
+    Ok(LocalTime {
+        year: tm.tm_year + 1900,
+        month: (tm.tm_mon + 1) as u32,
+        day: tm.tm_mday as u32,
+        weekday: if tm.tm_wday == 0 {
+            7
+        } else {
+            tm.tm_wday as u32
+        },
+        year_day: (tm.tm_yday + 1) as u32,
+        utc_offset: tm.tm_gmtoff as i64,
+        hour: tm.tm_hour as u32,
+        minute: tm.tm_min as u32,
+        second: tm.tm_sec as u32,
+        millisecond: now.subsec_millis(),
+    })
+}
+
+#[test]
+fn test_hash_name() {
+    // reference values of FNV-1a (32 bit)
+    assert_eq!(hash_name(""), 0x811c_9dc5);
+    assert_eq!(hash_name("a"), 0xe40c_292c);
+    assert_eq!(hash_name("foobar"), 0xbf9c_f968);
+}
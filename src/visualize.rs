@@ -0,0 +1,292 @@
+/*
+    This file is part of Eruption.
+
+    Eruption is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Eruption is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with Eruption.  If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use dbus::blocking::Connection;
+use lazy_static::lazy_static;
+use log::*;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::canvas::{Canvas, Color};
+use crate::constants;
+use crate::hwdevices::layout::{self, KeyboardVariant, KEYBOARD_HEIGHT, KEYBOARD_WIDTH};
+use crate::hwdevices::RGBA;
+
+lazy_static! {
+    /// The LED map that has been sent to the device most recently, as 32 bits (ARGB) values
+    static ref LED_MAP: RwLock<Vec<u32>> = RwLock::new(vec![]);
+
+    /// The most recent color maps of the scripts, by script file name
+    static ref LAYER_MAPS: RwLock<HashMap<String, Vec<u32>>> = RwLock::new(HashMap::new());
+
+    /// Time at which a client has requested the layers most recently. Layers
+    /// are only exported while clients are interested in them
+    static ref LAYERS_REQUESTED: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+/// Export the LED map that has just been sent to the device
+pub fn export_led_map(led_map: &[RGBA]) {
+    *LED_MAP.write() = led_map
+        .iter()
+        .map(|c| (c.a as u32) << 24 | (c.r as u32) << 16 | (c.g as u32) << 8 | c.b as u32)
+        .collect();
+}
+
+/// Get the LED map that has been sent to the device most recently
+pub fn get_led_map() -> Vec<u32> {
+    LED_MAP.read().clone()
+}
+
+/// Returns true if a client has recently requested the layers of the active profile
+pub fn is_exporting_layers() -> bool {
+    LAYERS_REQUESTED.lock().map_or(false, |t| {
+        t.elapsed() < Duration::from_millis(constants::LAYER_EXPORT_TIMEOUT_MILLIS)
+    })
+}
+
+/// Export the unblended color map of the script `script_name`
+pub fn export_layer(script_name: &str, layer: &Canvas) {
+    LAYER_MAPS.write().insert(
+        script_name.to_string(),
+        layer.iter().map(|c| c.to_u32()).collect(),
+    );
+}
+
+/// Get the color maps of the active scripts, in the order they are stacked
+pub fn get_layer_maps() -> Vec<(String, Vec<u32>)> {
+    let was_exporting = is_exporting_layers();
+    *LAYERS_REQUESTED.lock() = Some(Instant::now());
+
+    let mut layer_maps = LAYER_MAPS.write();
+
+    // discard stale layers, e.g. of scripts of a previously active profile
+    if !was_exporting {
+        layer_maps.clear();
+    }
+
+    crate::ACTIVE_SCRIPTS
+        .lock()
+        .iter()
+        .map(|manifest| {
+            let name = manifest
+                .script_file
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+
+            let map = layer_maps.get(&name).cloned().unwrap_or_else(Vec::new);
+
+            (name, map)
+        })
+        .collect()
+}
+
+/// Terminal columns per key unit
+const COLS_PER_UNIT: f64 = 4.0;
+
+/// Pixels per key unit in vertical direction, two pixels share one terminal row
+const PIXELS_PER_UNIT: f64 = 4.0;
+
+/// Color of the keyboard's case
+const BACKGROUND_COLOR: RGBA = RGBA {
+    r: 0x18,
+    g: 0x18,
+    b: 0x18,
+    a: 0xff,
+};
+
+/// Draw `frame` as a truecolor keyboard diagram, using ANSI escape sequences.
+/// Each terminal row shows two rows of pixels, by means of the upper half block
+pub fn draw_frame(frame: &[RGBA], variant: KeyboardVariant) -> String {
+    let width = (KEYBOARD_WIDTH * COLS_PER_UNIT).ceil() as usize;
+    let height = (KEYBOARD_HEIGHT * PIXELS_PER_UNIT).ceil() as usize;
+
+    let mut pixels = vec![BACKGROUND_COLOR; width * height];
+
+    for key in layout::get_layout(variant) {
+        let color = match frame.get(key.index) {
+            Some(c) => *c,
+            None => continue,
+        };
+
+        // leave the last column and pixel row of each key blank, to
+        // separate adjacent keys
+        let x0 = (key.x * COLS_PER_UNIT).round() as usize;
+        let x1 = ((key.x + key.width) * COLS_PER_UNIT).round() as usize - 1;
+        let y0 = (key.y * PIXELS_PER_UNIT).round() as usize;
+        let y1 = ((key.y + key.height) * PIXELS_PER_UNIT).round() as usize - 1;
+
+        for y in y0..y1.min(height) {
+            for x in x0..x1.min(width) {
+                pixels[y * width + x] = color;
+            }
+        }
+    }
+
+    let mut result = String::new();
+
+    for row in pixels.chunks(width * 2) {
+        let (upper, lower) = row.split_at(width.min(row.len()));
+
+        for (x, top) in upper.iter().enumerate() {
+            let bottom = lower.get(x).unwrap_or(&BACKGROUND_COLOR);
+
+            write!(
+                &mut result,
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                top.r, top.g, top.b, bottom.r, bottom.g, bottom.b
+            )
+            .unwrap();
+        }
+
+        result.push_str("\x1b[0m\n");
+    }
+
+    result
+}
+
+/// Convert 32 bits (ARGB) sRGB color values to a frame, colors are composited over black
+fn to_frame(map: &[u32]) -> Vec<RGBA> {
+    map.iter()
+        .map(|c| Color::from_u32(*c).over(&Color::default()).to_rgba())
+        .collect()
+}
+
+/// Entry point of the `visualize` subcommand, returns the process exit code
+pub fn run_cli(matches: &clap::ArgMatches) -> i32 {
+    let layer: Option<usize> = match matches.value_of("layer").map(|v| v.parse()) {
+        Some(Ok(layer)) => Some(layer),
+
+        Some(Err(_)) => {
+            error!("Invalid layer index");
+            return 2;
+        }
+
+        None => None,
+    };
+
+    let num_frames: Option<usize> = matches.value_of("frames").and_then(|v| v.parse().ok());
+
+    let connection = match Connection::new_system() {
+        Ok(connection) => connection,
+
+        Err(e) => {
+            error!("Could not connect to the system bus: {}", e);
+            return 1;
+        }
+    };
+
+    let proxy = connection.with_proxy(
+        "org.eruption",
+        "/org/eruption/status",
+        Duration::from_millis(u64::from(constants::DBUS_TIMEOUT_MILLIS)),
+    );
+
+    let variant = KeyboardVariant::from_config();
+    let stdout = io::stdout();
+
+    // clear the screen and hide the cursor
+    print!("\x1b[2J\x1b[?25l");
+
+    let mut result = 0;
+    let mut frame_index = 0;
+    let mut fps = constants::TARGET_FPS;
+    let mut fps_timer: Option<Instant> = None;
+
+    while !crate::QUIT.load(Ordering::SeqCst) && num_frames.map_or(true, |n| frame_index < n) {
+        let start_time = Instant::now();
+
+        // follow the frame rate of the daemon
+        if fps_timer.map_or(true, |t| t.elapsed() >= Duration::from_secs(1)) {
+            let stats: std::result::Result<((u64, u64, bool, u64, u64, u64, u64, u64),), _> =
+                proxy.method_call("org.eruption.Status", "GetFrameStats", ());
+
+            if let Ok((stats,)) = stats {
+                fps = stats.0.max(constants::MIN_TARGET_FPS);
+            }
+
+            fps_timer = Some(Instant::now());
+        }
+
+        let frame = match layer {
+            Some(layer) => proxy
+                .method_call("org.eruption.Status", "GetLayerMaps", ())
+                .map_err(|e| e.to_string())
+                .and_then(|(layers,): (Vec<(String, Vec<u32>)>,)| {
+                    let num_layers = layers.len();
+
+                    layers
+                        .into_iter()
+                        .nth(layer)
+                        .map(|(name, map)| (name, to_frame(&map)))
+                        .ok_or_else(|| {
+                            format!(
+                                "Invalid layer index {}, there are {} layers",
+                                layer, num_layers
+                            )
+                        })
+                }),
+
+            None => proxy
+                .method_call("org.eruption.Status", "GetLedMap", ())
+                .map_err(|e| e.to_string())
+                .map(|(map,): (Vec<u32>,)| ("all".to_string(), to_frame(&map))),
+        };
+
+        match frame {
+            Ok((name, frame)) => {
+                let mut out = stdout.lock();
+
+                writeln!(
+                    out,
+                    "\x1b[H{}frame: {}  fps: {}  layer: {}\x1b[K",
+                    draw_frame(&frame, variant),
+                    frame_index,
+                    fps,
+                    name
+                )
+                .and_then(|_| out.flush())
+                .unwrap_or_else(|e| error!("Could not write to the terminal: {}", e));
+            }
+
+            Err(e) => {
+                error!("Could not get the LED map from the daemon: {}", e);
+                result = 1;
+                break;
+            }
+        }
+
+        frame_index += 1;
+
+        let frame_duration = Duration::from_millis(1000 / fps);
+        let elapsed = start_time.elapsed();
+        if elapsed < frame_duration {
+            thread::sleep(frame_duration - elapsed);
+        }
+    }
+
+    // show the cursor again
+    println!("\x1b[?25h");
+
+    result
+}
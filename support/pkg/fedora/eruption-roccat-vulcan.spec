@@ -55,6 +55,8 @@ cargo build --all --release --verbose
 %{__mkdir_p} %{buildroot}%{_libdir}/%{ShortName}/scripts/lib
 %{__mkdir_p} %{buildroot}%{_libdir}/%{ShortName}/scripts/lib/macros
 %{__mkdir_p} %{buildroot}%{_libdir}/%{ShortName}/scripts/lib/themes
+%{__mkdir_p} %{buildroot}%{_libdir}/%{ShortName}/scripts/lib/palettes
+%{__mkdir_p} %{buildroot}%{_libdir}/%{ShortName}/scripts/images
 %{__mkdir_p} %{buildroot}%{_libdir}/%{ShortName}/scripts/examples
 %{__mkdir_p} %{buildroot}%{_docdir}/%{ShortName}
 %{__mkdir_p} %{buildroot}%{_datarootdir}/icons/hicolor/scalable/apps
@@ -141,12 +143,16 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{bu
 %{_sharedstatedir}/%{ShortName}/profiles/vu-meter.profile
 %{_sharedstatedir}/%{ShortName}/profiles/turbulence.profile
 %{_datarootdir}/%{ShortName}/scripts/examples/simple.lua
+%{_datarootdir}/%{ShortName}/scripts/images/rainbow.png
 %{_datarootdir}/%{ShortName}/scripts/lib/debug.lua
 %{_datarootdir}/%{ShortName}/scripts/lib/queue.lua
 %{_datarootdir}/%{ShortName}/scripts/lib/utilities.lua
 %{_datarootdir}/%{ShortName}/scripts/lib/declarations.lua
 %config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/themes/default.lua
 %config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/themes/gaming.lua
+%config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/palettes/sunset.palette
+%config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/palettes/ocean.palette
+%config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/palettes/neon.palette
 %config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/macros/modifiers.lua
 %config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/macros/user-macros.lua
 %config(noreplace) %{_datarootdir}/%{ShortName}/scripts/lib/macros/user-mappings.lua
@@ -187,6 +193,9 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{bu
 %{_datarootdir}/%{ShortName}/scripts/rmf.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/voronoi.lua
 %{_datarootdir}/%{ShortName}/scripts/voronoi.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/perlin.native.manifest
+%{_datarootdir}/%{ShortName}/scripts/turbulence.native.manifest
+%{_datarootdir}/%{ShortName}/scripts/voronoi.native.manifest
 %{_datarootdir}/%{ShortName}/scripts/fire.lua
 %{_datarootdir}/%{ShortName}/scripts/fire.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/fireworks.lua
@@ -203,6 +212,10 @@ install -Dp -m 0755 %{_builddir}/%{name}-%{version}/target/release/eruption %{bu
 %{_datarootdir}/%{ShortName}/scripts/heartbeat.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/heatmap.lua
 %{_datarootdir}/%{ShortName}/scripts/heatmap.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/image.lua
+%{_datarootdir}/%{ShortName}/scripts/image.lua.manifest
+%{_datarootdir}/%{ShortName}/scripts/playback.lua
+%{_datarootdir}/%{ShortName}/scripts/playback.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/impact.lua
 %{_datarootdir}/%{ShortName}/scripts/impact.lua.manifest
 %{_datarootdir}/%{ShortName}/scripts/multigradient.lua
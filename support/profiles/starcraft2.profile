@@ -12,6 +12,9 @@ active_scripts = [
 #   'profiles.lua',
 ]
 
+[imports]
+Macros = ['macros/starcraft2']
+
 [[config."Solid Color"]]
 type = 'color'
 name = 'color_background'
@@ -31,8 +34,3 @@ value = 0.5
 type = 'color'
 name = 'color_highlight'
 value = 0x0004040f
-
-[[config.Macros]]
-type = 'string'
-name = 'requires'
-value = 'macros/starcraft2'